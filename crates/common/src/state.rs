@@ -3,7 +3,8 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use slight_file::{Resource, SecretStoreResource};
+use anyhow::{bail, Result};
+use slight_file::{capability_store::CapabilityStore, Resource, SecretStoreResource};
 
 /// `BasicState` provides an attempt at a "fit-all" for basic scenarios
 /// of a host's state.
@@ -38,6 +39,28 @@ impl BasicState {
             slightfile_path: slightfile_path.as_ref().to_owned(),
         }
     }
+
+    /// Looks up the state of the `capability_type` capability a guest opens as
+    /// `name`, falling back to the one declared for `implementor`.
+    ///
+    /// This doesn't depend on the runtime the guest runs on, so that every
+    /// runtime resolves capabilities the same way.
+    pub fn resolve(
+        capability_store: &CapabilityStore<Self>,
+        name: &str,
+        implementor: &str,
+        capability_type: &str,
+    ) -> Result<Self> {
+        match capability_store
+            .get(name, capability_type)
+            .or_else(|| capability_store.get(implementor, capability_type))
+        {
+            Some(state) => Ok(state.clone()),
+            None => bail!(
+                "could not find capability under name '{name}' for implementor '{implementor}'"
+            ),
+        }
+    }
 }
 
 impl std::fmt::Debug for BasicState {
@@ -0,0 +1,166 @@
+//! Runs the same workload against each keyvalue backend, so that their
+//! throughput and latency can be compared before choosing one (and regressions
+//! caught):
+//!   - `set`, `get` and `delete` of `KEYS` keys, each holding `VALUE_SIZE` bytes,
+//!   one after the other, and
+//!   - `keys`, listing those keys.
+//!
+//! The filesystem backend always runs. Backends that need a server run when
+//! their feature is enabled and the server is given, as for the conformance
+//! tests (see `tests/keyvalue-test`):
+//!   - redis, with `SLIGHT_BENCH_REDIS_ADDRESS` (e.g., `redis://localhost:6379`), and
+//!   - awsdynamodb, with `SLIGHT_BENCH_DYNAMODB_ENDPOINT` (e.g., DynamoDB local at
+//!   `http://localhost:8000`), whose `slight-keyvalue-bench` table must exist
+//!   with a string partition key named `key`.
+//!
+//! Run with `cargo bench -p slight-keyvalue --bench backends --features redis,awsdynamodb`.
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use slight_common::BasicState;
+use slight_file::{resource::KeyvalueResource, Resource};
+use slight_keyvalue::implementors::{filesystem::FilesystemImplementor, KeyvalueImplementor};
+use tokio::runtime::Runtime;
+
+/// How many keys each operation of the workload goes through.
+const KEYS: usize = 100;
+/// The size of the value of each key.
+const VALUE_SIZE: usize = 1024;
+/// The name of the store on every backend.
+const STORE: &str = "slight-keyvalue-bench";
+
+type Backend = (&'static str, Arc<dyn KeyvalueImplementor + Send + Sync>);
+
+fn state(resource: KeyvalueResource, configs: HashMap<String, String>) -> BasicState {
+    BasicState::new(
+        None,
+        Resource::Keyvalue(resource),
+        STORE.to_string(),
+        Some(configs),
+        "./slightfile.toml",
+    )
+}
+
+/// Opens the store on every backend available, along with the base directory of
+/// the filesystem one (to remove it afterwards).
+async fn backends() -> (Vec<Backend>, String) {
+    let filesystem =
+        FilesystemImplementor::new(&state(KeyvalueResource::Filesystem, HashMap::new()), STORE)
+            .await
+            .unwrap();
+    let base = filesystem.base.clone();
+    #[allow(unused_mut)]
+    let mut backends: Vec<Backend> = vec![("filesystem", Arc::new(filesystem))];
+
+    #[cfg(feature = "redis")]
+    if let Ok(address) = std::env::var("SLIGHT_BENCH_REDIS_ADDRESS") {
+        use slight_keyvalue::implementors::redis::RedisImplementor;
+        let configs = HashMap::from([("REDIS_ADDRESS".to_string(), address)]);
+        let redis = RedisImplementor::new(&state(KeyvalueResource::Redis, configs), STORE)
+            .await
+            .unwrap();
+        backends.push(("redis", Arc::new(redis)));
+    }
+
+    #[cfg(feature = "awsdynamodb")]
+    if let Ok(endpoint) = std::env::var("SLIGHT_BENCH_DYNAMODB_ENDPOINT") {
+        use slight_keyvalue::implementors::awsdynamodb::AwsDynamoDbImplementor;
+        let configs = HashMap::from([("AWS_ENDPOINT_URL".to_string(), endpoint)]);
+        let dynamodb =
+            AwsDynamoDbImplementor::new(&state(KeyvalueResource::AwsDynamoDb, configs), STORE)
+                .await
+                .unwrap();
+        backends.push(("awsdynamodb", Arc::new(dynamodb)));
+    }
+
+    (backends, base)
+}
+
+fn key(i: usize) -> String {
+    format!("bench-key-{i}")
+}
+
+async fn set_all(implementor: &(dyn KeyvalueImplementor + Send + Sync), value: &[u8]) {
+    for i in 0..KEYS {
+        implementor.set(&key(i), value).await.unwrap();
+    }
+}
+
+async fn get_all(implementor: &(dyn KeyvalueImplementor + Send + Sync)) {
+    for i in 0..KEYS {
+        implementor.get(&key(i)).await.unwrap();
+    }
+}
+
+async fn delete_all(implementor: &(dyn KeyvalueImplementor + Send + Sync)) {
+    for i in 0..KEYS {
+        implementor.delete(&key(i)).await.unwrap();
+    }
+}
+
+fn bench_backends(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let value = vec![0u8; VALUE_SIZE];
+    let (backends, base) = rt.block_on(backends());
+
+    let mut group = c.benchmark_group("set");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    for (backend, implementor) in &backends {
+        group.bench_function(BenchmarkId::from_parameter(backend), |b| {
+            b.to_async(&rt)
+                .iter(|| set_all(implementor.as_ref(), &value));
+        });
+    }
+    group.finish();
+
+    // every key was set by the `set` benchmark, and is read back from here on
+    let mut group = c.benchmark_group("get");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    for (backend, implementor) in &backends {
+        group.bench_function(BenchmarkId::from_parameter(backend), |b| {
+            b.to_async(&rt).iter(|| get_all(implementor.as_ref()));
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("keys");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    for (backend, implementor) in &backends {
+        group.bench_function(BenchmarkId::from_parameter(backend), |b| {
+            b.to_async(&rt)
+                .iter(|| async { implementor.keys().await.unwrap() });
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("delete");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    for (backend, implementor) in &backends {
+        group.bench_function(BenchmarkId::from_parameter(backend), |b| {
+            // the keys are set again before each iteration, outside of its timing
+            b.to_async(&rt).iter_custom(|iters| {
+                let (implementor, value) = (implementor.as_ref(), &value);
+                async move {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        set_all(implementor, value).await;
+                        let start = Instant::now();
+                        delete_all(implementor).await;
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                }
+            });
+        });
+    }
+    group.finish();
+
+    std::fs::remove_dir_all(base).ok();
+}
+
+criterion_group!(benches, bench_backends);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+//! Compares listing the keys of a `ChainImplementor` one link after the other
+//! against listing them concurrently, for chains of increasing length.
+//!
+//! Run with `cargo bench -p slight-keyvalue --bench chain`.
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use slight_keyvalue::{
+    implementors::{
+        chain::{ChainImplementor, ChainWrite},
+        KeyvalueImplementor,
+    },
+    keyvalue::KeyvalueError,
+};
+use tokio::runtime::Runtime;
+
+/// How long each link takes to answer, standing in for a network round trip.
+const LATENCY: Duration = Duration::from_millis(5);
+
+/// A link listing a fixed set of keys, that answers after `LATENCY`.
+/// Only `keys` is measured, so its values are stubbed: it doesn't hold any,
+/// and drops the ones it's given.
+#[derive(Debug)]
+struct RemoteLink {
+    keys: Vec<String>,
+}
+
+#[async_trait]
+impl KeyvalueImplementor for RemoteLink {
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        tokio::time::sleep(LATENCY).await;
+        Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+    }
+
+    async fn set(&self, _key: &str, _value: &[u8]) -> Result<()> {
+        tokio::time::sleep(LATENCY).await;
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        tokio::time::sleep(LATENCY).await;
+        Ok(self.keys.clone())
+    }
+
+    async fn delete(&self, _key: &str) -> Result<()> {
+        tokio::time::sleep(LATENCY).await;
+        Ok(())
+    }
+}
+
+fn chain(links: usize, concurrency: usize) -> ChainImplementor {
+    let links = (0..links)
+        .map(|i| {
+            let keys = (0..100).map(|k| format!("link-{i}-key-{k}")).collect();
+            Arc::new(RemoteLink { keys }) as Arc<dyn KeyvalueImplementor + Send + Sync>
+        })
+        .collect();
+    ChainImplementor::new(links, ChainWrite::First).with_concurrency(concurrency)
+}
+
+fn bench_keys(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("chain_keys");
+    for links in [2, 4, 8, 16] {
+        for (mode, concurrency) in [("sequential", 1), ("parallel", links)] {
+            let chain = chain(links, concurrency);
+            group.bench_with_input(BenchmarkId::new(mode, links), &links, |b, _| {
+                b.to_async(&rt)
+                    .iter(|| async { chain.keys().await.unwrap() });
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_keys);
+criterion_main!(benches);
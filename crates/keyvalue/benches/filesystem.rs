@@ -0,0 +1,45 @@
+//! Measures the cost of the filesystem implementor's `FSYNC` option so users can
+//! weigh durability against write throughput.
+//!
+//! Run with `cargo bench -p slight-keyvalue --bench filesystem`.
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use slight_common::BasicState;
+use slight_file::{resource::KeyvalueResource, Resource};
+use slight_keyvalue::implementors::{filesystem::FilesystemImplementor, KeyvalueImplementor};
+use tokio::runtime::Runtime;
+
+fn filesystem_state(name: &str, fsync: bool) -> BasicState {
+    BasicState::new(
+        None,
+        Resource::Keyvalue(KeyvalueResource::Filesystem),
+        name.to_string(),
+        Some(HashMap::from([("FSYNC".to_string(), fsync.to_string())])),
+        "./slightfile.toml",
+    )
+}
+
+fn bench_set(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let value = vec![0u8; 4096];
+    let mut group = c.benchmark_group("filesystem_set");
+    for fsync in [false, true] {
+        let name = format!("slight-keyvalue-bench-fsync-{fsync}");
+        let implementor = rt
+            .block_on(FilesystemImplementor::new(
+                &filesystem_state(&name, fsync),
+                &name,
+            ))
+            .unwrap();
+        group.bench_with_input(BenchmarkId::new("fsync", fsync), &fsync, |b, _| {
+            b.to_async(&rt)
+                .iter(|| async { implementor.set("key", &value).await.unwrap() });
+        });
+        std::fs::remove_dir_all(&implementor.base).ok();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_set);
+criterion_main!(benches);
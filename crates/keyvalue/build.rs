@@ -0,0 +1,15 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "proxy")]
+    proxy_protos();
+}
+
+/// Generates the gRPC client and server of the `proxy` implementor, with a
+/// vendored `protoc` so that building doesn't require one to be installed.
+#[cfg(feature = "proxy")]
+fn proxy_protos() {
+    println!("cargo:rerun-if-changed=proto/keyvalue_proxy.proto");
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    tonic_build::compile_protos("proto/keyvalue_proxy.proto").unwrap();
+}
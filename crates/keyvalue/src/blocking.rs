@@ -0,0 +1,78 @@
+//! A synchronous facade over the keyvalue implementors, for embedders that drive
+//! slight from non-async code and don't run a Tokio runtime of their own.
+//!
+//! `BlockingKeyvalue` owns a small current-thread Tokio runtime and blocks on it
+//! for every operation. It must therefore not be called from within an async
+//! context (e.g., from a task running on a Tokio runtime), where blocking on a
+//! runtime panics.
+//!
+//! Guests are unaffected by this module, they keep going through the
+//! `keyvalue::Keyvalue` host implementation.
+
+use anyhow::Result;
+use slight_common::BasicState;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    keyvalue::KeyvalueError, with_allowed_prefixes, with_normalized_keys, KeyvalueImplementors,
+    KeyvalueInner,
+};
+
+/// A keyvalue store whose operations block until they complete.
+pub struct BlockingKeyvalue {
+    runtime: Runtime,
+    inner: KeyvalueInner,
+}
+
+impl BlockingKeyvalue {
+    /// Opens the store `name` with the given implementor, configured by
+    /// `slight_state` (as `keyvalue::open` would for a guest).
+    pub fn open(
+        keyvalue_implementor: KeyvalueImplementors,
+        slight_state: &BasicState,
+        name: &str,
+    ) -> Result<Self> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let mut inner = runtime.block_on(KeyvalueInner::new(
+            keyvalue_implementor,
+            slight_state,
+            name,
+            None,
+        ))?;
+        inner.keyvalue_implementor = runtime.block_on(with_allowed_prefixes(
+            inner.keyvalue_implementor,
+            slight_state,
+        ))?;
+        inner.keyvalue_implementor = runtime.block_on(with_normalized_keys(
+            inner.keyvalue_implementor,
+            slight_state,
+        ))?;
+        Ok(Self { runtime, inner })
+    }
+
+    pub fn get(&self, key: &str) -> Result<Vec<u8>, KeyvalueError> {
+        self.inner.keyvalue_implementor.validate_key(key)?;
+        Ok(self.runtime.block_on(self.inner.get(key))?)
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) -> Result<(), KeyvalueError> {
+        self.inner.keyvalue_implementor.validate_key(key)?;
+        Ok(self
+            .runtime
+            .block_on(self.inner.keyvalue_implementor.set(key, value))?)
+    }
+
+    #[cfg(feature = "kv-keys")]
+    pub fn keys(&self) -> Result<Vec<String>, KeyvalueError> {
+        Ok(self
+            .runtime
+            .block_on(self.inner.keyvalue_implementor.keys())?)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), KeyvalueError> {
+        self.inner.keyvalue_implementor.validate_key(key)?;
+        Ok(self
+            .runtime
+            .block_on(self.inner.keyvalue_implementor.delete(key))?)
+    }
+}
@@ -0,0 +1,96 @@
+//! Deletions of large prefixes, run in the background so that guests don't
+//! block on them (see `delete-prefix-in-background`).
+//!
+//! A job deletes the keys of its prefix a page at a time, with
+//! `KeyvalueImplementor::delete_prefix_page`, until none is left or a page
+//! fails. Guests follow its progress with `delete-status`, and a job is
+//! forgotten once its status was reported as done.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use tracing::log;
+
+use crate::{implementors::KeyvalueImplementor, keyvalue::KeyvalueError};
+
+/// How many keys each call to the backend deletes at most.
+pub const PAGE_SIZE: usize = 1000;
+
+/// The progress of a job, as reported to guests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeleteProgress {
+    pub deleted: u64,
+    pub done: bool,
+    /// Why the job stopped early, if it did.
+    pub error: Option<String>,
+}
+
+/// The jobs started by the guests of a host, by id.
+#[derive(Debug, Default)]
+pub struct DeleteJobs {
+    next_id: Mutex<u64>,
+    jobs: Arc<Mutex<HashMap<u64, DeleteProgress>>>,
+}
+
+impl DeleteJobs {
+    /// Starts deleting every key of `implementor` that starts with `prefix`,
+    /// and returns the id of the job.
+    pub fn start(
+        &self,
+        implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+        prefix: &str,
+    ) -> u64 {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert(id, DeleteProgress::default());
+        let jobs = self.jobs.clone();
+        let prefix = prefix.to_string();
+        tokio::spawn(async move {
+            loop {
+                let page = implementor.delete_prefix_page(&prefix, PAGE_SIZE).await;
+                let mut jobs = jobs.lock().unwrap();
+                let progress = jobs
+                    .get_mut(&id)
+                    .expect("jobs are only forgotten once done");
+                match page {
+                    Ok(0) => progress.done = true,
+                    Ok(deleted) => progress.deleted += deleted,
+                    Err(e) => {
+                        log::error!("failed to delete prefix '{prefix}': {e:?}");
+                        progress.done = true;
+                        progress.error = Some(e.to_string());
+                    }
+                }
+                if progress.done {
+                    return;
+                }
+            }
+        });
+        id
+    }
+
+    /// Returns the progress of job `id`, and forgets it if it is done.
+    ///
+    /// Fails with `KeyvalueError::InvalidValue` if there is no such job (e.g.,
+    /// its status was already reported as done).
+    pub fn status(&self, id: u64) -> Result<DeleteProgress> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let progress = jobs
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| KeyvalueError::InvalidValue(format!("unknown delete job {id}")))?;
+        if progress.done {
+            jobs.remove(&id);
+        }
+        Ok(progress)
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use slight_common::BasicState;
+use slight_runtime_configs::maybe_get_from_state;
+use tokio::sync::OnceCell;
+
+use crate::keyvalue::KeyvalueError;
+
+/// How long an idempotency key is remembered by default.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(600);
+/// How many idempotency keys a store remembers at most by default.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// The outcome of an operation, as returned again to the calls repeating it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// The revision a `set-if-version` wrote.
+    Revision(u64),
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The operation the key was first used for (e.g., `set on 'my-key'`),
+    /// which repeats must match.
+    request: String,
+    first_seen: Instant,
+    outcome: Arc<OnceCell<Outcome>>,
+}
+
+/// The entries of a table, along with their keys from the oldest to the most
+/// recently used, so that evicting doesn't scan every entry.
+///
+/// A key used again after it expired is pushed again, and its former place in
+/// `by_age` (recognized by its `first_seen` not matching the entry's) is
+/// skipped once it reaches the front.
+#[derive(Debug, Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    by_age: VecDeque<(Instant, String)>,
+}
+
+/// The idempotency keys of a store, along with the outcome of the operation
+/// each of them tagged, so that a guest retrying an operation (e.g., after a
+/// timeout) gets the outcome of the attempt that landed rather than applying
+/// it twice.
+///
+/// Only successful outcomes are recorded: an operation that failed is applied
+/// again by the next call with its idempotency key. Keys are forgotten after
+/// `ttl`, or from the oldest once `capacity` keys are remembered, and aren't
+/// shared with other slight instances using the same backend.
+#[derive(Debug)]
+pub struct IdempotencyTable {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl IdempotencyTable {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Creates the table of a store, as configured by its capability's
+    /// `IDEMPOTENCY_TTL_SECS` and `IDEMPOTENCY_CAPACITY` (defaulting to
+    /// `DEFAULT_TTL` and `DEFAULT_CAPACITY`).
+    pub async fn from_state(slight_state: &BasicState) -> Result<Self> {
+        let ttl = maybe_get_from_state("IDEMPOTENCY_TTL_SECS", slight_state)
+            .await?
+            .map(|secs| {
+                secs.parse().map(Duration::from_secs).with_context(|| {
+                    format!("IDEMPOTENCY_TTL_SECS must be a number of seconds, got '{secs}'")
+                })
+            })
+            .transpose()?
+            .unwrap_or(DEFAULT_TTL);
+        let capacity = maybe_get_from_state("IDEMPOTENCY_CAPACITY", slight_state)
+            .await?
+            .map(|capacity| {
+                capacity.parse().with_context(|| {
+                    format!("IDEMPOTENCY_CAPACITY must be a number of keys, got '{capacity}'")
+                })
+            })
+            .transpose()?
+            .unwrap_or(DEFAULT_CAPACITY);
+        Ok(Self::new(ttl, capacity))
+    }
+
+    /// Runs `op`, the operation described by `request`, unless a call with the
+    /// same `idempotency_key` already did, in which case it returns that call's
+    /// outcome. Concurrent calls with the same key wait for the first one.
+    ///
+    /// Fails with `KeyvalueError::InvalidValue` if `idempotency_key` was used
+    /// for another request.
+    pub async fn run<F, Fut>(
+        &self,
+        idempotency_key: &str,
+        request: String,
+        op: F,
+    ) -> Result<Outcome>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Outcome>>,
+    {
+        let outcome = {
+            let mut entries = self.entries.lock().unwrap();
+            // an expired key is forgotten even if it wasn't evicted yet, so that
+            // it can be used for another request
+            if entries
+                .by_key
+                .get(idempotency_key)
+                .is_some_and(|entry| entry.first_seen.elapsed() >= self.ttl)
+            {
+                entries.by_key.remove(idempotency_key);
+            }
+            if !entries.by_key.contains_key(idempotency_key) {
+                self.evict(&mut entries);
+                let first_seen = Instant::now();
+                entries
+                    .by_age
+                    .push_back((first_seen, idempotency_key.to_string()));
+                entries.by_key.insert(
+                    idempotency_key.to_string(),
+                    Entry {
+                        request: request.clone(),
+                        first_seen,
+                        outcome: Arc::new(OnceCell::new()),
+                    },
+                );
+            }
+            let entry = &entries.by_key[idempotency_key];
+            if entry.request != request {
+                return Err(KeyvalueError::InvalidValue(format!(
+                    "idempotency key '{idempotency_key}' was already used for {}",
+                    entry.request
+                ))
+                .into());
+            }
+            entry.outcome.clone()
+        };
+        outcome.get_or_try_init(op).await.copied()
+    }
+
+    /// Forgets the expired keys, then the oldest ones until there is room for
+    /// another key.
+    fn evict(&self, entries: &mut Entries) {
+        while let Some((first_seen, _)) = entries.by_age.front() {
+            if first_seen.elapsed() < self.ttl && entries.by_key.len() < self.capacity {
+                break;
+            }
+            let (first_seen, key) = entries.by_age.pop_front().unwrap();
+            if entries
+                .by_key
+                .get(&key)
+                .is_some_and(|entry| entry.first_seen == first_seen)
+            {
+                entries.by_key.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    /// Runs a `set` of `key` tagged `idempotency_key`, counting in `runs` the
+    /// times it's actually applied.
+    async fn set(
+        table: &IdempotencyTable,
+        idempotency_key: &str,
+        key: &str,
+        runs: &AtomicU32,
+    ) -> Result<Outcome> {
+        table
+            .run(idempotency_key, format!("set on '{key}'"), || async {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(Outcome::Done)
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_repeats_are_applied_once() -> Result<()> {
+        let table = IdempotencyTable::new(DEFAULT_TTL, DEFAULT_CAPACITY);
+        let runs = AtomicU32::new(0);
+
+        assert_eq!(set(&table, "token", "my-key", &runs).await?, Outcome::Done);
+        assert_eq!(set(&table, "token", "my-key", &runs).await?, Outcome::Done);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(set(&table, "token", "my-other-key", &runs).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_keys_are_forgotten() -> Result<()> {
+        let table = IdempotencyTable::new(Duration::from_millis(20), DEFAULT_CAPACITY);
+        let runs = AtomicU32::new(0);
+
+        set(&table, "token", "my-key", &runs).await?;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        // expired, so it can tag another request, which is applied
+        set(&table, "token", "my-other-key", &runs).await?;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        set(&table, "token", "my-other-key", &runs).await?;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oldest_keys_are_evicted_at_capacity() -> Result<()> {
+        let table = IdempotencyTable::new(DEFAULT_TTL, 2);
+        let runs = AtomicU32::new(0);
+
+        for token in ["token-1", "token-2", "token-3"] {
+            set(&table, token, "my-key", &runs).await?;
+        }
+        assert_eq!(table.entries.lock().unwrap().by_key.len(), 2);
+        set(&table, "token-3", "my-key", &runs).await?;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        set(&table, "token-1", "my-key", &runs).await?;
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, KeyvalueError, SetOutcome, VersionedValue},
+    metadata::KeyMetadata,
+    stream::{KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
+
+use super::{KeysPage, KeyvalueImplementor};
+#[cfg(feature = "kv-stream")]
+use crate::{
+    scan::{KeyvalueScanImplementor, MappedScan},
+    watch::KeyvalueWatchImplementor,
+};
+
+/// This is a wrapper around any `KeyvalueImplementor` that only lets guests
+/// access the keys starting with one of its allowed prefixes, enabled with the
+/// `ALLOWED_PREFIXES` config (a comma-separated list of prefixes).
+///
+/// Other keys are rejected with `KeyvalueError::Forbidden` by `validate_key`,
+/// which the host calls before every operation that takes a key, and are left
+/// out of `keys`, `keys_older_than` and `scan`. This isolates guests at the host
+/// boundary, regardless of the backend's own access control.
+#[derive(Debug, Clone)]
+pub struct AllowListImplementor {
+    inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    allowed_prefixes: Vec<String>,
+}
+
+impl AllowListImplementor {
+    pub fn new(
+        inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+        allowed_prefixes: Vec<String>,
+    ) -> Self {
+        Self {
+            inner,
+            allowed_prefixes,
+        }
+    }
+
+    fn is_allowed(&self, key: &str) -> bool {
+        self.allowed_prefixes
+            .iter()
+            .any(|prefix| key.starts_with(prefix.as_str()))
+    }
+
+    fn allowed_keys(&self, keys: Vec<String>) -> Vec<String> {
+        keys.into_iter()
+            .filter(|key| self.is_allowed(key))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for AllowListImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        if !self.is_allowed(key) {
+            return Err(KeyvalueError::Forbidden(key.to_string()).into());
+        }
+        self.inner.validate_key(key)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.inner.get(key).await
+    }
+
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        self.inner.get_consistent(key, strong).await
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.inner.get_range(key, offset, len).await
+    }
+
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        self.inner.get_stream(key).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.inner.set(key, value).await
+    }
+
+    async fn set_stream(
+        &self,
+        key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        self.inner.set_stream(key).await
+    }
+
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        self.inner.set_reporting(key, value).await
+    }
+
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        self.inner
+            .set_with_content_type(key, value, content_type, content_encoding)
+            .await
+    }
+
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.inner.set_with_expiry(key, value, expiry).await
+    }
+
+    /// Only the allowed keys are listed.
+    async fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.allowed_keys(self.inner.keys().await?))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        let (keys, cursor) = self.inner.list_keys(prefix, cursor, limit).await?;
+        Ok((self.allowed_keys(keys), cursor))
+    }
+
+    // `delete_prefix` isn't forwarded: the default implementation only
+    // deletes keys listed by `keys` (i.e., allowed ones).
+
+    async fn health(&self) -> Result<()> {
+        self.inner.health().await
+    }
+
+    fn connection_status(&self) -> ConnectionStatus {
+        self.inner.connection_status()
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.inner.reconnect().await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        Ok(self.allowed_keys(self.inner.keys_older_than(seconds).await?))
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        Ok(self.allowed_keys(self.inner.keys_by_index(value).await?))
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        let this = self.clone();
+        Ok(Box::new(MappedScan::new(
+            self.inner.scan().await?,
+            move |pair| this.is_allowed(&pair.0).then_some(pair),
+        )))
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        self.inner.watch(key).await
+    }
+
+    async fn undelete(&self, key: &str) -> Result<()> {
+        self.inner.undelete(key).await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        self.inner.lock(key, ttl).await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        self.inner.unlock(key, token).await
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.inner.get_metadata(key).await
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.inner.set_metadata(key, metadata).await
+    }
+
+    async fn get_versioned(&self, key: &str) -> Result<VersionedValue> {
+        self.inner.get_versioned(key).await
+    }
+
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        self.inner.set_if_version(key, value, expected).await
+    }
+
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        self.inner.cas(key, expected, new).await
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        self.inner.increment(key, delta).await
+    }
+}
@@ -1,25 +1,68 @@
-use anyhow::{bail, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
-use aws_config::{from_env, meta::region::RegionProviderChain};
-use aws_sdk_dynamodb::model::{AttributeValue, Select};
-use aws_sdk_dynamodb::Client;
+use aws_config::{from_env, meta::region::RegionProviderChain, SdkConfig};
+use aws_sdk_dynamodb::model::{
+    AttributeValue, DeleteRequest, KeysAndAttributes, PutRequest, ReturnValue, Select, WriteRequest,
+};
+use aws_sdk_dynamodb::types::{Blob, DisplayErrorContext, SdkError};
+use aws_sdk_dynamodb::{Client, Credentials, Region};
+use aws_smithy_types::retry::ProvideErrorKind;
 
+use serde::Deserialize;
 use slight_common::BasicState;
-use slight_runtime_configs::get_from_state;
+use slight_runtime_configs::configs_from_state;
 use tracing::log;
 
-use super::KeyvalueImplementor;
+use crate::{
+    keyvalue::{KeyvalueCapabilities, KeyvalueError, SetOutcome},
+    metadata::KeyMetadata,
+};
+
+use super::{
+    add_to_counter, default_capabilities, expiry_timestamp, has_expired, invalid_key,
+    is_key_not_found, lock_token, now_timestamp, now_timestamp_millis, parse_counter,
+    retrying::RetryPolicy, KeysPage, KeyvalueImplementor,
+};
+#[cfg(feature = "kv-keys")]
+use super::{cutoff_timestamp, unsupported};
+#[cfg(feature = "kv-keys")]
+use crate::keyvalue::Operation;
+#[cfg(feature = "kv-stream")]
+use crate::scan::{KeyvalueScanImplementor, Pair};
+
+/// The access key ID and secret access key used against a non-AWS endpoint when
+/// none is configured.
+const LOCAL_CREDENTIAL: &str = "local";
+/// The region used against a non-AWS endpoint when none is configured.
+const LOCAL_REGION: &str = "us-east-1";
 
 /// This is the underlying struct behind the "AWS DynamoDB" variant of the `KeyvalueImplementor` enum.
 ///
 /// It provides a properties that pertains solely to the AWS DynamoDB implementation
 /// of this capability:
-///    - `client`, and
-///   - `table_name`,
+///    - `client`,
+///   - `replica_client` (i.e., the client of the replica serving reads instead, if any),
+///   - `table_name`, and
+///   - `index` (i.e., the global secondary index `keys_by_index` queries, if any).
 #[derive(Debug, Clone)]
 pub struct AwsDynamoDbImplementor {
     client: Client,
+    replica_client: Option<Client>,
     table_name: String,
+    index: Option<SecondaryIndex>,
+}
+
+/// A global secondary index of the table, keyed by a string attribute.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "kv-keys"), allow(dead_code))]
+struct SecondaryIndex {
+    name: String,
+    attribute: String,
 }
 
 impl AwsDynamoDbImplementor {
@@ -41,99 +84,308 @@ impl AwsDynamoDbImplementor {
     ///       "S": <key>
     ///   },
     ///   "value": {
-    ///       "S": <value>
+    ///       "B": <value>
     ///   }
     /// }
     /// ```
-    pub async fn new(slight_state: &BasicState, name: &str) -> Self {
-        let access_id = get_from_state("AWS_ACCESS_KEY_ID", slight_state)
-            .await
-            .unwrap();
-        std::env::set_var("AWS_ACCESS_KEY_ID", access_id);
-
-        let access_key = get_from_state("AWS_SECRET_ACCESS_KEY", slight_state)
-            .await
-            .unwrap();
-        std::env::set_var("AWS_SECRET_ACCESS_KEY", access_key);
-
-        let region = get_from_state("AWS_REGION", slight_state).await;
-        let default_region = get_from_state("AWS_DEFAULT_REGION", slight_state).await;
-        if region.is_err() && default_region.is_err() {
-            panic!("AWS_REGION or AWS_DEFAULT_REGION must be set");
-        } else if region.is_err() {
-            std::env::set_var("AWS_DEFAULT_REGION", default_region.unwrap());
-        } else {
-            std::env::set_var("AWS_REGION", region.unwrap());
-        }
-
-        let region = RegionProviderChain::default_provider();
-        let config = from_env().region(region).load().await;
-        let client = Client::new(&config);
+    ///
+    /// Values too large for a single item are stored as a manifest item
+    /// (`{ "key": <key>, "chunks": { "N": <count> } }`) plus one chunk item per
+    /// piece (`{ "key": "<key>#<i>", "value": ..., "chunk_of": <key> }`). Keys of
+    /// the form `<key>#<i>` are therefore reserved, and rejected as invalid.
+    ///
+    /// Each logical item also carries a `created_at` number attribute holding when
+    /// its value was last written (in seconds since the unix epoch), and, once
+    /// set, a `metadata` map attribute holding its key metadata. Items written
+    /// by `set_with_expiry` (chunks included) carry a `ttl` number attribute
+    /// holding when they expire (in seconds since the unix epoch): enabling
+    /// DynamoDB's TTL on `ttl` has it delete them eventually, and they are
+    /// hidden from every read once expired either way.
+    ///
+    /// Locks are kept as their own items (`{ "key": "<key>#lock", "lock_of": <key>,
+    /// "token": ..., "expires_at": <ms> }`), so keys of the form `<key>#lock` are
+    /// reserved (and rejected) as well.
+    ///
+    /// Setting `AWS_ENDPOINT_URL` (e.g., `http://localhost:8000` for DynamoDB
+    /// local) sends requests there instead of AWS. The credentials and region are
+    /// then optional, and default to dummy values that such endpoints accept.
+    ///
+    /// Setting `AWS_DYNAMODB_INDEX` to the name of a global secondary index of
+    /// the table, along with `AWS_DYNAMODB_INDEX_ATTRIBUTE` to the name of its
+    /// (string) partition key, enables `keys_by_index`, which queries the index
+    /// instead of scanning the table. Slight doesn't write that attribute, and
+    /// `set` replaces whole items, so it must be written by the table's other
+    /// clients after the values.
+    ///
+    /// For global tables, setting `AWS_READ_REGION` to the region of a replica
+    /// of the table serves `get`, `keys`, `keys_older_than` and `keys_by_index`
+    /// from that replica, while every other operation goes to the table in
+    /// `AWS_REGION`; `PREFER_REPLICA = "false"` reads from `AWS_REGION` anyway.
+    /// Global tables replicate asynchronously, so replica reads may not see
+    /// the latest writes (including the store's own): guests that read their
+    /// writes back should use `get_consistent` with `strong` set, which is
+    /// always served by the table in `AWS_REGION`.
+    pub async fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let config: AwsDynamoDbConfig = configs_from_state(slight_state).await?;
+        let read_region = config.aws_read_region.clone();
+        let prefer_replica = config.prefer_replica.unwrap_or(true);
+        let index = match (
+            config.aws_dynamodb_index.clone(),
+            config.aws_dynamodb_index_attribute.clone(),
+        ) {
+            (Some(name), Some(attribute)) => Some(SecondaryIndex { name, attribute }),
+            (None, None) => None,
+            _ => bail!("AWS_DYNAMODB_INDEX and AWS_DYNAMODB_INDEX_ATTRIBUTE must be set together"),
+        };
+        let sdk_config = match config.aws_endpoint_url.clone() {
+            Some(endpoint_url) => endpoint_config(config, endpoint_url).await,
+            None => aws_config(config).await?,
+        };
+        let client = Client::new(&sdk_config);
+        let replica_client = match read_region {
+            Some(read_region) if prefer_replica => {
+                log::info!(
+                    "Reading from the DynamoDB replica in region: {}",
+                    read_region
+                );
+                let replica_config = aws_sdk_dynamodb::config::Builder::from(&sdk_config)
+                    .region(Region::new(read_region))
+                    .build();
+                Some(Client::from_conf(replica_config))
+            }
+            _ => None,
+        };
         let table_name = name.into();
         log::info!(
             "Creating a new AWS DynamoDB resource with table name: {}",
             name
         );
-        Self { client, table_name }
+        Ok(Self {
+            client,
+            replica_client,
+            table_name,
+            index,
+        })
     }
 }
 
+/// The configs of an AWS DynamoDB store (see `AwsDynamoDbImplementor::new`).
+///
+/// The credentials, and either region, are required unless `AWS_ENDPOINT_URL`
+/// is set.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct AwsDynamoDbConfig {
+    aws_access_key_id: Option<String>,
+    aws_secret_access_key: Option<String>,
+    aws_region: Option<String>,
+    aws_default_region: Option<String>,
+    aws_endpoint_url: Option<String>,
+    aws_dynamodb_index: Option<String>,
+    aws_dynamodb_index_attribute: Option<String>,
+    aws_read_region: Option<String>,
+    prefer_replica: Option<bool>,
+}
+
+/// Loads the AWS configuration from the capability's AWS configs.
+async fn aws_config(config: AwsDynamoDbConfig) -> Result<SdkConfig> {
+    let access_id = config
+        .aws_access_key_id
+        .context("AWS_ACCESS_KEY_ID must be set")?;
+    std::env::set_var("AWS_ACCESS_KEY_ID", access_id);
+
+    let access_key = config
+        .aws_secret_access_key
+        .context("AWS_SECRET_ACCESS_KEY must be set")?;
+    std::env::set_var("AWS_SECRET_ACCESS_KEY", access_key);
+
+    match (config.aws_region, config.aws_default_region) {
+        (Some(region), _) => std::env::set_var("AWS_REGION", region),
+        (None, Some(default_region)) => std::env::set_var("AWS_DEFAULT_REGION", default_region),
+        (None, None) => bail!("AWS_REGION or AWS_DEFAULT_REGION must be set"),
+    }
+
+    let region = RegionProviderChain::default_provider();
+    Ok(from_env().region(region).load().await)
+}
+
+/// Loads the AWS configuration for a DynamoDB-compatible endpoint other than
+/// AWS, where the credentials and region may be omitted.
+async fn endpoint_config(config: AwsDynamoDbConfig, endpoint_url: String) -> SdkConfig {
+    log::info!("Using DynamoDB endpoint: {}", endpoint_url);
+    let access_id = config
+        .aws_access_key_id
+        .unwrap_or_else(|| LOCAL_CREDENTIAL.to_string());
+    let access_key = config
+        .aws_secret_access_key
+        .unwrap_or_else(|| LOCAL_CREDENTIAL.to_string());
+    let region = config
+        .aws_region
+        .or(config.aws_default_region)
+        .unwrap_or_else(|| LOCAL_REGION.to_string());
+    from_env()
+        .endpoint_url(endpoint_url)
+        .credentials_provider(Credentials::new(
+            access_id, access_key, None, None, "slight",
+        ))
+        .region(Region::new(region))
+        .load()
+        .await
+}
+
 #[async_trait]
 impl KeyvalueImplementor for AwsDynamoDbImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        if key.is_empty() || key.len() > 2048 {
+            return Err(invalid_key(
+                key,
+                "partition keys must be 1 to 2048 bytes long",
+            ));
+        }
+        if is_reserved(key) {
+            return Err(invalid_key(
+                key,
+                "keys ending in '#<i>' or '#lock' are reserved for chunks and locks",
+            ));
+        }
+        Ok(())
+    }
+
     async fn get(&self, key: &str) -> Result<Vec<u8>> {
-        let key_attribute = AttributeValue::S(key.into());
+        self.get_consistent(key, false).await
+    }
+
+    /// Maps `strong` to DynamoDB's `ConsistentRead`, for the manifest and chunk
+    /// items alike. Strong reads are served by the table in `AWS_REGION`, and
+    /// others by the replica, if any.
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
         log::info!("Getting value from key: {}", key);
+        let client = if strong { &self.client } else { self.reads() };
+        let item = match self.get_item(client, key, strong).await? {
+            Some(item) if !is_expired(&item) => item,
+            _ => return Err(KeyvalueError::KeyNotFound(key.to_string()).into()),
+        };
+
+        match item.get(CHUNKS_ATTRIBUTE) {
+            Some(chunks) => {
+                let chunks = chunk_count(key, chunks)?;
+                let mut value = vec![];
+                for i in 0..chunks {
+                    let chunk = self
+                        .get_item(client, &chunk_key(key, i), strong)
+                        .await?
+                        .with_context(|| format!("missing chunk {i} for key: {key}"))?;
+                    value.extend_from_slice(value_bytes(&chunk)?);
+                }
+                Ok(value)
+            }
+            None => Ok(value_bytes(&item)?.to_vec()),
+        }
+    }
+
+    /// Uses a `GetItem` projected on the item's key (and expiry), so that the
+    /// value isn't read.
+    async fn exists(&self, key: &str) -> Result<bool> {
         let res = self
-            .client
-            .query()
+            .reads()
+            .get_item()
             .table_name(&self.table_name)
-            .key_condition_expression("#key = :value".to_string())
-            .expression_attribute_names("#key".to_string(), "key".to_string())
-            .expression_attribute_values(":value".to_string(), key_attribute)
-            .select(Select::AllAttributes)
+            .key("key", AttributeValue::S(key.into()))
+            .projection_expression("#key, #ttl")
+            .expression_attribute_names("#key", "key")
+            .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
             .send()
-            .await?;
-        match res.items.unwrap_or_default().pop() {
-            Some(item) => {
-                let value = item.get("value").unwrap();
-                let value = value.as_s().unwrap();
-                Ok(value.as_bytes().to_vec())
-            }
-            None => bail!("no value found for key: {}", key),
-        }
+            .await
+            .map_err(sdk_error)?;
+        Ok(res.item.map_or(false, |item| !is_expired(&item)))
     }
 
+    /// See `set_reporting`.
     async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
-        let key_attribute = AttributeValue::S(key.into());
-        let value = AttributeValue::S(
-            String::from_utf8(value.to_vec()).expect("failed to convert value to String"),
-        );
-        log::info!("Setting key value pair: ({}, {:#?})", key, value);
+        self.set_reporting(key, value).await?;
+        Ok(())
+    }
 
-        self.client
-            .put_item()
-            .table_name(&self.table_name)
-            .item("key", key_attribute)
-            .item("value", value)
-            .send()
+    /// Values larger than DynamoDB's 400 KB item limit are transparently split
+    /// into chunk items (`<key>#0`, `<key>#1`, …), with the item under `key`
+    /// becoming a manifest that records the chunk count. Chunks are written
+    /// before the manifest, so a reader never observes a manifest pointing at
+    /// missing chunks.
+    ///
+    /// Whether the key existed before comes from the old item `PutItem` returns.
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        self.put_value(key, value, None).await
+    }
+
+    /// Stores when the key expires in the `ttl` attribute of its items, which
+    /// DynamoDB's TTL can be enabled on (see `AwsDynamoDbImplementor::new`).
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.put_value(key, value, Some(expiry_timestamp(expiry)))
             .await?;
         Ok(())
     }
 
+    /// Only logical keys are listed, chunk and expired items are hidden.
+    ///
+    /// This scans the whole table, which is slow and costly on large tables
+    /// (see `keys_by_index`).
     async fn keys(&self) -> Result<Vec<String>> {
-        let res = self
-            .client
+        let keys = self
+            .scan_keys(self.reads(), None)
+            .await?
+            .into_iter()
+            .filter(|(_, hidden)| !hidden)
+            .map(|(key, _)| key)
+            .collect();
+        Ok(keys)
+    }
+
+    /// Reads a single page of a `Scan`, of up to `limit` items, filtered by
+    /// `prefix` on the server side (along with chunk, lock and expired items),
+    /// and uses the `LastEvaluatedKey` of the page as its cursor. As `Limit`
+    /// counts the items read rather than the ones matching, pages of a narrow
+    /// prefix are often short or empty.
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        let mut filter = format!(
+            "attribute_not_exists(#chunk_of) AND attribute_not_exists(#lock_of) AND {UNEXPIRED_FILTER}"
+        );
+        let mut scan = self
+            .reads()
             .scan()
             .table_name(&self.table_name)
-            .select(Select::AllAttributes)
+            .projection_expression("#key")
+            .expression_attribute_names("#key", "key")
+            .expression_attribute_names("#chunk_of", CHUNK_OF_ATTRIBUTE)
+            .expression_attribute_names("#lock_of", LOCK_OF_ATTRIBUTE)
+            .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
+            .expression_attribute_values(":now", AttributeValue::N(now_timestamp().to_string()))
+            .limit(limit.min(i32::MAX as u32) as i32)
+            .set_exclusive_start_key(cursor.map(|cursor| {
+                HashMap::from([("key".to_string(), AttributeValue::S(cursor.into()))])
+            }));
+        if !prefix.is_empty() {
+            filter = format!("begins_with(#key, :prefix) AND {filter}");
+            scan = scan.expression_attribute_values(":prefix", AttributeValue::S(prefix.into()));
+        }
+        let res = scan
+            .filter_expression(filter)
             .send()
-            .await?;
-        let items = res.items.unwrap_or_default();
-        let keys = items
-            .iter()
-            .map(|item| item.get("key").unwrap().as_s().unwrap().to_string())
+            .await
+            .map_err(sdk_error)?;
+        let keys = res
+            .items
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|mut item| match item.remove("key") {
+                Some(AttributeValue::S(key)) => Some(key),
+                _ => None,
+            })
             .collect();
-        Ok(keys)
+        let cursor = match res.last_evaluated_key.and_then(|mut key| key.remove("key")) {
+            Some(AttributeValue::S(key)) => Some(key),
+            _ => None,
+        };
+        Ok((keys, cursor))
     }
 
     /// FIXME: should delete return a success if it is a noop
@@ -141,12 +393,1051 @@ impl KeyvalueImplementor for AwsDynamoDbImplementor {
     async fn delete(&self, key: &str) -> Result<()> {
         let key_attribute = AttributeValue::S(key.into());
         log::info!("Deleting key: {}", key);
-        self.client
+        let res = self
+            .client
             .delete_item()
             .table_name(&self.table_name)
             .key("key", key_attribute)
+            .return_values(ReturnValue::AllOld)
             .send()
+            .await
+            .map_err(sdk_error)?;
+        if let Some(chunks) = old_chunk_count(key, res.attributes.as_ref())? {
+            let chunk_keys = (0..chunks).map(|i| chunk_key(key, i)).collect::<Vec<_>>();
+            self.batch_delete(&chunk_keys).await?;
+        }
+        Ok(())
+    }
+
+    /// Uses `BatchGetItem`, 100 keys at a time (DynamoDB's limit per call).
+    /// Values split into chunks are read with `get` instead.
+    async fn get_bulk(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        let items = self.batch_get(self.reads(), keys, None).await?;
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            let value = match items.get(*key) {
+                None => None,
+                Some(item) if is_expired(item) => None,
+                Some(item) if item.contains_key(CHUNKS_ATTRIBUTE) => match self.get(key).await {
+                    Ok(value) => Some(value),
+                    // deleted since the batch was read
+                    Err(e) if is_key_not_found(&e) => None,
+                    Err(e) => return Err(e),
+                },
+                Some(item) => Some(value_bytes(item)?.to_vec()),
+            };
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    /// Uses `BatchWriteItem`, 25 items at a time (DynamoDB's limit per call).
+    /// The chunk counts of the old values are read with `BatchGetItem` first,
+    /// so that the chunks of overwritten values are cleaned up, as with `set`.
+    /// Values too large for a single item are written with `set` instead.
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        // a batch can't write the same key twice, so the last value wins
+        let pairs: HashMap<&str, &[u8]> = pairs.iter().copied().collect();
+        let mut small = vec![];
+        for (key, value) in pairs {
+            if value.len() > MAX_CHUNK_SIZE {
+                self.set(key, value).await?;
+            } else {
+                small.push((key, value));
+            }
+        }
+        let keys: Vec<&str> = small.iter().map(|(key, _)| *key).collect();
+        let old_items = self
+            .batch_get(&self.client, &keys, Some(CHUNKS_ATTRIBUTE))
             .await?;
+
+        let created_at = now_timestamp().to_string();
+        let requests = small
+            .iter()
+            .map(|(key, value)| {
+                WriteRequest::builder()
+                    .put_request(
+                        PutRequest::builder()
+                            .item("key", AttributeValue::S(key.to_string()))
+                            .item("value", AttributeValue::B(Blob::new(*value)))
+                            .item(CREATED_AT_ATTRIBUTE, AttributeValue::N(created_at.clone()))
+                            .build(),
+                    )
+                    .build()
+            })
+            .collect();
+        self.batch_write(requests).await?;
+
+        let mut stale = vec![];
+        for (key, item) in &old_items {
+            if let Some(chunks) = old_chunk_count(key, Some(item))? {
+                stale.extend((0..chunks).map(|i| chunk_key(key, i)));
+            }
+        }
+        self.batch_delete(&stale).await
+    }
+
+    /// Uses `BatchWriteItem`, 25 items at a time (DynamoDB's limit per call).
+    /// The chunk counts of the values are read with `BatchGetItem` first, so
+    /// that their chunks are deleted too, as with `delete`.
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        let items = self
+            .batch_get(&self.client, keys, Some(CHUNKS_ATTRIBUTE))
+            .await?;
+        // a batch can't delete the same key twice
+        let mut deleted: Vec<String> = keys
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        for (key, item) in &items {
+            if let Some(chunks) = old_chunk_count(key, Some(item))? {
+                deleted.extend((0..chunks).map(|i| chunk_key(key, i)));
+            }
+        }
+        self.batch_delete(&deleted).await
+    }
+
+    /// Scans the whole table, which holds this store alone, and deletes every
+    /// item with `BatchWriteItem` (i.e., chunks, locks and expired items too).
+    async fn clear(&self) -> Result<()> {
+        log::info!("Clearing table: {}", self.table_name);
+        let keys = self
+            .scan_keys(&self.client, None)
+            .await?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        self.batch_delete(&keys).await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        log::info!("Deleting keys with prefix: {}", prefix);
+        // only logical keys are deleted, along with their chunks (as with
+        // `delete_bulk`): a prefix may match the chunks or lock of a key it
+        // doesn't match itself (e.g., `big#` those of `big`)
+        let keys = self
+            .scan_keys(&self.client, Some(prefix))
+            .await?
+            .into_iter()
+            .filter(|(_, hidden)| !hidden)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        let keys = keys.iter().map(String::as_str).collect::<Vec<_>>();
+        self.delete_bulk(&keys).await?;
+        Ok(keys.len() as u64)
+    }
+
+    /// Uses the `created_at` attribute stored alongside each value by `set`.
+    /// Items written before `created_at` was introduced, and expired ones, are
+    /// never listed.
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let res = self
+                .reads()
+                .scan()
+                .table_name(&self.table_name)
+                .projection_expression("#key")
+                .filter_expression(format!("#created_at < :cutoff AND {UNEXPIRED_FILTER}"))
+                .expression_attribute_names("#key", "key")
+                .expression_attribute_names("#created_at", CREATED_AT_ATTRIBUTE)
+                .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
+                .expression_attribute_values(
+                    ":cutoff",
+                    AttributeValue::N(cutoff_timestamp(seconds).to_string()),
+                )
+                .expression_attribute_values(":now", AttributeValue::N(now_timestamp().to_string()))
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(sdk_error)?;
+            for item in res.items.unwrap_or_default() {
+                if let Some(AttributeValue::S(key)) = item.get("key") {
+                    keys.push(key.clone());
+                }
+            }
+            exclusive_start_key = res.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Queries the `AWS_DYNAMODB_INDEX` global secondary index for the items
+    /// whose `AWS_DYNAMODB_INDEX_ATTRIBUTE` is `value`, leaving out expired
+    /// ones. Index reads are eventually consistent.
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Err(unsupported(Operation::KeysByIndex)),
+        };
+        let mut keys = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            // the table's keys are always projected into its indexes
+            let res = self
+                .reads()
+                .query()
+                .table_name(&self.table_name)
+                .index_name(&index.name)
+                .key_condition_expression("#attribute = :value")
+                .filter_expression(UNEXPIRED_FILTER)
+                .projection_expression("#key")
+                .expression_attribute_names("#attribute", &index.attribute)
+                .expression_attribute_names("#key", "key")
+                .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
+                .expression_attribute_values(":value", AttributeValue::S(value.into()))
+                .expression_attribute_values(":now", AttributeValue::N(now_timestamp().to_string()))
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(sdk_error)?;
+            for item in res.items.unwrap_or_default() {
+                if let Some(AttributeValue::S(key)) = item.get("key") {
+                    keys.push(key.clone());
+                }
+            }
+            exclusive_start_key = res.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Scans the table a page at a time, from the replica if any. Chunk, lock
+    /// and expired items are hidden, and chunked values are read in full.
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        Ok(Box::new(AwsDynamoDbScan {
+            implementor: self.clone(),
+            exclusive_start_key: None,
+            done: false,
+        }))
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        let capabilities = default_capabilities()
+            | KeyvalueCapabilities::KEYS_OLDER_THAN
+            | KeyvalueCapabilities::LOCK
+            | KeyvalueCapabilities::SCAN
+            | KeyvalueCapabilities::SET_WITH_EXPIRY
+            | KeyvalueCapabilities::COMPARE_AND_SWAP
+            | KeyvalueCapabilities::INCREMENT;
+        match self.index {
+            Some(_) => capabilities | KeyvalueCapabilities::KEYS_BY_INDEX,
+            None => capabilities,
+        }
+    }
+
+    /// Uses a conditional write on the lock item, which succeeds only if there is
+    /// none or it has expired.
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        let token = lock_token();
+        let now = now_timestamp_millis();
+        let res = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("key", AttributeValue::S(lock_key(key)))
+            .item(LOCK_OF_ATTRIBUTE, AttributeValue::S(key.into()))
+            .item(LOCK_TOKEN_ATTRIBUTE, AttributeValue::S(token.clone()))
+            .item(
+                LOCK_EXPIRES_AT_ATTRIBUTE,
+                AttributeValue::N((now + ttl.as_millis() as i64).to_string()),
+            )
+            .condition_expression("attribute_not_exists(#key) OR #expires_at < :now")
+            .expression_attribute_names("#key", "key")
+            .expression_attribute_names("#expires_at", LOCK_EXPIRES_AT_ATTRIBUTE)
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(token),
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                Err(KeyvalueError::LockHeld(key.to_string()).into())
+            }
+            Err(e) => Err(sdk_error(e)),
+        }
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        let res = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("key", AttributeValue::S(lock_key(key)))
+            .condition_expression("#token = :token")
+            .expression_attribute_names("#token", LOCK_TOKEN_ATTRIBUTE)
+            .expression_attribute_values(":token", AttributeValue::S(token.into()))
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            // the lock expired, and may have been acquired by someone else since
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                Ok(())
+            }
+            Err(e) => Err(sdk_error(e)),
+        }
+    }
+
+    /// Uses a `PutItem` conditioned on the item's `value` attribute, or on
+    /// there being no live item for `None`. Values split into chunks never
+    /// match, and `new` must fit in a single item.
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        if new.len() > MAX_CHUNK_SIZE {
+            return Err(KeyvalueError::InvalidValue(format!(
+                "values swapped with cas are limited to {MAX_CHUNK_SIZE} bytes on DynamoDB"
+            ))
+            .into());
+        }
+        let now = now_timestamp().to_string();
+        let put = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("key", AttributeValue::S(key.into()))
+            .item("value", AttributeValue::B(Blob::new(new)))
+            .item(CREATED_AT_ATTRIBUTE, AttributeValue::N(now.clone()))
+            .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
+            .expression_attribute_values(":now", AttributeValue::N(now))
+            .return_values(ReturnValue::AllOld);
+        let put = match expected {
+            // an expired item is as good as missing
+            None => put
+                .condition_expression("attribute_not_exists(#key) OR #ttl <= :now")
+                .expression_attribute_names("#key", "key"),
+            Some(expected) => put
+                .condition_expression(format!("#value = :expected AND {UNEXPIRED_FILTER}"))
+                .expression_attribute_names("#value", "value")
+                .expression_attribute_values(":expected", AttributeValue::B(Blob::new(expected))),
+        };
+        let res = match put.send().await {
+            Ok(res) => res,
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                return Ok(false)
+            }
+            Err(e) => return Err(sdk_error(e)),
+        };
+        // clean up the chunks of an expired, larger value
+        if let Some(old_chunks) = old_chunk_count(key, res.attributes.as_ref())? {
+            let stale = (0..old_chunks)
+                .map(|i| chunk_key(key, i))
+                .collect::<Vec<_>>();
+            self.batch_delete(&stale).await?;
+        }
+        Ok(true)
+    }
+
+    /// Adds in place with an `UpdateItem`, which keeps the item's expiry and
+    /// metadata, storing the counter as a number (which reads back as its
+    /// decimal text). The update is conditioned on the item being missing, or
+    /// being live with a number the addition keeps within `i64`'s range.
+    ///
+    /// When the condition fails, the item is read to tell why: an expired item
+    /// is replaced, and an integer written by `set` is converted into a number,
+    /// both with writes conditioned on the item not changing in between. If it
+    /// did change, the increment starts over.
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        let (low, high) = match delta {
+            0.. => (i64::MIN, i64::MAX - delta),
+            _ => (i64::MIN - delta, i64::MAX),
+        };
+        loop {
+            let now = now_timestamp().to_string();
+            let res = self
+                .client
+                .update_item()
+                .table_name(&self.table_name)
+                .key("key", AttributeValue::S(key.into()))
+                .update_expression(
+                    "SET #value = if_not_exists(#value, :zero) + :delta, #created_at = if_not_exists(#created_at, :now)",
+                )
+                .condition_expression(format!(
+                    "attribute_not_exists(#key) OR (attribute_type(#value, :number) AND #value BETWEEN :low AND :high AND {UNEXPIRED_FILTER})"
+                ))
+                .expression_attribute_names("#key", "key")
+                .expression_attribute_names("#value", "value")
+                .expression_attribute_names("#created_at", CREATED_AT_ATTRIBUTE)
+                .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
+                .expression_attribute_values(":zero", AttributeValue::N("0".into()))
+                .expression_attribute_values(":delta", AttributeValue::N(delta.to_string()))
+                .expression_attribute_values(":now", AttributeValue::N(now))
+                .expression_attribute_values(":number", AttributeValue::S("N".into()))
+                .expression_attribute_values(":low", AttributeValue::N(low.to_string()))
+                .expression_attribute_values(":high", AttributeValue::N(high.to_string()))
+                .return_values(ReturnValue::UpdatedNew)
+                .send()
+                .await;
+            match res {
+                Ok(res) => {
+                    let value = res
+                        .attributes
+                        .as_ref()
+                        .and_then(|attributes| attributes.get("value"))
+                        .and_then(|value| value.as_n().ok())
+                        .context("DynamoDB didn't return the incremented value")?;
+                    return Ok(value.parse()?);
+                }
+                Err(SdkError::ServiceError(e))
+                    if e.err().is_conditional_check_failed_exception() => {}
+                Err(e) => return Err(sdk_error(e)),
+            }
+
+            let item = match self.get_item(&self.client, key, true).await? {
+                Some(item) => item,
+                // deleted since the update
+                None => continue,
+            };
+            if is_expired(&item) {
+                if self.replace_expired_counter(key, delta).await? {
+                    return Ok(delta);
+                }
+                continue;
+            }
+            // fails if the value isn't an integer, or the addition overflows
+            let value = add_to_counter(key, counter_value(key, &item)?, delta)?;
+            match item.get("value") {
+                // a number (or no item at all) means the item changed since the update
+                Some(AttributeValue::N(_)) | None => continue,
+                Some(old) => {
+                    if self.convert_counter(key, old.clone(), value).await? {
+                        return Ok(value);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        let item = self
+            .get_item(&self.client, key, false)
+            .await?
+            .filter(|item| !is_expired(item))
+            .ok_or_else(|| KeyvalueError::KeyNotFound(key.to_string()))?;
+        match item.get(METADATA_ATTRIBUTE) {
+            Some(AttributeValue::M(metadata)) => KeyMetadata::from_pairs(
+                metadata
+                    .iter()
+                    .filter_map(|(name, value)| Some((name, value.as_s().ok()?))),
+            ),
+            _ => Ok(KeyMetadata::default()),
+        }
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        let metadata = metadata
+            .to_pairs()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), AttributeValue::S(value)))
+            .collect();
+        let res = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("key", AttributeValue::S(key.into()))
+            .update_expression("SET #metadata = :metadata")
+            .condition_expression("attribute_exists(#key)")
+            .expression_attribute_names("#key", "key")
+            .expression_attribute_names("#metadata", METADATA_ATTRIBUTE)
+            .expression_attribute_values(":metadata", AttributeValue::M(metadata))
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+            }
+            Err(e) => Err(sdk_error(e)),
+        }
+    }
+}
+
+/// The attribute of a logical item holding its key metadata.
+const METADATA_ATTRIBUTE: &str = "metadata";
+
+/// The attribute holding when an item's value was written, in seconds since the unix epoch.
+const CREATED_AT_ATTRIBUTE: &str = "created_at";
+
+/// The attribute of an item written by `set_with_expiry` holding when it
+/// expires, in seconds since the unix epoch, as DynamoDB's TTL expects.
+const TTL_ATTRIBUTE: &str = "ttl";
+
+/// Keeps the items of a scan or query that haven't expired, given `#ttl` (see
+/// `TTL_ATTRIBUTE`) and `:now`.
+const UNEXPIRED_FILTER: &str = "(attribute_not_exists(#ttl) OR #ttl > :now)";
+
+/// The attribute of a manifest item holding the number of chunks of its value.
+const CHUNKS_ATTRIBUTE: &str = "chunks";
+
+/// The attribute of a chunk item holding the logical key it belongs to.
+const CHUNK_OF_ATTRIBUTE: &str = "chunk_of";
+
+/// The attribute of a lock item holding the logical key it locks.
+const LOCK_OF_ATTRIBUTE: &str = "lock_of";
+
+/// The attribute of a lock item holding the token of its holder.
+const LOCK_TOKEN_ATTRIBUTE: &str = "token";
+
+/// The attribute of a lock item holding when it expires, in milliseconds since
+/// the unix epoch.
+const LOCK_EXPIRES_AT_ATTRIBUTE: &str = "expires_at";
+
+/// The largest value stored in a single item. DynamoDB limits items
+/// (attribute names included) to 400 KB, so this leaves room for the key
+/// and bookkeeping attributes.
+const MAX_CHUNK_SIZE: usize = 350 * 1024;
+
+fn lock_key(key: &str) -> String {
+    format!("{key}#lock")
+}
+
+fn chunk_key(key: &str, i: usize) -> String {
+    format!("{key}#{i}")
+}
+
+/// Whether `key` has the form of a chunk (`<key>#<i>`) or lock (`<key>#lock`)
+/// item's key, which guests can't write.
+fn is_reserved(key: &str) -> bool {
+    match key.rsplit_once('#') {
+        Some((_, suffix)) => {
+            suffix == "lock" || (!suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()))
+        }
+        None => false,
+    }
+}
+
+/// Parses the `chunks` attribute of the manifest item of `key`, which may have
+/// been written by another client.
+fn chunk_count(key: &str, chunks: &AttributeValue) -> Result<usize> {
+    let chunks = chunks
+        .as_n()
+        .map_err(|_| anyhow!("the chunk count of key '{key}' isn't a number"))?;
+    chunks
+        .parse()
+        .with_context(|| format!("invalid chunk count '{chunks}' for key '{key}'"))
+}
+
+/// Returns the chunk count of the old attributes of `key`'s manifest item, if
+/// any.
+fn old_chunk_count(
+    key: &str,
+    attributes: Option<&HashMap<String, AttributeValue>>,
+) -> Result<Option<usize>> {
+    attributes
+        .and_then(|a| a.get(CHUNKS_ATTRIBUTE))
+        .map(|chunks| chunk_count(key, chunks))
+        .transpose()
+}
+
+/// Whether an item was written by `set_with_expiry` with an expiry that has
+/// passed. DynamoDB deletes expired items long after they expire (if its TTL
+/// is enabled at all), so reads check it themselves.
+fn is_expired(item: &HashMap<String, AttributeValue>) -> bool {
+    match item.get(TTL_ATTRIBUTE) {
+        Some(AttributeValue::N(ttl)) => ttl.parse().map_or(false, has_expired),
+        _ => false,
+    }
+}
+
+/// The error codes of DynamoDB throttling requests.
+const THROTTLING_ERROR_CODES: [&str; 3] = [
+    "ProvisionedThroughputExceededException",
+    "RequestLimitExceeded",
+    "ThrottlingException",
+];
+
+/// The error codes of DynamoDB rejecting the request's credentials.
+const AUTHENTICATION_ERROR_CODES: [&str; 6] = [
+    "AccessDeniedException",
+    "ExpiredTokenException",
+    "IncompleteSignatureException",
+    "InvalidSignatureException",
+    "MissingAuthenticationTokenException",
+    "UnrecognizedClientException",
+];
+
+/// Surfaces the SDK's errors as the `KeyvalueError` guests can tell apart:
+///   - throttling as `KeyvalueError::Throttled`, so that guests can back off
+///   (the SDK has already retried by then, and DynamoDB doesn't suggest how long
+///   to wait),
+///   - rejected credentials as `KeyvalueError::AuthenticationError`, and
+///   - failing to reach DynamoDB, or to hear back in time, as
+///   `KeyvalueError::ConnectionError` or `KeyvalueError::TimeoutError`.
+///
+/// Other errors are returned as is.
+fn sdk_error<E>(e: SdkError<E>) -> anyhow::Error
+where
+    E: ProvideErrorKind + std::error::Error + Send + Sync + 'static,
+{
+    let message = DisplayErrorContext(&e).to_string();
+    match &e {
+        SdkError::DispatchFailure(_) => KeyvalueError::ConnectionError(message).into(),
+        SdkError::TimeoutError(_) => KeyvalueError::TimeoutError(message).into(),
+        SdkError::ServiceError(service) => match service.err().code() {
+            Some(code) if THROTTLING_ERROR_CODES.contains(&code) => {
+                KeyvalueError::Throttled(None).into()
+            }
+            Some(code) if AUTHENTICATION_ERROR_CODES.contains(&code) => {
+                KeyvalueError::AuthenticationError(message).into()
+            }
+            _ => e.into(),
+        },
+        _ => e.into(),
+    }
+}
+
+/// Returns the bytes of an item's `value` attribute.
+///
+/// Values are stored as binary (`B`) attributes, so arbitrary bytes round-trip.
+/// Items written before that hold their value as a string (`S`) attribute,
+/// which is still read.
+fn value_bytes(item: &HashMap<String, AttributeValue>) -> Result<&[u8]> {
+    match item.get("value") {
+        Some(AttributeValue::B(value)) => Ok(value.as_ref()),
+        Some(AttributeValue::S(value)) => Ok(value.as_bytes()),
+        // written by `increment`
+        Some(AttributeValue::N(value)) => Ok(value.as_bytes()),
+        _ => bail!("item has no binary, string or number 'value' attribute"),
+    }
+}
+
+/// Reads the counter a live item holds (see `increment`), whether it is a
+/// number written by `increment`, or the decimal text of a value.
+fn counter_value(key: &str, item: &HashMap<String, AttributeValue>) -> Result<i64> {
+    if item.contains_key(CHUNKS_ATTRIBUTE) {
+        // values split into chunks are far too long to be integers
+        return parse_counter(key, &[]);
+    }
+    parse_counter(key, value_bytes(item)?)
+}
+
+/// A scan over the items of an `AwsDynamoDbImplementor`'s table.
+#[cfg(feature = "kv-stream")]
+struct AwsDynamoDbScan {
+    implementor: AwsDynamoDbImplementor,
+    /// The key of the last item of the previous page.
+    exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    /// Whether the previous page was the last.
+    done: bool,
+}
+
+#[cfg(feature = "kv-stream")]
+#[async_trait]
+impl KeyvalueScanImplementor for AwsDynamoDbScan {
+    async fn next_batch(&mut self) -> Result<Option<Vec<Pair>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let implementor = &self.implementor;
+        let res = implementor
+            .reads()
+            .scan()
+            .table_name(&implementor.table_name)
+            .set_exclusive_start_key(self.exclusive_start_key.take())
+            .send()
+            .await
+            .map_err(sdk_error)?;
+        self.exclusive_start_key = res.last_evaluated_key;
+        self.done = self.exclusive_start_key.is_none();
+
+        let mut pairs = vec![];
+        for item in res.items.unwrap_or_default() {
+            let key = match item.get("key") {
+                Some(AttributeValue::S(key)) => key,
+                _ => continue,
+            };
+            if item.contains_key(CHUNK_OF_ATTRIBUTE)
+                || item.contains_key(LOCK_OF_ATTRIBUTE)
+                || is_expired(&item)
+            {
+                continue;
+            }
+            if !item.contains_key(CHUNKS_ATTRIBUTE) {
+                pairs.push((key.clone(), value_bytes(&item)?.to_vec()));
+                continue;
+            }
+            // the chunks of the value are separate items, which may not be on this page
+            match implementor.get(key).await {
+                Ok(value) => pairs.push((key.clone(), value)),
+                // keys deleted since the page was read are skipped
+                Err(e) if is_key_not_found(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(pairs))
+    }
+}
+
+impl AwsDynamoDbImplementor {
+    /// DynamoDB caps `BatchWriteItem` at 25 requests per call.
+    const MAX_BATCH_WRITE_ITEMS: usize = 25;
+    /// DynamoDB caps `BatchGetItem` at 100 keys per call.
+    const MAX_BATCH_GET_ITEMS: usize = 100;
+    /// How the unprocessed items (or keys) of a batch are resubmitted: DynamoDB
+    /// leaves them out when the table is throttled, so they are resubmitted with
+    /// an exponential backoff, and a batch still unprocessed after the last
+    /// attempt fails with `KeyvalueError::Throttled`.
+    const BATCH_RETRY: RetryPolicy = RetryPolicy {
+        max_attempts: 5,
+        base_delay: Duration::from_millis(50),
+    };
+
+    /// Writes `value` as `set_reporting` describes, with its items expiring at
+    /// `expires_at`, if given.
+    async fn put_value(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<i64>,
+    ) -> Result<SetOutcome> {
+        let key_attribute = AttributeValue::S(key.into());
+        log::info!("Setting value of {} bytes for key: {}", value.len(), key);
+
+        let chunks = if value.len() <= MAX_CHUNK_SIZE {
+            vec![value]
+        } else {
+            value.chunks(MAX_CHUNK_SIZE).collect()
+        };
+        // chunks expire along with the manifest
+        let ttl = expires_at.map(|expires_at| AttributeValue::N(expires_at.to_string()));
+        let mut put = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("key", key_attribute)
+            .item(
+                CREATED_AT_ATTRIBUTE,
+                AttributeValue::N(now_timestamp().to_string()),
+            )
+            .return_values(ReturnValue::AllOld);
+        if let Some(ttl) = &ttl {
+            put = put.item(TTL_ATTRIBUTE, ttl.clone());
+        }
+        let put = if chunks.len() <= 1 {
+            put.item("value", AttributeValue::B(Blob::new(value)))
+        } else {
+            for (i, chunk) in chunks.iter().enumerate() {
+                let mut put_chunk = self
+                    .client
+                    .put_item()
+                    .table_name(&self.table_name)
+                    .item("key", AttributeValue::S(chunk_key(key, i)))
+                    .item("value", AttributeValue::B(Blob::new(*chunk)))
+                    .item(CHUNK_OF_ATTRIBUTE, AttributeValue::S(key.into()));
+                if let Some(ttl) = &ttl {
+                    put_chunk = put_chunk.item(TTL_ATTRIBUTE, ttl.clone());
+                }
+                put_chunk.send().await.map_err(sdk_error)?;
+            }
+            put.item(
+                CHUNKS_ATTRIBUTE,
+                AttributeValue::N(chunks.len().to_string()),
+            )
+        };
+        let res = put.send().await.map_err(sdk_error)?;
+
+        // clean up the chunks of a previous, larger value
+        let new_chunks = if chunks.len() <= 1 { 0 } else { chunks.len() };
+        if let Some(old_chunks) = old_chunk_count(key, res.attributes.as_ref())? {
+            let stale = (new_chunks..old_chunks)
+                .map(|i| chunk_key(key, i))
+                .collect::<Vec<_>>();
+            self.batch_delete(&stale).await?;
+        }
+        Ok(SetOutcome {
+            existed_before: res.attributes.map_or(false, |a| !a.is_empty()),
+            bytes_written: value.len() as u64,
+        })
+    }
+
+    /// The client serving reads that may be stale (i.e., the replica's, if any).
+    fn reads(&self) -> &Client {
+        self.replica_client.as_ref().unwrap_or(&self.client)
+    }
+
+    /// Replaces an expired item with a counter holding `delta`, unless it was
+    /// written since it was read. Returns whether it was replaced.
+    async fn replace_expired_counter(&self, key: &str, delta: i64) -> Result<bool> {
+        let now = now_timestamp().to_string();
+        let res = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("key", AttributeValue::S(key.into()))
+            .item("value", AttributeValue::N(delta.to_string()))
+            .item(CREATED_AT_ATTRIBUTE, AttributeValue::N(now.clone()))
+            .condition_expression("#ttl <= :now")
+            .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
+            .expression_attribute_values(":now", AttributeValue::N(now))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await;
+        let res = match res {
+            Ok(res) => res,
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                return Ok(false)
+            }
+            Err(e) => return Err(sdk_error(e)),
+        };
+        // clean up the chunks of the expired value
+        if let Some(old_chunks) = old_chunk_count(key, res.attributes.as_ref())? {
+            let stale = (0..old_chunks)
+                .map(|i| chunk_key(key, i))
+                .collect::<Vec<_>>();
+            self.batch_delete(&stale).await?;
+        }
+        Ok(true)
+    }
+
+    /// Converts the `old` value of a live item into a counter holding `value`,
+    /// keeping its expiry and metadata, unless it was written since it was
+    /// read. Returns whether it was converted.
+    async fn convert_counter(&self, key: &str, old: AttributeValue, value: i64) -> Result<bool> {
+        let res = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("key", AttributeValue::S(key.into()))
+            .update_expression("SET #value = :value")
+            .condition_expression(format!("#value = :old AND {UNEXPIRED_FILTER}"))
+            .expression_attribute_names("#value", "value")
+            .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
+            .expression_attribute_values(":value", AttributeValue::N(value.to_string()))
+            .expression_attribute_values(":old", old)
+            .expression_attribute_values(":now", AttributeValue::N(now_timestamp().to_string()))
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(e)) if e.err().is_conditional_check_failed_exception() => {
+                Ok(false)
+            }
+            Err(e) => Err(sdk_error(e)),
+        }
+    }
+
+    async fn get_item(
+        &self,
+        client: &Client,
+        key: &str,
+        consistent_read: bool,
+    ) -> Result<Option<HashMap<String, AttributeValue>>> {
+        let key_attribute = AttributeValue::S(key.into());
+        let res = client
+            .query()
+            .table_name(&self.table_name)
+            .key_condition_expression("#key = :value".to_string())
+            .expression_attribute_names("#key".to_string(), "key".to_string())
+            .expression_attribute_values(":value".to_string(), key_attribute)
+            .select(Select::AllAttributes)
+            .consistent_read(consistent_read)
+            .send()
+            .await
+            .map_err(sdk_error)?;
+        Ok(res.items.unwrap_or_default().pop())
+    }
+
+    /// Scans the (optionally prefix-filtered) keys of the table, following
+    /// pagination, and returns each key along with whether it is a chunk, lock
+    /// or expired item (i.e., not a logical key).
+    async fn scan_keys(
+        &self,
+        client: &Client,
+        prefix: Option<&str>,
+    ) -> Result<Vec<(String, bool)>> {
+        let mut keys = vec![];
+        let mut exclusive_start_key = None;
+        let mut pages = 0;
+        loop {
+            pages += 1;
+            let mut scan = client
+                .scan()
+                .table_name(&self.table_name)
+                .projection_expression("#key, #chunk_of, #lock_of, #ttl")
+                .expression_attribute_names("#key", "key")
+                .expression_attribute_names("#chunk_of", CHUNK_OF_ATTRIBUTE)
+                .expression_attribute_names("#lock_of", LOCK_OF_ATTRIBUTE)
+                .expression_attribute_names("#ttl", TTL_ATTRIBUTE)
+                .set_exclusive_start_key(exclusive_start_key);
+            // the table is keyed only by a partition key, so a prefix match
+            // requires a filtered scan rather than a query
+            if let Some(prefix) = prefix {
+                scan = scan
+                    .filter_expression("begins_with(#key, :prefix)")
+                    .expression_attribute_values(":prefix", AttributeValue::S(prefix.into()));
+            }
+            let res = scan.send().await.map_err(sdk_error)?;
+            for item in res.items.unwrap_or_default() {
+                if let Some(AttributeValue::S(key)) = item.get("key") {
+                    let hidden = item.contains_key(CHUNK_OF_ATTRIBUTE)
+                        || item.contains_key(LOCK_OF_ATTRIBUTE)
+                        || is_expired(&item);
+                    keys.push((key.clone(), hidden));
+                }
+            }
+            exclusive_start_key = res.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+        // a single page is at most 1 MB, so more of them hint at a table
+        // large enough for scans to be noticeably slow and costly
+        if pages > 1 {
+            log::warn!(
+                "scanned {pages} pages of DynamoDB table '{}' to list its keys, consider querying an index with AWS_DYNAMODB_INDEX instead",
+                self.table_name
+            );
+        }
+        Ok(keys)
+    }
+
+    /// Deletes `keys` using `BatchWriteItem` (see `batch_write`).
+    async fn batch_delete(&self, keys: &[String]) -> Result<()> {
+        let requests = keys
+            .iter()
+            .map(|key| {
+                WriteRequest::builder()
+                    .delete_request(
+                        DeleteRequest::builder()
+                            .key("key", AttributeValue::S(key.clone()))
+                            .build(),
+                    )
+                    .build()
+            })
+            .collect();
+        self.batch_write(requests).await
+    }
+
+    /// Sends `requests` using `BatchWriteItem`, chunking them to respect
+    /// DynamoDB's batch limit and resubmitting any unprocessed items (see
+    /// `BATCH_RETRY`).
+    async fn batch_write(&self, requests: Vec<WriteRequest>) -> Result<()> {
+        for chunk in requests.chunks(Self::MAX_BATCH_WRITE_ITEMS) {
+            let mut requests = chunk.to_vec();
+            let mut attempt = 0;
+            while !requests.is_empty() {
+                Self::back_off(&mut attempt).await?;
+                let res = self
+                    .client
+                    .batch_write_item()
+                    .request_items(&self.table_name, requests)
+                    .send()
+                    .await
+                    .map_err(sdk_error)?;
+                requests = res
+                    .unprocessed_items
+                    .and_then(|mut unprocessed| unprocessed.remove(&self.table_name))
+                    .unwrap_or_default();
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the items of `keys` using `BatchGetItem`, by key, chunking keys to
+    /// respect DynamoDB's batch limit and resubmitting any unprocessed keys (see
+    /// `BATCH_RETRY`).
+    /// Only the `projection` attribute (and the key) of each item is read, if
+    /// given, and missing keys are left out.
+    async fn batch_get(
+        &self,
+        client: &Client,
+        keys: &[&str],
+        projection: Option<&str>,
+    ) -> Result<HashMap<String, HashMap<String, AttributeValue>>> {
+        // a batch can't read the same key twice
+        let keys: Vec<&str> = keys
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let mut items = HashMap::new();
+        for chunk in keys.chunks(Self::MAX_BATCH_GET_ITEMS) {
+            let mut request = KeysAndAttributes::builder().set_keys(Some(
+                chunk
+                    .iter()
+                    .map(|key| {
+                        HashMap::from([("key".to_string(), AttributeValue::S(key.to_string()))])
+                    })
+                    .collect(),
+            ));
+            if let Some(projection) = projection {
+                request = request
+                    .projection_expression("#key, #projection")
+                    .expression_attribute_names("#key", "key")
+                    .expression_attribute_names("#projection", projection);
+            }
+            let mut request_items = HashMap::from([(self.table_name.clone(), request.build())]);
+            let mut attempt = 0;
+            while !request_items.is_empty() {
+                Self::back_off(&mut attempt).await?;
+                let res = client
+                    .batch_get_item()
+                    .set_request_items(Some(request_items))
+                    .send()
+                    .await
+                    .map_err(sdk_error)?;
+                for item in res
+                    .responses
+                    .and_then(|mut responses| responses.remove(&self.table_name))
+                    .unwrap_or_default()
+                {
+                    if let Some(AttributeValue::S(key)) = item.get("key") {
+                        items.insert(key.clone(), item);
+                    }
+                }
+                request_items = res.unprocessed_keys.unwrap_or_default();
+            }
+        }
+        Ok(items)
+    }
+
+    /// Counts another attempt at sending a batch, first waiting out the backoff
+    /// of `BATCH_RETRY` if it resubmits unprocessed items, and fails once
+    /// they're all used up.
+    async fn back_off(attempt: &mut u32) -> Result<()> {
+        if *attempt >= Self::BATCH_RETRY.max_attempts {
+            return Err(KeyvalueError::Throttled(None).into());
+        }
+        if *attempt > 0 {
+            tokio::time::sleep(Self::BATCH_RETRY.delay(*attempt, None)).await;
+        }
+        *attempt += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_and_lock_keys_are_reserved() {
+        assert!(is_reserved(&chunk_key("big", 0)));
+        assert!(is_reserved(&chunk_key("big", 12)));
+        assert!(is_reserved(&lock_key("big")));
+        assert!(!is_reserved("big"));
+        assert!(!is_reserved("big#"));
+        assert!(!is_reserved("big#0a"));
+        assert!(!is_reserved("big#locked"));
+    }
+
+    #[test]
+    fn test_chunk_count() -> Result<()> {
+        assert_eq!(chunk_count("big", &AttributeValue::N("3".into()))?, 3);
+        assert!(chunk_count("big", &AttributeValue::S("3".into())).is_err());
+        assert!(chunk_count("big", &AttributeValue::N("-1".into())).is_err());
+        assert_eq!(old_chunk_count("big", None)?, None);
         Ok(())
     }
 }
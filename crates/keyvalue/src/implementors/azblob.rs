@@ -1,14 +1,29 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use azure_storage::prelude::*;
+use azure_core::request_options::{Metadata, Range};
+use azure_storage::{prelude::*, CloudLocation};
 use azure_storage_blobs::{container::operations::BlobItem, prelude::*};
+use serde::Deserialize;
 use slight_common::BasicState;
-use slight_runtime_configs::get_from_state;
+use slight_runtime_configs::configs_from_state;
 use tracing::log;
 
-use crate::providers::azure;
+use crate::{
+    keyvalue::{KeyvalueCapabilities, KeyvalueError},
+    metadata::KeyMetadata,
+    providers::azure,
+    stream::{KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
 
-use super::KeyvalueImplementor;
+#[cfg(feature = "kv-keys")]
+use super::cutoff_timestamp;
+use super::{default_capabilities, invalid_key, KeyvalueImplementor};
+
+/// The most bytes a read of a `get_stream` stream downloads at once.
+const STREAM_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The size of the blocks a `set_stream` stream stages (but the last one).
+const STREAM_BLOCK_SIZE: usize = 4 * 1024 * 1024;
 
 /// This is the underlying struct behind the `AzBlob` variant of the `KeyvalueImplementor` enum.
 ///
@@ -16,47 +31,195 @@ use super::KeyvalueImplementor;
 /// of this capability:
 ///     - `container_client`
 ///
+/// Key metadata is kept in the blob's user-defined metadata.
+///
+/// `get_stream` downloads values a range at a time, and `set_stream` uploads
+/// them as the staged blocks of a block blob, which are only committed on close.
+///
 /// As per its' usage in `KeyvalueImplementor`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 pub struct AzBlobImplementor {
     container_client: ContainerClient,
 }
 
-impl AzBlobImplementor {
-    pub async fn new(slight_state: &BasicState, name: &str) -> Self {
-        let storage_account_name = get_from_state("AZURE_STORAGE_ACCOUNT", slight_state)
-            .await
-            .unwrap();
-        let storage_account_key = get_from_state("AZURE_STORAGE_KEY", slight_state)
-            .await
-            .unwrap();
+/// The configs of an azblob store (see `AzBlobImplementor::new`).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct AzBlobConfig {
+    azure_storage_account: String,
+    azure_storage_key: String,
+    azure_storage_endpoint: Option<String>,
+}
 
+impl AzBlobImplementor {
+    /// Creates a new `AzBlobImplementor` instance.
+    ///
+    /// It reads the following configs:
+    ///   - `AZURE_STORAGE_ACCOUNT`,
+    ///   - `AZURE_STORAGE_KEY`, and
+    ///   - `AZURE_STORAGE_ENDPOINT` (optional) — the blob service endpoint to use
+    ///   instead of the public Azure one (e.g., for Azurite, set it to
+    ///   `http://127.0.0.1:10000/devstoreaccount1` along with Azurite's
+    ///   well-known account name and key).
+    pub async fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let config: AzBlobConfig = configs_from_state(slight_state).await?;
+        let storage_account_name = config.azure_storage_account;
         let storage_credentials =
-            StorageCredentials::Key(storage_account_name.clone(), storage_account_key);
-        let service_client = BlobServiceClient::new(storage_account_name, storage_credentials);
+            StorageCredentials::Key(storage_account_name.clone(), config.azure_storage_key);
+        let service_client = match config.azure_storage_endpoint {
+            Some(uri) => {
+                log::info!("Using azblob endpoint: {}", uri);
+                ClientBuilder::with_location(CloudLocation::Custom {
+                    uri,
+                    credentials: storage_credentials,
+                })
+                .blob_service_client()
+            }
+            None => BlobServiceClient::new(storage_account_name, storage_credentials),
+        };
 
         let container_client = service_client.container_client(name);
-        Self { container_client }
+        Ok(Self { container_client })
+    }
+
+    /// Deletes the blob named `name`, succeeding if there is none.
+    async fn delete_blob(&self, name: &str) -> Result<()> {
+        match azure::delete(self.container_client.blob_client(name)).await {
+            Err(e)
+                if e.downcast_ref::<azure_core::Error>()
+                    .map_or(false, azure::is_not_found) =>
+            {
+                Ok(())
+            }
+            res => res.with_context(|| format!("failed to delete key '{name}'")),
+        }
     }
 }
 
 #[async_trait]
 impl KeyvalueImplementor for AzBlobImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        if key.is_empty() || key.chars().count() > 1024 {
+            return Err(invalid_key(
+                key,
+                "blob names must be 1 to 1024 characters long",
+            ));
+        }
+        Ok(())
+    }
+
     async fn get(&self, key: &str) -> Result<Vec<u8>> {
         let blob_client = self.container_client.blob_client(key);
-        let res = azure::get(blob_client)
+        match azure::get(blob_client).await {
+            Err(e) if azure::is_not_found(&e) => {
+                Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+            }
+            res => res.with_context(|| format!("failed to get value for key {key}")),
+        }
+    }
+
+    /// Uses a ranged get, except for empty ranges, which only check that the
+    /// blob exists.
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        if len == 0 {
+            self.get_metadata(key).await?;
+            return Ok(vec![]);
+        }
+        let blob_client = self.container_client.blob_client(key);
+        let range = Range::new(offset, offset.saturating_add(len));
+        match azure::get_range(blob_client, range).await {
+            Err(e) if azure::is_not_found(&e) => {
+                Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+            }
+            // the blob exists, but ends before `offset`
+            Err(e) if azure::is_range_not_satisfiable(&e) => Ok(vec![]),
+            res => res.with_context(|| format!("failed to get value range for key {key}")),
+        }
+    }
+
+    /// Reads the blob's size from its properties, and then downloads it in
+    /// ranges of up to `STREAM_CHUNK_SIZE` bytes as the stream is read.
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        let blob_client = self.container_client.blob_client(key);
+        let len = match azure::get_size(blob_client.clone()).await {
+            Err(e) if azure::is_not_found(&e) => {
+                return Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+            }
+            res => res.with_context(|| format!("failed to get value for key {key}"))?,
+        };
+        Ok(Box::new(AzBlobReadStream {
+            blob_client,
+            key: key.to_string(),
+            offset: 0,
+            len,
+        }))
+    }
+
+    /// Uses the blob's properties (i.e., a `HEAD` request).
+    async fn exists(&self, key: &str) -> Result<bool> {
+        let blob_client = self.container_client.blob_client(key);
+        azure::exists(blob_client)
             .await
-            .with_context(|| format!("failed to get value for key {key}"))?;
-        Ok(res)
+            .with_context(|| format!("failed to check whether key '{key}' exists"))
     }
 
     async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
         let blob_client = self.container_client.blob_client(key);
         let value = Vec::from(value);
-        azure::set(blob_client, value)
-            .await
-            .with_context(|| format!("failed to set value for key '{key}'"))?;
-        Ok(())
+        match azure::set(blob_client, value).await {
+            // Azure doesn't suggest how long to back off for
+            Err(e) if azure::is_throttled(&e) => Err(KeyvalueError::Throttled(None).into()),
+            res => res.with_context(|| format!("failed to set value for key '{key}'")),
+        }
+    }
+
+    async fn set_stream(
+        &self,
+        key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        Ok(Some(Box::new(AzBlobWriteStream {
+            blob_client: self.container_client.blob_client(key),
+            key: key.to_string(),
+            buffer: vec![],
+            block_list: BlockList::default(),
+        })))
+    }
+
+    /// Sets the blob's `Content-Type` and `Content-Encoding` headers along with
+    /// the metadata, so that the blob can be served over HTTP as is (e.g., a
+    /// pre-gzipped value with `Content-Encoding: gzip`).
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        let blob_client = self.container_client.blob_client(key);
+        let metadata = KeyMetadata {
+            content_type: Some(content_type.to_string()),
+            content_encoding: content_encoding.map(String::from),
+            ..Default::default()
+        };
+        let mut blob_metadata = Metadata::new();
+        for (name, value) in metadata.to_pairs() {
+            blob_metadata.insert(name, value);
+        }
+        match azure::set_with_content_type(
+            blob_client,
+            Vec::from(value),
+            content_type.to_string(),
+            content_encoding.map(String::from),
+            blob_metadata,
+        )
+        .await
+        {
+            Err(e) if azure::is_throttled(&e) => Err(KeyvalueError::Throttled(None).into()),
+            res => res.with_context(|| format!("failed to set value for key '{key}'")),
+        }
     }
 
     async fn keys(&self) -> Result<Vec<String>> {
@@ -81,4 +244,210 @@ impl KeyvalueImplementor for AzBlobImplementor {
             .with_context(|| "failed to delete key's value")?;
         Ok(())
     }
+
+    /// Deletes each blob in turn, skipping the ones that don't exist.
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        for key in keys {
+            self.delete_blob(key).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes every blob of the container, which holds this store alone.
+    async fn clear(&self) -> Result<()> {
+        let blobs = azure::list_blobs(self.container_client.clone())
+            .await
+            .with_context(|| "failed to list blobs")?;
+        for blob in blobs {
+            if let BlobItem::Blob(b) = blob {
+                self.delete_blob(&b.name).await?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        let blobs = azure::list_blobs_with_prefix(self.container_client.clone(), prefix)
+            .await
+            .with_context(|| format!("failed to list blobs with prefix '{prefix}'"))?;
+        let mut deleted = 0;
+        for blob in blobs {
+            if let BlobItem::Blob(b) = blob {
+                let blob_client = self.container_client.blob_client(&b.name);
+                azure::delete(blob_client)
+                    .await
+                    .with_context(|| format!("failed to delete key '{}'", b.name))?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Uses the last-modified time of each blob.
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        let blobs = azure::list_blobs(self.container_client.clone())
+            .await
+            .with_context(|| "failed to list blobs")?;
+        let cutoff = cutoff_timestamp(seconds);
+        let keys = blobs
+            .into_iter()
+            .filter_map(|blob| match blob {
+                BlobItem::Blob(b) if b.properties.last_modified.unix_timestamp() < cutoff => {
+                    Some(b.name)
+                }
+                _ => None,
+            })
+            .collect();
+        Ok(keys)
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        default_capabilities() | KeyvalueCapabilities::KEYS_OLDER_THAN
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        let blob_client = self.container_client.blob_client(key);
+        let mut metadata = match azure::get_metadata(blob_client).await {
+            Ok(metadata) => metadata,
+            Err(e) if azure::is_not_found(&e) => {
+                return Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to get metadata for key '{key}'"))
+            }
+        };
+        let pairs = metadata
+            .as_mut()
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), String::from_utf8(value.to_vec())?)))
+            .collect::<Result<Vec<_>>>()?;
+        KeyMetadata::from_pairs(pairs)
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        let blob_client = self.container_client.blob_client(key);
+        let mut blob_metadata = Metadata::new();
+        for (name, value) in metadata.to_pairs() {
+            blob_metadata.insert(name, value);
+        }
+        match azure::set_metadata(blob_client, blob_metadata).await {
+            Err(e) if azure::is_not_found(&e) => {
+                Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+            }
+            res => res.with_context(|| format!("failed to set metadata for key '{key}'")),
+        }
+    }
+}
+
+/// A stream downloading a blob a range at a time, as opened by `get_stream`.
+///
+/// The blob's size is read when the stream is opened, so a blob replaced while
+/// it's being read can be read partly from each value.
+struct AzBlobReadStream {
+    blob_client: BlobClient,
+    key: String,
+    offset: u64,
+    len: u64,
+}
+
+#[async_trait]
+impl KeyvalueReadStreamImplementor for AzBlobReadStream {
+    async fn read(&mut self, size: u64) -> Result<Option<Vec<u8>>> {
+        if self.offset >= self.len {
+            return Ok(None);
+        }
+        let len = size.min(self.len - self.offset).min(STREAM_CHUNK_SIZE);
+        let range = Range::new(self.offset, self.offset + len);
+        let chunk = match azure::get_range(self.blob_client.clone(), range).await {
+            Err(e) if azure::is_not_found(&e) => {
+                return Err(KeyvalueError::KeyNotFound(self.key.clone()).into())
+            }
+            // the blob was replaced by a shorter one since the stream was opened
+            Err(e) if azure::is_range_not_satisfiable(&e) => vec![],
+            res => {
+                res.with_context(|| format!("failed to get value range for key {}", self.key))?
+            }
+        };
+        if chunk.is_empty() {
+            self.offset = self.len;
+            return Ok(None);
+        }
+        self.offset += chunk.len() as u64;
+        Ok(Some(chunk))
+    }
+
+    fn available(&self) -> u64 {
+        self.len.saturating_sub(self.offset)
+    }
+}
+
+/// A stream uploading a block blob, as opened by `set_stream`.
+///
+/// What's written is staged in blocks of `STREAM_BLOCK_SIZE` bytes, and the
+/// blocks are committed as the blob's value on close. The blocks of a stream
+/// that is never closed are left uncommitted, which the service discards after
+/// a week.
+struct AzBlobWriteStream {
+    blob_client: BlobClient,
+    key: String,
+    buffer: Vec<u8>,
+    block_list: BlockList,
+}
+
+impl AzBlobWriteStream {
+    /// Stages `block` as the next block of the blob.
+    async fn stage(&mut self, block: Vec<u8>) -> Result<()> {
+        // block ids must all be as long, and are committed in the order listed
+        let block_id = BlockId::new(format!("{:016x}", self.block_list.blocks.len()));
+        match azure::put_block(self.blob_client.clone(), block_id.clone(), block).await {
+            Err(e) if azure::is_throttled(&e) => return Err(KeyvalueError::Throttled(None).into()),
+            res => res.with_context(|| format!("failed to set value for key '{}'", self.key))?,
+        }
+        self.block_list
+            .blocks
+            .push(BlobBlockType::new_uncommitted(block_id));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl KeyvalueWriteStreamImplementor for AzBlobWriteStream {
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(data);
+        while self.buffer.len() >= STREAM_BLOCK_SIZE {
+            let block = self.buffer.drain(..STREAM_BLOCK_SIZE).collect();
+            self.stage(block).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            let block = std::mem::take(&mut self.buffer);
+            self.stage(block).await?;
+        }
+        let block_list = std::mem::take(&mut self.block_list);
+        match azure::put_block_list(self.blob_client.clone(), block_list).await {
+            Err(e) if azure::is_throttled(&e) => Err(KeyvalueError::Throttled(None).into()),
+            res => res.with_context(|| format!("failed to set value for key '{}'", self.key)),
+        }
+    }
+}
+
+/// Classifies the azure errors returned as is (see `super::classify_error`):
+/// throttling (e.g., while listing blobs), rejected credentials, and failing to
+/// reach the service.
+pub(crate) fn classify_error(e: &anyhow::Error) -> Option<KeyvalueError> {
+    let azure_error = e.downcast_ref::<azure_core::Error>()?;
+    if azure::is_throttled(azure_error) {
+        Some(KeyvalueError::Throttled(None))
+    } else if azure::is_unauthorized(azure_error) {
+        Some(KeyvalueError::AuthenticationError(e.to_string()))
+    } else if azure::is_io_error(azure_error) {
+        Some(KeyvalueError::ConnectionError(e.to_string()))
+    } else {
+        None
+    }
 }
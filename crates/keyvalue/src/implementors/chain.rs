@@ -0,0 +1,203 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::{stream, StreamExt, TryStreamExt};
+use tracing::log;
+
+use crate::keyvalue::ConnectionStatus;
+
+use super::KeyvalueImplementor;
+
+/// How many links a `ChainImplementor` lists the keys of at once when
+/// `CHAIN_CONCURRENCY` isn't set.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Which links of a `ChainImplementor` receive writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainWrite {
+    /// Only the first link (i.e., the highest-precedence one).
+    First,
+    /// Every link.
+    All,
+}
+
+/// This is a wrapper around an ordered list of `KeyvalueImplementor`s (its links),
+/// from highest to lowest precedence, enabled with the `CHAIN` config.
+///
+/// It suits layered data (e.g., local overrides, then shared values, then
+/// defaults):
+///   - `get` answers with the value of the first link that has one,
+///   - `set` writes to the first link, or to all of them (see `ChainWrite`),
+///   - `delete` removes the key from every link, and
+///   - `keys` lists the keys of every link, without duplicates, querying up to
+///   `concurrency` links at once.
+///
+/// Keys must be valid on every link.
+#[derive(Debug, Clone)]
+pub struct ChainImplementor {
+    links: Vec<Arc<dyn KeyvalueImplementor + Send + Sync>>,
+    write: ChainWrite,
+    concurrency: usize,
+}
+
+impl ChainImplementor {
+    /// Creates a chain of `links`, which must not be empty.
+    pub fn new(links: Vec<Arc<dyn KeyvalueImplementor + Send + Sync>>, write: ChainWrite) -> Self {
+        assert!(
+            !links.is_empty(),
+            "a keyvalue chain needs at least one link"
+        );
+        Self {
+            links,
+            write,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Bounds how many links are queried at once by fan-out operations (e.g.,
+    /// `keys`); 1 queries them one after the other.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for ChainImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        for link in &self.links {
+            link.validate_key(key)?;
+        }
+        Ok(())
+    }
+
+    /// A link failing for any reason (e.g., it doesn't have the key, or it is
+    /// unreachable) falls through to the next one. If every link fails, the
+    /// error of the last one is returned.
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let mut last_error = None;
+        for (i, link) in self.links.iter().enumerate() {
+            match link.get(key).await {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    log::debug!("keyvalue chain link {i} has no value for key '{key}': {e}");
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("a keyvalue chain has at least one link"))
+    }
+
+    /// The key exists if any link has it. As with `get`, a failing link falls
+    /// through to the next one, and if every link fails, the error of the last
+    /// one is returned.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        let mut last_error = None;
+        let mut answered = false;
+        for (i, link) in self.links.iter().enumerate() {
+            match link.exists(key).await {
+                Ok(true) => return Ok(true),
+                Ok(false) => answered = true,
+                Err(e) => {
+                    log::debug!("keyvalue chain link {i} failed to check key '{key}': {e}");
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if !answered => Err(e),
+            _ => Ok(false),
+        }
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        match self.write {
+            ChainWrite::First => self.links[0].set(key, value).await,
+            ChainWrite::All => {
+                for link in &self.links {
+                    link.set(key, value).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        // `buffered` yields the listings in link order, so keys keep the order
+        // of the first link holding them
+        let listings: Vec<_> = self.links.iter().map(|link| link.keys()).collect();
+        let listings: Vec<Vec<String>> = stream::iter(listings)
+            .buffered(self.concurrency)
+            .try_collect()
+            .await?;
+        let mut seen = HashSet::new();
+        let mut keys = vec![];
+        for listing in listings {
+            for key in listing {
+                if seen.insert(key.clone()) {
+                    keys.push(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Every link must be healthy, as any of them may be the one answering.
+    async fn health(&self) -> Result<()> {
+        for link in &self.links {
+            link.health().await?;
+        }
+        Ok(())
+    }
+
+    /// Connected if every link is, disconnected if every link is, and
+    /// degraded otherwise (i.e., some links may still answer).
+    fn connection_status(&self) -> ConnectionStatus {
+        let statuses = self
+            .links
+            .iter()
+            .map(|link| link.connection_status())
+            .collect::<Vec<_>>();
+        if statuses.iter().all(|s| *s == ConnectionStatus::Connected) {
+            ConnectionStatus::Connected
+        } else if statuses
+            .iter()
+            .all(|s| *s == ConnectionStatus::Disconnected)
+        {
+            ConnectionStatus::Disconnected
+        } else {
+            ConnectionStatus::Degraded
+        }
+    }
+
+    /// Reconnects every link, even if some fail, and fails with the first error.
+    async fn reconnect(&self) -> Result<()> {
+        let mut first_error = None;
+        for link in &self.links {
+            if let Err(e) = link.reconnect().await {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Succeeds if the key was deleted from at least one link, as most links
+    /// won't hold every key.
+    async fn delete(&self, key: &str) -> Result<()> {
+        let mut first_error = None;
+        let mut deleted = false;
+        for link in &self.links {
+            match link.delete(key).await {
+                Ok(()) => deleted = true,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) if !deleted => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
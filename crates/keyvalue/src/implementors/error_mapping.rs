@@ -0,0 +1,273 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, KeyvalueError, SetOutcome, VersionedValue},
+    metadata::KeyMetadata,
+    stream::{KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
+
+use super::{KeysPage, KeyvalueImplementor};
+#[cfg(feature = "kv-stream")]
+use crate::{
+    keyvalue::ChangeKind,
+    scan::{KeyvalueScanImplementor, Pair},
+    watch::KeyvalueWatchImplementor,
+};
+
+/// Maps the errors of a store's implementor to the `KeyvalueError` guests see,
+/// as registered with `Keyvalue::with_error_mapper`.
+pub type ErrorMapper = Arc<dyn Fn(&anyhow::Error) -> KeyvalueError + Send + Sync>;
+
+/// This is a wrapper around any `KeyvalueImplementor` that maps every error it
+/// returns with an `ErrorMapper`, instead of the built-in mapping (see
+/// `crate::default_error_mapping`).
+///
+/// It wraps a store's implementor last, so that the errors of every other
+/// wrapper (e.g., `allow_list::AllowListImplementor`) are mapped too.
+#[derive(Clone)]
+pub struct ErrorMappingImplementor {
+    inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    mapper: ErrorMapper,
+}
+
+impl std::fmt::Debug for ErrorMappingImplementor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ErrorMappingImplementor")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ErrorMappingImplementor {
+    pub fn new(inner: Arc<dyn KeyvalueImplementor + Send + Sync>, mapper: ErrorMapper) -> Self {
+        Self { inner, mapper }
+    }
+
+    fn map<T>(&self, res: Result<T>) -> Result<T> {
+        res.map_err(|e| (self.mapper)(&e).into())
+    }
+}
+
+/// Maps the errors of a scan, like `ErrorMappingImplementor` does for the
+/// store the scan reads.
+#[cfg(feature = "kv-stream")]
+struct ErrorMappingScan {
+    inner: Box<dyn KeyvalueScanImplementor + Send + Sync>,
+    mapper: ErrorMapper,
+}
+
+#[cfg(feature = "kv-stream")]
+#[async_trait]
+impl KeyvalueScanImplementor for ErrorMappingScan {
+    async fn next_batch(&mut self) -> Result<Option<Vec<Pair>>> {
+        let mapper = &self.mapper;
+        self.inner.next_batch().await.map_err(|e| mapper(&e).into())
+    }
+}
+
+/// Maps the errors of a watch, like `ErrorMappingScan` does for scans.
+#[cfg(feature = "kv-stream")]
+struct ErrorMappingWatch {
+    inner: Box<dyn KeyvalueWatchImplementor + Send + Sync>,
+    mapper: ErrorMapper,
+}
+
+#[cfg(feature = "kv-stream")]
+#[async_trait]
+impl KeyvalueWatchImplementor for ErrorMappingWatch {
+    async fn next_change(&mut self) -> Result<ChangeKind> {
+        let mapper = &self.mapper;
+        self.inner
+            .next_change()
+            .await
+            .map_err(|e| mapper(&e).into())
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for ErrorMappingImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        self.map(self.inner.validate_key(key))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.map(self.inner.get(key).await)
+    }
+
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        self.map(self.inner.get_consistent(key, strong).await)
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.map(self.inner.get_range(key, offset, len).await)
+    }
+
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        self.map(self.inner.get_stream(key).await)
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.map(self.inner.exists(key).await)
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.map(self.inner.set(key, value).await)
+    }
+
+    async fn set_stream(
+        &self,
+        key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        self.map(self.inner.set_stream(key).await)
+    }
+
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        self.map(self.inner.set_reporting(key, value).await)
+    }
+
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        self.map(
+            self.inner
+                .set_with_content_type(key, value, content_type, content_encoding)
+                .await,
+        )
+    }
+
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.map(self.inner.set_with_expiry(key, value, expiry).await)
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        self.map(self.inner.keys().await)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.map(self.inner.delete(key).await)
+    }
+
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        self.map(self.inner.list_keys(prefix, cursor, limit).await)
+    }
+
+    async fn get_bulk(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.map(self.inner.get_bulk(keys).await)
+    }
+
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        self.map(self.inner.set_bulk(pairs).await)
+    }
+
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        self.map(self.inner.delete_bulk(keys).await)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.map(self.inner.clear().await)
+    }
+
+    async fn replace_all(&self, entries: &[(&str, &[u8])]) -> Result<()> {
+        self.map(self.inner.replace_all(entries).await)
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.map(self.inner.health().await)
+    }
+
+    fn connection_status(&self) -> ConnectionStatus {
+        self.inner.connection_status()
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.map(self.inner.reconnect().await)
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        self.map(self.inner.delete_prefix(prefix).await)
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix_page(&self, prefix: &str, limit: usize) -> Result<u64> {
+        self.map(self.inner.delete_prefix_page(prefix, limit).await)
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        self.map(self.inner.keys_older_than(seconds).await)
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        self.map(self.inner.keys_by_index(value).await)
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        let inner = self.map(self.inner.scan().await)?;
+        Ok(Box::new(ErrorMappingScan {
+            inner,
+            mapper: self.mapper.clone(),
+        }))
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        let inner = self.map(self.inner.watch(key).await)?;
+        Ok(Box::new(ErrorMappingWatch {
+            inner,
+            mapper: self.mapper.clone(),
+        }))
+    }
+
+    async fn undelete(&self, key: &str) -> Result<()> {
+        self.map(self.inner.undelete(key).await)
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        self.map(self.inner.lock(key, ttl).await)
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        self.map(self.inner.unlock(key, token).await)
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.map(self.inner.get_metadata(key).await)
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.map(self.inner.set_metadata(key, metadata).await)
+    }
+
+    async fn get_versioned(&self, key: &str) -> Result<VersionedValue> {
+        self.map(self.inner.get_versioned(key).await)
+    }
+
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        self.map(self.inner.set_if_version(key, value, expected).await)
+    }
+
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        self.map(self.inner.cas(key, expected, new).await)
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        self.map(self.inner.increment(key, delta).await)
+    }
+}
@@ -1,63 +1,647 @@
 use std::{
     env,
     fs::{self, File},
-    io::{Read, Write},
-    path::PathBuf,
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use fs2::FileExt;
+use serde::Deserialize;
 use slight_common::BasicState;
+use slight_runtime_configs::configs_from_state;
+use tracing::log;
 
-use super::KeyvalueImplementor;
+use crate::{
+    keyvalue::{KeyvalueCapabilities, KeyvalueError, SetOutcome},
+    metadata::KeyMetadata,
+    stream::{KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
+
+use super::{
+    add_to_counter, default_capabilities, expiry_timestamp, has_expired, invalid_key,
+    is_key_not_found, parse_counter, KeysPage, KeyvalueImplementor,
+};
+#[cfg(feature = "kv-stream")]
+use crate::{
+    scan::{KeyvalueScanImplementor, Pair},
+    watch::{self, KeyState, KeyvalueWatchImplementor},
+};
+
+/// How many directory entries `scan` reads at a time.
+#[cfg(feature = "kv-stream")]
+const SCAN_BATCH_SIZE: usize = 100;
+
+/// The most bytes a read of a `get_stream` stream answers with at once.
+const STREAM_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// How often `watch` polls a key by default.
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The prefix of the files slight keeps in a store's base directory besides
+/// values, which keys can't start with.
+const RESERVED_PREFIX: &str = ".slight-";
+
+/// The file of a store's base directory that holds the version of its layout.
+const LAYOUT_VERSION_FILE: &str = ".slight-layout-version";
+
+/// The prefix of the temporary files values and metadata are written to before
+/// they are renamed over the key's file.
+const TEMP_FILE_PREFIX: &str = ".slight-tmp-";
+
+/// The version of the layout this implementor reads and writes.
+///
+/// Stores without a `LAYOUT_VERSION_FILE` are at version 0, which is the
+/// layout of version 1 (a file per value in the base directory, and metadata
+/// sidecars under `<base>.metadata`) before it was versioned.
+pub const LAYOUT_VERSION: u32 = 1;
+
+/// The steps upgrading a store from each layout version to the next, i.e.,
+/// `MIGRATIONS[v]` upgrades a store at version `v` to version `v + 1`.
+const MIGRATIONS: [(&str, fn(&FilesystemImplementor) -> Result<()>); LAYOUT_VERSION as usize] =
+    [("record the layout version", |_| Ok(()))];
 
 /// This is the underlying struct behind the `Filesystem` variant of the `KeyvalueImplementor` enum.
 ///
-/// It provides two properties that pertain solely to the filesystem implementation of
+/// It provides four properties that pertain solely to the filesystem implementation of
 /// of this capability:
-///     - `base`
+///     - `base`,
+///     - `fsync`,
+///     - `wal`, and
+///     - `watch_interval`
+///
+/// Key metadata is kept in sidecar files of the same name under the sibling
+/// `<base>.metadata` directory, including when keys set with `set_with_expiry`
+/// expire. Expired values are hidden from every read, but stay on disk until
+/// their key is set or deleted again.
+///
+/// Values and metadata are written to a temporary file in the same directory,
+/// which is then renamed over the key's file, so readers see either the old or
+/// the new value, and never a partial one. A crash mid-write can leave a
+/// temporary file behind, which is skipped like any other reserved file.
+///
+/// `get_stream` reads values from their file through a buffer, and `set_stream`
+/// writes them to a temporary file that is renamed over the key's file on close,
+/// so large values are never held in memory whole. With `wal`, values written
+/// with `set_stream` are buffered instead, since they are journaled whole.
 ///
 /// As per its' usage in `KeyvalueImplementor`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 pub struct FilesystemImplementor {
     /// The base path for where the key-value store can be found in your file-system
     pub base: String,
+    /// Whether `set` flushes the value (and the directory entry) to disk before returning
+    pub fsync: bool,
+    /// Whether `set` and `delete` are journaled to the write-ahead log under `<base>.wal`
+    pub wal: bool,
+    /// How often `watch` polls the watched key for changes
+    pub watch_interval: Duration,
+}
+
+/// The configs of a filesystem store (see `FilesystemImplementor::new`).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct FilesystemConfig {
+    #[serde(default)]
+    fsync: bool,
+    #[serde(default)]
+    wal: bool,
+    watch_interval_ms: Option<u64>,
 }
 
 impl FilesystemImplementor {
-    pub async fn new(_slight_state: &BasicState, name: &str) -> Self {
-        Self {
-            base: env::temp_dir().join(name).to_str().unwrap().to_owned(),
+    /// Creates a new `FilesystemImplementor` instance.
+    ///
+    /// It reads the following optional config:
+    ///   - `FSYNC` — when `"true"`, `set` calls `File::sync_all` on the value's file
+    ///   (and its metadata sidecar, if any) and syncs the directories holding them
+    ///   before returning. Without it (the default),
+    ///   a successful `set` only means the value reached the OS's buffers, so a
+    ///   power loss shortly after can still lose the write. Enabling it trades
+    ///   write throughput for durability.
+    ///   - `WAL` — when `"true"`, every `set`, `increment` and `delete` first records its intent
+    ///   in a write-ahead log (one synced record file per mutation under the sibling
+    ///   `<base>.wal` directory), then applies it, and finally removes the record.
+    ///   A `set_bulk` records all of its keys in a single record. Records left behind
+    ///   by a crash are replayed the first time the store is opened by a process, so
+    ///   an interrupted mutation (or a whole `set_bulk`) is eventually fully applied
+    ///   or never applied at all. Each mutation costs an extra synced write, so it is
+    ///   off by default.
+    ///   - `WATCH_INTERVAL_MS` — how often, in milliseconds, `watch` checks the
+    ///   watched key for changes (defaults to 1000). The filesystem can't notify
+    ///   slight of changes, so shorter intervals report them sooner at the cost
+    ///   of more reads.
+    ///
+    /// Stores written with an older layout (see `LAYOUT_VERSION`) are upgraded
+    /// in place when opened, and stores written with a newer one fail to open
+    /// with `KeyvalueError::UnsupportedFormat`, rather than being misread.
+    pub async fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let config: FilesystemConfig = configs_from_state(slight_state).await?;
+        let implementor = Self {
+            base: env::temp_dir()
+                .join(name)
+                .to_str()
+                .with_context(|| format!("the path of the store '{name}' isn't valid UTF-8"))?
+                .to_owned(),
+            fsync: config.fsync,
+            wal: config.wal,
+            watch_interval: config
+                .watch_interval_ms
+                .map_or(DEFAULT_WATCH_INTERVAL, Duration::from_millis),
+        };
+        implementor.migrate()?;
+        if config.wal {
+            implementor.replay_wal()?;
         }
+        Ok(implementor)
     }
-}
 
-#[async_trait]
-impl KeyvalueImplementor for FilesystemImplementor {
-    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+    /// Upgrades the store's layout to `LAYOUT_VERSION`, recording the version
+    /// reached after each step so that an interrupted upgrade resumes from there.
+    fn migrate(&self) -> Result<()> {
         fs::create_dir_all(&self.base)
             .with_context(|| "failed to create base directory for keyvalue instance")?;
+        let path = PathBuf::from(&self.base).join(LAYOUT_VERSION_FILE);
+        let mut version = match fs::read_to_string(&path) {
+            Ok(version) => version
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid layout version in '{}'", path.display()))?,
+            // a new store starts at the current layout
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let empty = fs::read_dir(&self.base)
+                    .with_context(|| "failed to read base directory")?
+                    .next()
+                    .is_none();
+                if empty {
+                    return self.write_layout_version(LAYOUT_VERSION);
+                }
+                0
+            }
+            Err(e) => return Err(e).with_context(|| "failed to read store's layout version"),
+        };
+        if version > LAYOUT_VERSION {
+            return Err(KeyvalueError::UnsupportedFormat(format!(
+                "keyvalue store '{}' has layout version {version}, but this version of slight only reads up to {LAYOUT_VERSION}",
+                self.base
+            ))
+            .into());
+        }
+        while version < LAYOUT_VERSION {
+            let (description, migration) = MIGRATIONS[version as usize];
+            log::info!(
+                "migrating keyvalue store '{}' from layout version {version} to {}: {description}",
+                self.base,
+                version + 1
+            );
+            migration(self)?;
+            version += 1;
+            self.write_layout_version(version)?;
+        }
+        Ok(())
+    }
+
+    fn write_layout_version(&self, version: u32) -> Result<()> {
+        let path = PathBuf::from(&self.base).join(LAYOUT_VERSION_FILE);
         let mut file =
-            File::open(PathBuf::from(&self.base).join(key)).with_context(|| "failed to get key")?;
+            File::create(path).with_context(|| "failed to create store's layout version")?;
+        file.write_all(version.to_string().as_bytes())
+            .and_then(|_| file.sync_all())
+            .with_context(|| "failed to write store's layout version")
+    }
 
+    /// Flushes the store's directory so newly created entries survive a crash.
+    ///
+    /// Directories can't be opened for syncing on Windows, where the metadata
+    /// is already flushed along with the file.
+    fn sync_base_dir(&self) -> Result<()> {
+        #[cfg(unix)]
+        File::open(&self.base)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| "failed to sync base directory for keyvalue instance")?;
+        Ok(())
+    }
+
+    /// Flushes the directory of the metadata sidecar at `path`, as
+    /// `sync_base_dir` does for values, so that the sidecar written (or removed)
+    /// along with a value doesn't revert to its former state after a crash.
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn sync_metadata_dir(&self, path: &Path) -> Result<()> {
+        #[cfg(unix)]
+        if let Some(dir) = path.parent() {
+            File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .with_context(|| "failed to sync metadata directory for keyvalue instance")?;
+        }
+        Ok(())
+    }
+
+    fn metadata_path(&self, key: &str) -> PathBuf {
+        PathBuf::from(format!("{}.metadata", self.base)).join(key)
+    }
+
+    /// Writes `contents` to `path` by renaming a temporary file of the same
+    /// directory over it, which is atomic on the same filesystem, syncing the
+    /// file first if `fsync` is set. `what` names the contents in errors.
+    fn write_atomically(&self, path: &Path, contents: &[u8], what: &str) -> Result<()> {
+        let temp_path = temp_path(path);
+        let res = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(contents)?;
+                if self.fsync {
+                    file.sync_all()?;
+                }
+                Ok(())
+            })
+            .and_then(|_| fs::rename(&temp_path, path));
+        if res.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        res.with_context(|| format!("failed to write {what}"))
+    }
+
+    fn remove_metadata(&self, key: &str) -> Result<()> {
+        let path = self.metadata_path(key);
+        match fs::remove_file(&path) {
+            Ok(()) if self.fsync => self.sync_metadata_dir(&path),
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).with_context(|| "failed to delete key's metadata")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn write_value(&self, key: &str, value: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for keyvalue instance")?;
+
+        self.write_atomically(&PathBuf::from(&self.base).join(key), value, "key's value")?;
+
+        if self.fsync {
+            self.sync_base_dir()?;
+        }
+        self.remove_metadata(key)
+    }
+
+    /// Writes `value` over the current value of `key`, keeping its metadata.
+    fn update_value(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.write_atomically(&PathBuf::from(&self.base).join(key), value, "key's value")?;
+        if self.fsync {
+            self.sync_base_dir()?;
+        }
+        Ok(())
+    }
+
+    /// Writes `value` along with the metadata recording when it expires.
+    fn write_expiring_value(&self, key: &str, value: &[u8], expires_at: i64) -> Result<()> {
+        self.write_value(key, value)?;
+        let metadata = KeyMetadata {
+            expires_at: Some(expires_at),
+            ..Default::default()
+        };
+        self.write_metadata(key, &metadata)
+    }
+
+    fn write_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        let path = self.metadata_path(key);
+        fs::create_dir_all(path.parent().unwrap())
+            .with_context(|| "failed to create metadata directory for keyvalue instance")?;
+
+        self.write_atomically(&path, &metadata.encode(), "key's metadata")?;
+        if self.fsync {
+            self.sync_metadata_dir(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Whether `key` was set with an expiry that has passed, according to its
+    /// metadata.
+    fn is_expired(&self, key: &str) -> Result<bool> {
+        match fs::read(self.metadata_path(key)) {
+            Ok(buf) => Ok(KeyMetadata::decode(&buf)?
+                .expires_at
+                .map_or(false, has_expired)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e).with_context(|| "failed to read key's metadata"),
+        }
+    }
+
+    /// The state of `key`, as polled by `watch`.
+    #[cfg(feature = "kv-stream")]
+    fn key_state(&self, key: &str) -> Result<KeyState> {
+        let metadata = match fs::metadata(PathBuf::from(&self.base).join(key)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(KeyState::Missing),
+            Err(e) => return Err(e).with_context(|| "failed to read key's file metadata"),
+        };
+        if self.is_expired(key)? {
+            return Ok(KeyState::Expired);
+        }
+        let modified = metadata
+            .modified()
+            .with_context(|| "failed to read key's modification time")?;
+        Ok(KeyState::Present(modified, metadata.len()))
+    }
+
+    /// Removes the value of `key`, if it has one.
+    fn remove_value(&self, key: &str) -> Result<()> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for keyvalue instance")?;
+        match fs::remove_file(PathBuf::from(&self.base).join(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).with_context(|| "failed to delete key's value")
+            }
+            _ => {}
+        }
+        self.remove_metadata(key)
+    }
+
+    /// Opens (creating it if needed) the file `cas` and `increment` lock for
+    /// `key`, under the sibling `<base>.cas` directory.
+    fn cas_lock_file(&self, key: &str) -> Result<File> {
+        let dir = PathBuf::from(format!("{}.cas", self.base));
+        fs::create_dir_all(&dir).with_context(|| "failed to create cas lock directory")?;
+        fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(dir.join(key))
+            .with_context(|| "failed to open key's cas lock file")
+    }
+
+    fn wal_dir(&self) -> PathBuf {
+        PathBuf::from(format!("{}.wal", self.base))
+    }
+
+    /// Runs `apply`, journaling `op` around it if the WAL is enabled.
+    fn journaled(&self, op: WalOp, apply: impl FnOnce() -> Result<()>) -> Result<()> {
+        if !self.wal {
+            return apply();
+        }
+        let record = WalRecord::write(&self.wal_dir(), &op)?;
+        apply()?;
+        record.done()
+    }
+
+    /// Re-applies the WAL records left behind by a crash, oldest first.
+    ///
+    /// This only happens the first time a process opens the store, as later
+    /// opens could otherwise replay mutations that are still in flight.
+    fn replay_wal(&self) -> Result<()> {
+        static REPLAYED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+        let wal_dir = self.wal_dir();
+        {
+            let mut replayed = REPLAYED.lock().unwrap();
+            if replayed.contains(&wal_dir) {
+                return Ok(());
+            }
+            replayed.push(wal_dir.clone());
+        }
+
+        let mut records = match fs::read_dir(&wal_dir) {
+            Ok(entries) => entries
+                .map(|entry| Ok(entry?.path()))
+                .collect::<std::io::Result<Vec<_>>>()
+                .with_context(|| "failed to read WAL directory")?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| "failed to read WAL directory"),
+        };
+        records.sort();
+        for path in records {
+            // a record that wasn't fully written yet, whose mutation wasn't
+            // applied either
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                fs::remove_file(&path).with_context(|| "failed to remove torn WAL record")?;
+                continue;
+            }
+            log::info!("replaying keyvalue WAL record {}", path.display());
+            match WalOp::decode(&fs::read(&path)?)? {
+                WalOp::Set(key, value) => self.write_value(&key, &value)?,
+                WalOp::SetBatch(pairs) => {
+                    for (key, value) in pairs {
+                        self.write_value(&key, &value)?;
+                    }
+                }
+                WalOp::Update(key, value) => self.update_value(&key, &value)?,
+                WalOp::SetWithExpiry(key, value, expires_at) => {
+                    self.write_expiring_value(&key, &value, expires_at)?
+                }
+                // the delete may have been applied before the crash, which
+                // `remove_value` tolerates
+                WalOp::Delete(key) => self.remove_value(&key)?,
+            }
+            fs::remove_file(&path).with_context(|| "failed to remove replayed WAL record")?;
+        }
+        Ok(())
+    }
+
+    fn open_value(&self, key: &str) -> Result<File> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for keyvalue instance")?;
+        let file = match File::open(PathBuf::from(&self.base).join(key)) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+            }
+            res => res.with_context(|| "failed to get key")?,
+        };
+        if self.is_expired(key)? {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        Ok(file)
+    }
+
+    /// Renames the temporary file at `temp_path`, holding a value written by
+    /// a `FilesystemWriteStream`, over the file of `key`.
+    fn commit_value(&self, key: &str, temp_path: &Path) -> Result<()> {
+        fs::rename(temp_path, PathBuf::from(&self.base).join(key))
+            .with_context(|| "failed to write key's value")?;
+        if self.fsync {
+            self.sync_base_dir()?;
+        }
+        self.remove_metadata(key)
+    }
+
+    fn ensure_exists(&self, key: &str) -> Result<()> {
+        if !PathBuf::from(&self.base).join(key).is_file() || self.is_expired(key)? {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for FilesystemImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        if key.is_empty() || key == "." || key == ".." {
+            return Err(invalid_key(key, "must be a valid file name"));
+        }
+        if key.contains(['/', '\\', '\0']) {
+            return Err(invalid_key(key, "must not contain '/', '\\' or NUL"));
+        }
+        if key.starts_with(RESERVED_PREFIX) {
+            return Err(invalid_key(
+                key,
+                "keys starting with '.slight-' are reserved",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let mut file = self.open_value(key)?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)
             .with_context(|| "failed to read key's value")?;
         Ok(buf)
     }
 
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut file = self.open_value(key)?;
+        // seeking past the end is allowed, and reads nothing from there
+        file.seek(SeekFrom::Start(offset))
+            .with_context(|| "failed to seek in key's value")?;
+        let mut buf = Vec::new();
+        file.take(len)
+            .read_to_end(&mut buf)
+            .with_context(|| "failed to read key's value")?;
+        Ok(buf)
+    }
+
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        let file = self.open_value(key)?;
+        let remaining = file
+            .metadata()
+            .with_context(|| "failed to read key's value")?
+            .len();
+        Ok(Box::new(FilesystemReadStream {
+            file: BufReader::new(file),
+            remaining,
+        }))
+    }
+
+    /// Expired keys don't exist.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        let exists = PathBuf::from(&self.base)
+            .join(key)
+            .try_exists()
+            .with_context(|| "failed to check whether key's value exists")?;
+        Ok(exists && !self.is_expired(key)?)
+    }
+
     async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.journaled(WalOp::Set(key.to_owned(), value.to_vec()), || {
+            self.write_value(key, value)
+        })
+    }
+
+    /// Journals every pair in a single WAL record, so that a crash part way
+    /// through is replayed as the whole batch.
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        let batch = pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_vec()))
+            .collect();
+        self.journaled(WalOp::SetBatch(batch), || {
+            pairs
+                .iter()
+                .try_for_each(|(key, value)| self.write_value(key, value))
+        })
+    }
+
+    async fn set_stream(
+        &self,
+        key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        if self.wal {
+            return Ok(None);
+        }
         fs::create_dir_all(&self.base)
             .with_context(|| "failed to create base directory for keyvalue instance")?;
+        let temp_path = temp_path(&PathBuf::from(&self.base).join(key));
+        let file = File::create(&temp_path).with_context(|| "failed to write key's value")?;
+        Ok(Some(Box::new(FilesystemWriteStream {
+            implementor: self.clone(),
+            key: key.to_string(),
+            temp_path,
+            file: Some(BufWriter::new(file)),
+        })))
+    }
 
-        let mut file = File::create(PathBuf::from(&self.base).join(key))
-            .with_context(|| "failed to create key")?;
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        let existed_before = self.ensure_exists(key).is_ok();
+        self.set(key, value).await?;
+        Ok(SetOutcome {
+            existed_before,
+            bytes_written: value.len() as u64,
+        })
+    }
 
-        file.write_all(value)
-            .with_context(|| "failed to set key's value")?;
-        Ok(())
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        let expires_at = expiry_timestamp(expiry);
+        self.journaled(
+            WalOp::SetWithExpiry(key.to_owned(), value.to_vec(), expires_at),
+            || self.write_expiring_value(key, value, expires_at),
+        )
+    }
+
+    /// Holds an exclusive file lock on the key's file under `<base>.cas` while
+    /// it reads and writes the value, which serializes `cas` calls across
+    /// processes. Plain `set` and `delete` don't take the lock, so they can
+    /// still interleave with a `cas`. Lock files are never removed.
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        // the lock is released when `lock` is dropped (closing the file)
+        let lock = self.cas_lock_file(key)?;
+        lock.lock_exclusive()
+            .with_context(|| "failed to lock key's cas lock file")?;
+        let current = match self.get(key).await {
+            Ok(value) => Some(value),
+            Err(e) if is_key_not_found(&e) => None,
+            Err(e) => return Err(e),
+        };
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        self.set(key, new).await?;
+        Ok(true)
+    }
+
+    /// Holds the same lock as `cas` while it reads and writes the value, so
+    /// increments don't race with each other or with `cas` calls.
+    ///
+    /// A live key's value is replaced on its own, keeping its metadata, while
+    /// a missing or expired key is `set`, which clears any expiry left behind.
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        // the lock is released when `lock` is dropped (closing the file)
+        let lock = self.cas_lock_file(key)?;
+        lock.lock_exclusive()
+            .with_context(|| "failed to lock key's cas lock file")?;
+        let current = match self.get(key).await {
+            Ok(value) => Some(parse_counter(key, &value)?),
+            Err(e) if is_key_not_found(&e) => None,
+            Err(e) => return Err(e),
+        };
+        let value = add_to_counter(key, current.unwrap_or_default(), delta)?;
+        let contents = value.to_string().into_bytes();
+        if current.is_none() {
+            self.set(key, &contents).await?;
+            return Ok(value);
+        }
+        self.journaled(WalOp::Update(key.to_owned(), contents.clone()), || {
+            self.update_value(key, &contents)
+        })?;
+        Ok(value)
     }
 
+    /// Expired keys are excluded.
     async fn keys(&self) -> Result<Vec<String>> {
         fs::create_dir_all(&self.base)
             .with_context(|| "failed to create base directory for keyvalue instance")?;
@@ -65,16 +649,479 @@ impl KeyvalueImplementor for FilesystemImplementor {
         let mut keys = Vec::new();
         for entry in fs::read_dir(&self.base).with_context(|| "failed to read base directory")? {
             let entry = entry.with_context(|| "failed to read base directory entry")?;
-            keys.push(entry.file_name().to_str().unwrap().to_owned());
+            let key = entry.file_name().to_str().unwrap().to_owned();
+            if !key.starts_with(RESERVED_PREFIX) && !self.is_expired(&key)? {
+                keys.push(key);
+            }
         }
         Ok(keys)
     }
 
+    /// Sorts the names in the base directory, and uses the last name of a page
+    /// as its cursor. Expired keys are excluded.
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for keyvalue instance")?;
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.base).with_context(|| "failed to read base directory")? {
+            let entry = entry.with_context(|| "failed to read base directory entry")?;
+            let name = entry.file_name().to_str().unwrap().to_owned();
+            if name.starts_with(prefix)
+                && !name.starts_with(RESERVED_PREFIX)
+                && cursor.map_or(true, |cursor| name.as_str() > cursor)
+            {
+                names.push(name);
+            }
+        }
+        names.sort();
+
+        let mut keys = Vec::new();
+        for name in names {
+            if self.is_expired(&name)? {
+                continue;
+            }
+            if keys.len() == limit as usize {
+                let cursor = keys.last().cloned();
+                return Ok((keys, cursor));
+            }
+            keys.push(name);
+        }
+        Ok((keys, None))
+    }
+
+    /// Deleting a key that doesn't exist succeeds.
     async fn delete(&self, key: &str) -> Result<()> {
+        self.journaled(WalOp::Delete(key.to_owned()), || self.remove_value(key))
+    }
+
+    /// Deletes every file of the base directory but the reserved ones (see
+    /// `RESERVED_PREFIX`), expired values included, along with their metadata.
+    /// Each file is deleted as `delete` does, so with `WAL` set, a crash
+    /// leaves every key either fully deleted or untouched.
+    async fn clear(&self) -> Result<()> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for keyvalue instance")?;
+
+        for entry in fs::read_dir(&self.base).with_context(|| "failed to read base directory")? {
+            let entry = entry.with_context(|| "failed to read base directory entry")?;
+            let key = entry.file_name().to_str().unwrap().to_owned();
+            if !key.starts_with(RESERVED_PREFIX) {
+                self.delete(&key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Uses the mtime of each key's file. Expired keys are excluded.
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
         fs::create_dir_all(&self.base)
             .with_context(|| "failed to create base directory for keyvalue instance")?;
-        fs::remove_file(PathBuf::from(&self.base).join(key))
-            .with_context(|| "failed to delete key's value")?;
+
+        let cutoff = SystemTime::now() - Duration::from_secs(seconds);
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.base).with_context(|| "failed to read base directory")? {
+            let entry = entry.with_context(|| "failed to read base directory entry")?;
+            let modified = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .with_context(|| "failed to read key's modification time")?;
+            let key = entry.file_name().to_str().unwrap().to_owned();
+            if modified < cutoff && !key.starts_with(RESERVED_PREFIX) && !self.is_expired(&key)? {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Iterates over the base directory, reading the file of each entry.
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for keyvalue instance")?;
+
+        let entries = fs::read_dir(&self.base).with_context(|| "failed to read base directory")?;
+        Ok(Box::new(FilesystemScan {
+            implementor: self.clone(),
+            entries,
+        }))
+    }
+
+    /// Polls the key's file every `watch_interval` (see `watch::poll`), as the
+    /// filesystem can't notify its changes. Writes are told apart by the
+    /// modification time and length of the file.
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        let implementor = self.clone();
+        let key = key.to_owned();
+        watch::poll(self.watch_interval, move || implementor.key_state(&key))
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        default_capabilities()
+            | KeyvalueCapabilities::KEYS_OLDER_THAN
+            | KeyvalueCapabilities::SCAN
+            | KeyvalueCapabilities::SET_WITH_EXPIRY
+            | KeyvalueCapabilities::COMPARE_AND_SWAP
+            | KeyvalueCapabilities::WATCH
+            | KeyvalueCapabilities::INCREMENT
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.ensure_exists(key)?;
+        match fs::read(self.metadata_path(key)) {
+            Ok(buf) => KeyMetadata::decode(&buf),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(KeyMetadata::default()),
+            Err(e) => Err(e).with_context(|| "failed to read key's metadata"),
+        }
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.ensure_exists(key)?;
+        self.write_metadata(key, metadata)
+    }
+}
+
+/// A scan over the files of a `FilesystemImplementor`'s base directory.
+#[cfg(feature = "kv-stream")]
+struct FilesystemScan {
+    implementor: FilesystemImplementor,
+    entries: fs::ReadDir,
+}
+
+#[cfg(feature = "kv-stream")]
+#[async_trait]
+impl KeyvalueScanImplementor for FilesystemScan {
+    async fn next_batch(&mut self) -> Result<Option<Vec<Pair>>> {
+        let mut read = 0;
+        let mut pairs = vec![];
+        for entry in self.entries.by_ref().take(SCAN_BATCH_SIZE) {
+            read += 1;
+            let entry = entry.with_context(|| "failed to read base directory entry")?;
+            let key = entry.file_name().to_str().unwrap().to_owned();
+            if key.starts_with(RESERVED_PREFIX) {
+                continue;
+            }
+            match self.implementor.get(&key).await {
+                Ok(value) => pairs.push((key, value)),
+                // keys deleted since the directory was opened are skipped
+                Err(e) if is_key_not_found(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((read > 0).then_some(pairs))
+    }
+}
+
+/// A temporary file of the same directory as `path`, to write its new contents
+/// to before renaming it over `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+    path.with_file_name(format!(
+        "{TEMP_FILE_PREFIX}{}-{}",
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// A stream reading the file of a key, as opened by `get_stream`.
+struct FilesystemReadStream {
+    file: BufReader<File>,
+    remaining: u64,
+}
+
+#[async_trait]
+impl KeyvalueReadStreamImplementor for FilesystemReadStream {
+    async fn read(&mut self, size: u64) -> Result<Option<Vec<u8>>> {
+        let len = size.min(self.remaining).min(STREAM_CHUNK_SIZE);
+        let mut buf = vec![0; len as usize];
+        let read = self
+            .file
+            .read(&mut buf)
+            .with_context(|| "failed to read key's value")?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buf.truncate(read);
+        self.remaining -= read as u64;
+        Ok(Some(buf))
+    }
+
+    fn available(&self) -> u64 {
+        self.remaining
+    }
+}
+
+/// A stream writing the value of a key to a temporary file, which is renamed
+/// over the key's file on close, as opened by `set_stream`.
+///
+/// The temporary file is removed if the stream is dropped (or fails to close)
+/// before that.
+struct FilesystemWriteStream {
+    implementor: FilesystemImplementor,
+    key: String,
+    temp_path: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+#[async_trait]
+impl KeyvalueWriteStreamImplementor for FilesystemWriteStream {
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        let file = self.file.as_mut().with_context(|| "the stream is closed")?;
+        file.write_all(data)
+            .with_context(|| "failed to write key's value")
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        let file = self.file.take().with_context(|| "the stream is closed")?;
+        let res = file
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|file| {
+                if self.implementor.fsync {
+                    file.sync_all()?;
+                }
+                Ok(())
+            })
+            .with_context(|| "failed to write key's value")
+            .and_then(|_| self.implementor.commit_value(&self.key, &self.temp_path));
+        if res.is_err() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+        res
+    }
+}
+
+impl Drop for FilesystemWriteStream {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// A mutation journaled to the WAL.
+///
+/// Records are encoded as `[op: u8][key length: u32 BE][key]`, followed by
+/// `[value length: u32 BE][value]` for `Set`, `SetWithExpiry` and `Update`, and then by
+/// `[expires at: i64 BE]` for `SetWithExpiry`. A `SetBatch` is encoded as
+/// `[op: u8][pair count: u32 BE]`, followed by the key and value of each pair,
+/// as in a `Set`.
+#[derive(Debug, PartialEq)]
+enum WalOp {
+    Set(String, Vec<u8>),
+    /// A `set_bulk`, whose pairs are replayed together.
+    SetBatch(Vec<(String, Vec<u8>)>),
+    Delete(String),
+    /// A `set_with_expiry`, with when the value expires.
+    SetWithExpiry(String, Vec<u8>, i64),
+    /// A write that keeps the key's metadata (i.e., an `increment`).
+    Update(String, Vec<u8>),
+}
+
+impl WalOp {
+    const SET: u8 = 1;
+    const DELETE: u8 = 2;
+    const SET_WITH_EXPIRY: u8 = 3;
+    const UPDATE: u8 = 4;
+    const SET_BATCH: u8 = 5;
+
+    fn encode(&self) -> Vec<u8> {
+        let (op, key, value, expires_at) = match self {
+            WalOp::SetBatch(pairs) => {
+                let mut buf = vec![Self::SET_BATCH];
+                buf.extend_from_slice(&(pairs.len() as u32).to_be_bytes());
+                for (key, value) in pairs {
+                    put_prefixed(&mut buf, key.as_bytes());
+                    put_prefixed(&mut buf, value);
+                }
+                return buf;
+            }
+            WalOp::Set(key, value) => (Self::SET, key, Some(value), None),
+            WalOp::Update(key, value) => (Self::UPDATE, key, Some(value), None),
+            WalOp::Delete(key) => (Self::DELETE, key, None, None),
+            WalOp::SetWithExpiry(key, value, expires_at) => {
+                (Self::SET_WITH_EXPIRY, key, Some(value), Some(expires_at))
+            }
+        };
+        let mut buf = vec![op];
+        put_prefixed(&mut buf, key.as_bytes());
+        if let Some(value) = value {
+            put_prefixed(&mut buf, value);
+        }
+        if let Some(expires_at) = expires_at {
+            buf.extend_from_slice(&expires_at.to_be_bytes());
+        }
+        buf
+    }
+
+    /// Decodes a whole record, so that a `SetBatch` is either replayed in full
+    /// or fails to decode without any of its pairs being applied.
+    fn decode(buf: &[u8]) -> Result<Self> {
+        let (op, mut rest) = buf.split_first().with_context(|| "empty WAL record")?;
+        if *op == Self::SET_BATCH {
+            let count = rest.get(..4).with_context(|| "truncated WAL record")?;
+            let count = u32::from_be_bytes(count.try_into().unwrap());
+            rest = &rest[4..];
+            let pairs = (0..count)
+                .map(|_| Ok((take_key(&mut rest)?, take_prefixed(&mut rest)?.to_vec())))
+                .collect::<Result<_>>()?;
+            return Ok(WalOp::SetBatch(pairs));
+        }
+        let key = take_key(&mut rest)?;
+        match *op {
+            Self::SET => Ok(WalOp::Set(key, take_prefixed(&mut rest)?.to_vec())),
+            Self::UPDATE => Ok(WalOp::Update(key, take_prefixed(&mut rest)?.to_vec())),
+            Self::DELETE => Ok(WalOp::Delete(key)),
+            Self::SET_WITH_EXPIRY => {
+                let value = take_prefixed(&mut rest)?.to_vec();
+                let expires_at = rest.get(..8).with_context(|| "truncated WAL record")?;
+                Ok(WalOp::SetWithExpiry(
+                    key,
+                    value,
+                    i64::from_be_bytes(expires_at.try_into().unwrap()),
+                ))
+            }
+            op => bail!("unknown WAL record op {op}"),
+        }
+    }
+}
+
+/// Appends `bytes` to `buf`, prefixed by their big-endian `u32` length.
+fn put_prefixed(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Splits a length-prefixed key (see `take_prefixed`) off the front of `buf`.
+fn take_key(buf: &mut &[u8]) -> Result<String> {
+    String::from_utf8(take_prefixed(buf)?.to_vec())
+        .with_context(|| "WAL record key is not valid UTF-8")
+}
+
+/// Splits bytes prefixed by their big-endian `u32` length off the front of `buf`.
+fn take_prefixed<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    if buf.len() < 4 {
+        bail!("truncated WAL record");
+    }
+    let (len, rest) = buf.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+    if rest.len() < len {
+        bail!("truncated WAL record");
+    }
+    let (bytes, rest) = rest.split_at(len);
+    *buf = rest;
+    Ok(bytes)
+}
+
+/// A WAL record that has been durably written, and must be marked `done`
+/// once its mutation is applied.
+struct WalRecord(PathBuf);
+
+impl WalRecord {
+    fn write(wal_dir: &Path, op: &WalOp) -> Result<Self> {
+        static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+        fs::create_dir_all(wal_dir).with_context(|| "failed to create WAL directory")?;
+
+        // record names sort in the order they were written, so that replays
+        // apply mutations in order
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let path = wal_dir.join(format!(
+            "{nanos:039}-{:010}-{:010}",
+            std::process::id(),
+            SEQUENCE.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        // written aside and renamed into place, so that a crash while writing it
+        // leaves no partial record to replay
+        let temp_path = path.with_extension("tmp");
+        let mut file = File::create(&temp_path).with_context(|| "failed to create WAL record")?;
+        file.write_all(&op.encode())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temp_path, &path))
+            .with_context(|| "failed to write WAL record")?;
+        #[cfg(unix)]
+        File::open(wal_dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| "failed to sync WAL directory")?;
+        Ok(Self(path))
+    }
+
+    fn done(self) -> Result<()> {
+        fs::remove_file(&self.0).with_context(|| "failed to remove WAL record")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store with the WAL enabled, under a fresh base directory.
+    fn journaled_store(name: &str) -> FilesystemImplementor {
+        let base = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&base);
+        let _ = fs::remove_dir_all(format!("{}.wal", base.display()));
+        FilesystemImplementor {
+            base: base.to_str().unwrap().to_owned(),
+            fsync: false,
+            wal: true,
+            watch_interval: DEFAULT_WATCH_INTERVAL,
+        }
+    }
+
+    #[test]
+    fn test_wal_records_round_trip() -> Result<()> {
+        let ops = [
+            WalOp::Set("my-key".into(), b"value".to_vec()),
+            WalOp::Delete("my-key".into()),
+            WalOp::SetWithExpiry("my-key".into(), b"value".to_vec(), 1_700_000_000),
+            WalOp::Update("my-key".into(), b"2".to_vec()),
+            WalOp::SetBatch(vec![
+                ("my-key".into(), b"value".to_vec()),
+                ("my-other-key".into(), vec![]),
+            ]),
+        ];
+        for op in ops {
+            assert_eq!(WalOp::decode(&op.encode())?, op);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_wal_records_fail_to_decode() {
+        let batch = WalOp::SetBatch(vec![
+            ("my-key".into(), b"value".to_vec()),
+            ("my-other-key".into(), b"other value".to_vec()),
+        ])
+        .encode();
+        assert!(WalOp::decode(&batch[..batch.len() - 1]).is_err());
+        assert!(WalOp::decode(&[]).is_err());
+        assert!(WalOp::decode(&[0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_replay_applies_whole_batches() -> Result<()> {
+        let store = journaled_store("slight-keyvalue-test-wal-replay");
+        WalRecord::write(
+            &store.wal_dir(),
+            &WalOp::SetBatch(vec![
+                ("my-key".into(), b"value".to_vec()),
+                ("my-other-key".into(), b"other value".to_vec()),
+            ]),
+        )?;
+        WalRecord::write(&store.wal_dir(), &WalOp::Delete("my-key".into()))?;
+        // a record torn by a crash while it was written
+        fs::write(store.wal_dir().join("torn.tmp"), [WalOp::SET_BATCH])?;
+
+        store.replay_wal()?;
+
+        let base = PathBuf::from(&store.base);
+        assert!(!base.join("my-key").exists());
+        assert_eq!(fs::read(base.join("my-other-key"))?, b"other value");
+        assert_eq!(fs::read_dir(store.wal_dir())?.count(), 0);
         Ok(())
     }
 }
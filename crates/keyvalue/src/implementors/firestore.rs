@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use gcp_auth::AuthenticationManager;
+use reqwest::{Client, RequestBuilder, StatusCode, Url};
+use serde_json::{json, Value};
+use slight_common::BasicState;
+use slight_runtime_configs::configs_from_state;
+#[cfg(feature = "kv-keys")]
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::log;
+
+use crate::{
+    keyvalue::{KeyvalueCapabilities, KeyvalueError},
+    metadata::KeyMetadata,
+    providers::gcp,
+};
+
+#[cfg(feature = "kv-keys")]
+use super::cutoff_timestamp;
+use super::{default_capabilities, invalid_key, KeyvalueImplementor};
+
+const FIRESTORE_SCOPE: &str = "https://www.googleapis.com/auth/datastore";
+
+/// This is the underlying struct behind the `Firestore` variant of the `KeyvalueImplementor` enum.
+///
+/// It provides properties that pertain solely to the Firestore implementation
+/// of this capability:
+///     - `client`,
+///     - `authentication_manager`, and
+///     - `collection_url` (i.e., the REST endpoint of the collection backing the store).
+///
+/// Each store maps to a Firestore collection named after the capability, and each key
+/// maps to a document ID in that collection. Values are kept as the `value` bytes field
+/// of the document, and key metadata as its `metadata` map field.
+///
+/// As per its' usage in `KeyvalueImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Clone)]
+pub struct FirestoreImplementor {
+    client: Client,
+    authentication_manager: Arc<AuthenticationManager>,
+    collection_url: Url,
+}
+
+impl std::fmt::Debug for FirestoreImplementor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FirestoreImplementor")
+            .field("collection_url", &self.collection_url.as_str())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FirestoreImplementor {
+    /// Creates a new `FirestoreImplementor` instance.
+    ///
+    /// It reads the following configs:
+    ///   - `GOOGLE_APPLICATION_CREDENTIALS` (optional) — the path to a service account
+    ///   JSON key file; when omitted, application default credentials are used, and
+    ///   - `GCP_PROJECT_ID` (optional) — when omitted, the project of the service account
+    ///   is used.
+    pub async fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let config: gcp::GcpConfig = configs_from_state(slight_state).await?;
+        let authentication_manager = gcp::authentication_manager(&config).await?;
+        let project_id = gcp::project_id(&config, &authentication_manager).await?;
+        let collection_url = Url::parse(&format!(
+            "https://firestore.googleapis.com/v1/projects/{project_id}/databases/(default)/documents/{name}"
+        ))?;
+        log::info!(
+            "Creating a new Firestore resource with collection name: {}",
+            name
+        );
+
+        Ok(Self {
+            client: Client::new(),
+            authentication_manager: Arc::new(authentication_manager),
+            collection_url,
+        })
+    }
+
+    fn document_url(&self, key: &str) -> Result<Url> {
+        let mut url = self.collection_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("invalid Firestore collection url"))?
+            .push(key);
+        Ok(url)
+    }
+
+    /// Lists every document of the collection, following pagination.
+    async fn list_documents(&self) -> Result<Vec<Value>> {
+        let mut documents = vec![];
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = self.client.get(self.collection_url.clone());
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token)]);
+            }
+            let mut page: Value = self
+                .authorized(request)
+                .await?
+                .send()
+                .await?
+                .error_for_status()
+                .with_context(|| "failed to list documents")?
+                .json()
+                .await?;
+
+            if let Value::Array(page_documents) = page["documents"].take() {
+                documents.extend(page_documents);
+            }
+            page_token = page["nextPageToken"].as_str().map(String::from);
+            if page_token.is_none() {
+                break;
+            }
+        }
+        Ok(documents)
+    }
+
+    async fn authorized(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        let token = self
+            .authentication_manager
+            .get_token(&[FIRESTORE_SCOPE])
+            .await
+            .with_context(|| "failed to get GCP access token")?;
+        Ok(request.bearer_auth(token.as_str()))
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for FirestoreImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        if key.is_empty() || key.len() > 1500 {
+            return Err(invalid_key(
+                key,
+                "document IDs must be 1 to 1500 bytes long",
+            ));
+        }
+        if key.contains('/') || key == "." || key == ".." {
+            return Err(invalid_key(
+                key,
+                "document IDs must not contain '/', or be '.' or '..'",
+            ));
+        }
+        if key.len() >= 4 && key.starts_with("__") && key.ends_with("__") {
+            return Err(invalid_key(
+                key,
+                "document IDs matching '__.*__' are reserved",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let request = self.client.get(self.document_url(key)?);
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        let document: Value = res
+            .error_for_status()
+            .with_context(|| format!("failed to get value for key '{key}'"))?
+            .json()
+            .await?;
+        match document["fields"]["value"]["bytesValue"].as_str() {
+            Some(value) => Ok(STANDARD.decode(value)?),
+            None => bail!("document for key '{key}' has no 'value' bytes field"),
+        }
+    }
+
+    /// Gets the document masked to its `metadata` field, so that the value
+    /// isn't read.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        let request = self
+            .client
+            .get(self.document_url(key)?)
+            .query(&[("mask.fieldPaths", "metadata")]);
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        res.error_for_status()
+            .with_context(|| format!("failed to check whether key '{key}' exists"))?;
+        Ok(true)
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        // a PATCH without preconditions creates the document if it doesn't exist
+        let request = self.client.patch(self.document_url(key)?).json(&json!({
+            "fields": { "value": { "bytesValue": STANDARD.encode(value) } }
+        }));
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(KeyvalueError::Throttled(gcp::retry_after_ms(&res)).into());
+        }
+        res.error_for_status()
+            .with_context(|| format!("failed to set value for key '{key}'"))?;
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        let keys = self
+            .list_documents()
+            .await?
+            .iter()
+            .filter_map(document_id)
+            .collect();
+        Ok(keys)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let request = self.client.delete(self.document_url(key)?);
+        self.authorized(request)
+            .await?
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("failed to delete key '{key}'"))?;
+        Ok(())
+    }
+
+    /// Uses the `updateTime` Firestore keeps for each document.
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        let cutoff = cutoff_timestamp(seconds);
+        let mut keys = vec![];
+        for document in self.list_documents().await? {
+            let update_time = document["updateTime"]
+                .as_str()
+                .with_context(|| "document has no 'updateTime'")?;
+            let update_time = OffsetDateTime::parse(update_time, &Rfc3339)?;
+            if update_time.unix_timestamp() < cutoff {
+                keys.extend(document_id(&document));
+            }
+        }
+        Ok(keys)
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        default_capabilities() | KeyvalueCapabilities::KEYS_OLDER_THAN
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        let request = self.client.get(self.document_url(key)?);
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        let document: Value = res
+            .error_for_status()
+            .with_context(|| format!("failed to get metadata for key '{key}'"))?
+            .json()
+            .await?;
+        match document["fields"]["metadata"]["mapValue"]["fields"].as_object() {
+            Some(fields) => KeyMetadata::from_pairs(
+                fields
+                    .iter()
+                    .filter_map(|(name, value)| Some((name, value["stringValue"].as_str()?))),
+            ),
+            None => Ok(KeyMetadata::default()),
+        }
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        let fields = metadata
+            .to_pairs()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), json!({ "stringValue": value })))
+            .collect::<serde_json::Map<_, _>>();
+        // only the `metadata` field is updated, and only if the document exists
+        let request = self
+            .client
+            .patch(self.document_url(key)?)
+            .query(&[
+                ("updateMask.fieldPaths", "metadata"),
+                ("currentDocument.exists", "true"),
+            ])
+            .json(&json!({
+                "fields": { "metadata": { "mapValue": { "fields": fields } } }
+            }));
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        res.error_for_status()
+            .with_context(|| format!("failed to set metadata for key '{key}'"))?;
+        Ok(())
+    }
+}
+
+/// Document names are full resource paths, the document ID is the last segment.
+fn document_id(document: &Value) -> Option<String> {
+    document["name"]
+        .as_str()
+        .and_then(|name| name.rsplit('/').next())
+        .map(String::from)
+}
@@ -0,0 +1,382 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use gcp_auth::AuthenticationManager;
+use reqwest::{
+    header::{CONTENT_TYPE, RANGE},
+    Client, RequestBuilder, StatusCode, Url,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use slight_common::BasicState;
+use slight_runtime_configs::configs_from_state;
+#[cfg(feature = "kv-keys")]
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::log;
+
+use crate::{
+    keyvalue::{KeyvalueCapabilities, KeyvalueError},
+    metadata::KeyMetadata,
+    providers::gcp,
+};
+
+#[cfg(feature = "kv-keys")]
+use super::cutoff_timestamp;
+use super::{default_capabilities, invalid_key, KeyvalueImplementor};
+
+const STORAGE_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// This is the underlying struct behind the `GcpStorage` variant of the `KeyvalueImplementor` enum.
+///
+/// It provides properties that pertain solely to the Google Cloud Storage
+/// implementation of this capability:
+///     - `client`,
+///     - `authentication_manager`,
+///     - `objects_url` and `upload_url` (i.e., the REST endpoints of the
+///     objects of the bucket backing the store), and
+///     - `prefix`.
+///
+/// Each key maps to the object named after it under `prefix` (e.g., key `a` is
+/// object `<prefix>a`), so several stores can share a bucket under different
+/// prefixes. Key metadata is kept in the object's custom metadata.
+///
+/// As per its' usage in `KeyvalueImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Clone)]
+pub struct GcpStorageImplementor {
+    client: Client,
+    authentication_manager: Arc<AuthenticationManager>,
+    objects_url: Url,
+    upload_url: Url,
+    prefix: String,
+}
+
+impl std::fmt::Debug for GcpStorageImplementor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GcpStorageImplementor")
+            .field("objects_url", &self.objects_url.as_str())
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The configs of a Google Cloud Storage store (see `GcpStorageImplementor::new`).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct GcpStorageConfig {
+    gcp_storage_bucket: String,
+    #[serde(default)]
+    gcp_storage_prefix: String,
+}
+
+impl GcpStorageImplementor {
+    /// Creates a new `GcpStorageImplementor` instance.
+    ///
+    /// It reads the following configs:
+    ///   - `GCP_STORAGE_BUCKET` — the bucket holding the store,
+    ///   - `GCP_STORAGE_PREFIX` (optional) — the prefix of the store's object
+    ///   names (e.g., `my-app/`), which defaults to none, and
+    ///   - `GOOGLE_APPLICATION_CREDENTIALS` (optional) — the path to a service account
+    ///   JSON key file; when omitted, application default credentials are used.
+    pub async fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let gcp_config: gcp::GcpConfig = configs_from_state(slight_state).await?;
+        let config: GcpStorageConfig = configs_from_state(slight_state).await?;
+        let authentication_manager = gcp::authentication_manager(&gcp_config).await?;
+        let objects_url = bucket_url(
+            "https://storage.googleapis.com/storage/v1/b",
+            &config.gcp_storage_bucket,
+        );
+        let upload_url = bucket_url(
+            "https://storage.googleapis.com/upload/storage/v1/b",
+            &config.gcp_storage_bucket,
+        );
+        log::info!(
+            "Creating a new Google Cloud Storage resource '{}' in bucket '{}' with prefix '{}'",
+            name,
+            config.gcp_storage_bucket,
+            config.gcp_storage_prefix
+        );
+
+        Ok(Self {
+            client: Client::new(),
+            authentication_manager: Arc::new(authentication_manager),
+            objects_url,
+            upload_url,
+            prefix: config.gcp_storage_prefix,
+        })
+    }
+
+    fn object_name(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+
+    fn object_url(&self, key: &str) -> Result<Url> {
+        let mut url = self.objects_url.clone();
+        // object names are a single, percent-encoded, path segment (i.e., even
+        // their `/`s are encoded)
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("invalid Google Cloud Storage objects url"))?
+            .push(&self.object_name(key));
+        Ok(url)
+    }
+
+    /// Lists the objects under the store's prefix, masked to the `fields` given
+    /// (e.g., `name,updated`), following pagination.
+    async fn list_objects(&self, fields: &str) -> Result<Vec<Value>> {
+        let mut objects = vec![];
+        let mut page_token: Option<String> = None;
+        let fields = format!("items({fields}),nextPageToken");
+        loop {
+            let mut request = self
+                .client
+                .get(self.objects_url.clone())
+                .query(&[("prefix", self.prefix.as_str()), ("fields", &fields)]);
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token)]);
+            }
+            let mut page: Value = self
+                .authorized(request)
+                .await?
+                .send()
+                .await?
+                .error_for_status()
+                .with_context(|| "failed to list objects")?
+                .json()
+                .await?;
+
+            if let Value::Array(page_objects) = page["items"].take() {
+                objects.extend(page_objects);
+            }
+            page_token = page["nextPageToken"].as_str().map(String::from);
+            if page_token.is_none() {
+                break;
+            }
+        }
+        Ok(objects)
+    }
+
+    /// The key of a listed object, i.e., its name without the store's prefix.
+    fn object_key(&self, object: &Value) -> Option<String> {
+        object["name"]
+            .as_str()?
+            .strip_prefix(&self.prefix)
+            .map(String::from)
+    }
+
+    /// Reads the custom metadata of the object of `key`, answering with
+    /// `KeyvalueError::KeyNotFound` if there's none.
+    async fn custom_metadata(&self, key: &str) -> Result<Map<String, Value>> {
+        let request = self
+            .client
+            .get(self.object_url(key)?)
+            .query(&[("fields", "metadata")]);
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        let mut object: Value = res
+            .error_for_status()
+            .with_context(|| format!("failed to get metadata for key '{key}'"))?
+            .json()
+            .await?;
+        match object["metadata"].take() {
+            Value::Object(metadata) => Ok(metadata),
+            _ => Ok(Map::new()),
+        }
+    }
+
+    async fn authorized(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        let token = self
+            .authentication_manager
+            .get_token(&[STORAGE_SCOPE])
+            .await
+            .with_context(|| "failed to get GCP access token")?;
+        Ok(request.bearer_auth(token.as_str()))
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for GcpStorageImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        let name = self.object_name(key);
+        if key.is_empty() || name.len() > 1024 {
+            return Err(invalid_key(
+                key,
+                "object names (including the store's prefix) must be 1 to 1024 bytes long",
+            ));
+        }
+        if key.contains(['\r', '\n']) || name == "." || name == ".." {
+            return Err(invalid_key(
+                key,
+                "object names must not contain carriage returns or line feeds, or be '.' or '..'",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let request = self
+            .client
+            .get(self.object_url(key)?)
+            .query(&[("alt", "media")]);
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        let value = res
+            .error_for_status()
+            .with_context(|| format!("failed to get value for key '{key}'"))?
+            .bytes()
+            .await?;
+        Ok(value.to_vec())
+    }
+
+    /// Uses a ranged download, except for empty ranges, which only check that
+    /// the object exists.
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        if len == 0 {
+            self.custom_metadata(key).await?;
+            return Ok(vec![]);
+        }
+        let last = offset.saturating_add(len - 1);
+        let request = self
+            .client
+            .get(self.object_url(key)?)
+            .query(&[("alt", "media")])
+            .header(RANGE, format!("bytes={offset}-{last}"));
+        let res = self.authorized(request).await?.send().await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Err(KeyvalueError::KeyNotFound(key.to_string()).into()),
+            // the object exists, but ends before `offset`
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(vec![]),
+            _ => {
+                let value = res
+                    .error_for_status()
+                    .with_context(|| format!("failed to get value range for key '{key}'"))?
+                    .bytes()
+                    .await?;
+                Ok(value.to_vec())
+            }
+        }
+    }
+
+    /// Gets the object's resource masked to its name, so that the value isn't
+    /// read.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        let request = self
+            .client
+            .get(self.object_url(key)?)
+            .query(&[("fields", "name")]);
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        res.error_for_status()
+            .with_context(|| format!("failed to check whether key '{key}' exists"))?;
+        Ok(true)
+    }
+
+    /// Uploads the value as a new object, which replaces the previous one
+    /// (along with its metadata).
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        let name = self.object_name(key);
+        let request = self
+            .client
+            .post(self.upload_url.clone())
+            .query(&[("uploadType", "media"), ("name", name.as_str())])
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(value.to_vec());
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(KeyvalueError::Throttled(gcp::retry_after_ms(&res)).into());
+        }
+        res.error_for_status()
+            .with_context(|| format!("failed to set value for key '{key}'"))?;
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        let keys = self
+            .list_objects("name")
+            .await?
+            .iter()
+            .filter_map(|object| self.object_key(object))
+            .collect();
+        Ok(keys)
+    }
+
+    /// Deleting a key that doesn't exist succeeds.
+    async fn delete(&self, key: &str) -> Result<()> {
+        let request = self.client.delete(self.object_url(key)?);
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        res.error_for_status()
+            .with_context(|| format!("failed to delete key '{key}'"))?;
+        Ok(())
+    }
+
+    /// Uses the `updated` time Google Cloud Storage keeps for each object.
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        let cutoff = cutoff_timestamp(seconds);
+        let mut keys = vec![];
+        for object in self.list_objects("name,updated").await? {
+            let updated = object["updated"]
+                .as_str()
+                .with_context(|| "object has no 'updated' time")?;
+            let updated = OffsetDateTime::parse(updated, &Rfc3339)?;
+            if updated.unix_timestamp() < cutoff {
+                keys.extend(self.object_key(&object));
+            }
+        }
+        Ok(keys)
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        default_capabilities() | KeyvalueCapabilities::KEYS_OLDER_THAN
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        let metadata = self.custom_metadata(key).await?;
+        KeyMetadata::from_pairs(
+            metadata
+                .iter()
+                .filter_map(|(name, value)| Some((name, value.as_str()?))),
+        )
+    }
+
+    /// Patches the object's custom metadata, which merges it with the current
+    /// one, so the names `metadata` doesn't set are cleared with nulls.
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        let mut fields = self
+            .custom_metadata(key)
+            .await?
+            .into_iter()
+            .map(|(name, _)| (name, Value::Null))
+            .collect::<Map<_, _>>();
+        for (name, value) in metadata.to_pairs() {
+            fields.insert(name.to_string(), Value::String(value));
+        }
+        let request = self
+            .client
+            .patch(self.object_url(key)?)
+            .query(&[("fields", "name")])
+            .json(&json!({ "metadata": fields }));
+        let res = self.authorized(request).await?.send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        res.error_for_status()
+            .with_context(|| format!("failed to set metadata for key '{key}'"))?;
+        Ok(())
+    }
+}
+
+/// The REST endpoint of the objects of `bucket`, under the API at `base`.
+fn bucket_url(base: &str, bucket: &str) -> Url {
+    let mut url = Url::parse(base).unwrap();
+    url.path_segments_mut().unwrap().push(bucket).push("o");
+    url
+}
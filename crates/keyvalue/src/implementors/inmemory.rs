@@ -0,0 +1,140 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::log;
+
+use crate::{
+    keyvalue::{KeyvalueCapabilities, KeyvalueError},
+    metadata::KeyMetadata,
+};
+
+use super::{add_to_counter, default_capabilities, parse_counter, KeyvalueImplementor};
+
+/// The contents of one in-memory store.
+#[derive(Debug, Default)]
+struct Store {
+    values: HashMap<String, Vec<u8>>,
+    /// The metadata of the keys that have any, which a `set` or `delete`
+    /// removes.
+    metadata: HashMap<String, KeyMetadata>,
+}
+
+impl Store {
+    fn ensure_exists(&self, key: &str) -> Result<()> {
+        if !self.values.contains_key(key) {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, key: &str, value: &[u8]) {
+        self.values.insert(key.to_owned(), value.to_vec());
+        self.metadata.remove(key);
+    }
+}
+
+/// The stores opened by this process, by name.
+static STORES: Mutex<Option<HashMap<String, Arc<RwLock<Store>>>>> = Mutex::new(None);
+
+/// This is the underlying struct behind the `InMemory` variant of the `KeyvalueImplementor` enum.
+///
+/// It keeps values in the memory of the slight process, so it needs no backend
+/// and loses everything when the process exits (e.g., for tests, examples, and
+/// docs). Stores are shared by name: every store opened with the same name in a
+/// process sees the same values.
+///
+/// As per its' usage in `KeyvalueImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct InMemoryImplementor {
+    store: Arc<RwLock<Store>>,
+}
+
+impl InMemoryImplementor {
+    pub fn new(name: &str) -> Self {
+        log::info!("Opening in-memory keyvalue resource: {}", name);
+        let store = STORES
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .entry(name.to_owned())
+            .or_default()
+            .clone();
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for InMemoryImplementor {
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let store = self.store.read().unwrap();
+        store.ensure_exists(key)?;
+        Ok(store.values[key].clone())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(self.store.read().unwrap().values.contains_key(key))
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.store.write().unwrap().insert(key, value);
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.store.read().unwrap().values.keys().cloned().collect())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let mut store = self.store.write().unwrap();
+        store.values.remove(key);
+        store.metadata.remove(key);
+        Ok(())
+    }
+
+    /// Compares and swaps under the store's write lock.
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        let mut store = self.store.write().unwrap();
+        if store.values.get(key).map(Vec::as_slice) != expected {
+            return Ok(false);
+        }
+        store.insert(key, new);
+        Ok(true)
+    }
+
+    /// Adds under the store's write lock, keeping the key's metadata.
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        let mut store = self.store.write().unwrap();
+        let current = match store.values.get(key) {
+            Some(value) => parse_counter(key, value)?,
+            None => 0,
+        };
+        let value = add_to_counter(key, current, delta)?;
+        store
+            .values
+            .insert(key.to_owned(), value.to_string().into_bytes());
+        Ok(value)
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        default_capabilities()
+            | KeyvalueCapabilities::COMPARE_AND_SWAP
+            | KeyvalueCapabilities::INCREMENT
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        let store = self.store.read().unwrap();
+        store.ensure_exists(key)?;
+        Ok(store.metadata.get(key).cloned().unwrap_or_default())
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        let mut store = self.store.write().unwrap();
+        store.ensure_exists(key)?;
+        store.metadata.insert(key.to_owned(), metadata.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+use std::{fmt::Write as _, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, KeyvalueError, SetOutcome, VersionedValue},
+    metadata::KeyMetadata,
+};
+
+use super::{value_range, KeysPage, KeyvalueImplementor};
+#[cfg(feature = "kv-stream")]
+use crate::watch::KeyvalueWatchImplementor;
+
+/// The checksum algorithms of the `INTEGRITY` config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    Crc32,
+    Sha256,
+}
+
+impl Checksum {
+    /// Parses the name of an algorithm, as in the `INTEGRITY` config.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "crc32" => Ok(Self::Crc32),
+            "sha256" => Ok(Self::Sha256),
+            _ => bail!("unknown checksum algorithm '{name}', expected 'crc32' or 'sha256'"),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Crc32 => "crc32",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// Computes the checksum of `value`, as stored in its key's metadata (i.e.,
+    /// `<algorithm>:<lowercase hex digest>`).
+    fn compute(self, value: &[u8]) -> String {
+        let digest = match self {
+            Self::Crc32 => crc32fast::hash(value).to_be_bytes().to_vec(),
+            Self::Sha256 => Sha256::digest(value).to_vec(),
+        };
+        digest
+            .iter()
+            .fold(format!("{}:", self.name()), |mut checksum, b| {
+                let _ = write!(checksum, "{b:02x}");
+                checksum
+            })
+    }
+}
+
+/// This is a wrapper around any `KeyvalueImplementor` that checks values
+/// against a checksum, enabled with the `INTEGRITY` config (`crc32` or
+/// `sha256`).
+///
+/// Writing a value stores its checksum in the `checksum` field of the key's
+/// metadata, and reading it recomputes the checksum and answers a mismatch with
+/// `KeyvalueError::IntegrityMismatch`, which catches values corrupted at rest
+/// or overwritten by a writer that bypasses slight. The algorithm is recorded
+/// along with the checksum, so changing `INTEGRITY` doesn't fail the values
+/// written before. Keys without a checksum (e.g., written before `INTEGRITY`
+/// was set) are read unchecked.
+///
+/// The checksum is written after the value, not atomically with it, so this is
+/// a best-effort check rather than a guarantee:
+///   - a crash (or a failed metadata write) between the two leaves the value
+///     with the checksum of the previous one, and reading it reports a mismatch,
+///   - a read between the two reports a mismatch too, and
+///   - concurrent writers of a key can leave the value of one with the
+///     checksum of the other, which reads as a mismatch although neither value
+///     is corrupted.
+///
+/// A mismatch therefore means that the value is either corrupted or was being
+/// rewritten when it was read.
+///
+/// Checking costs a metadata read per `get`, and a whole value read per
+/// `get_range`. `scan` isn't supported, since it would cost the same metadata
+/// read for every key it streams, and neither is `increment`, since backends
+/// add in one step that leaves the checksum of the previous count behind.
+///
+/// The wrapped implementor must support key metadata.
+#[derive(Debug, Clone)]
+pub struct IntegrityImplementor {
+    inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    checksum: Checksum,
+}
+
+impl IntegrityImplementor {
+    pub fn new(inner: Arc<dyn KeyvalueImplementor + Send + Sync>, checksum: Checksum) -> Self {
+        Self { inner, checksum }
+    }
+
+    /// Records the checksum of `value`, which was just written to `key`.
+    async fn write_checksum(&self, key: &str, value: &[u8]) -> Result<()> {
+        let mut metadata = self.inner.get_metadata(key).await?;
+        metadata.checksum = Some(self.checksum.compute(value));
+        self.inner.set_metadata(key, &metadata).await
+    }
+
+    /// Checks `value`, as read from `key`, against the checksum of `key`.
+    async fn verify(&self, key: &str, value: Vec<u8>) -> Result<Vec<u8>> {
+        let expected = match self.inner.get_metadata(key).await?.checksum {
+            Some(expected) => expected,
+            None => return Ok(value),
+        };
+        let algorithm = expected.split(':').next().unwrap_or_default();
+        if Checksum::parse(algorithm)?.compute(&value) != expected {
+            return Err(KeyvalueError::IntegrityMismatch(key.to_string()).into());
+        }
+        Ok(value)
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for IntegrityImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        self.inner.validate_key(key)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.get_consistent(key, false).await
+    }
+
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        let value = self.inner.get_consistent(key, strong).await?;
+        self.verify(key, value).await
+    }
+
+    /// The whole value is read and checked, then sliced.
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        Ok(value_range(&self.get(key).await?, offset, len))
+    }
+
+    /// Doesn't verify the value, as it isn't read.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.inner.set(key, value).await?;
+        self.write_checksum(key, value).await
+    }
+
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        let outcome = self.inner.set_reporting(key, value).await?;
+        self.write_checksum(key, value).await?;
+        Ok(outcome)
+    }
+
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        self.inner
+            .set_with_content_type(key, value, content_type, content_encoding)
+            .await?;
+        self.write_checksum(key, value).await
+    }
+
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.inner.set_with_expiry(key, value, expiry).await?;
+        self.write_checksum(key, value).await
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        self.inner.keys().await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    /// Checksums are kept in the keys' metadata, which is deleted along with
+    /// them.
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        self.inner.delete_bulk(keys).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        self.inner.list_keys(prefix, cursor, limit).await
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.inner.health().await
+    }
+
+    fn connection_status(&self) -> ConnectionStatus {
+        self.inner.connection_status()
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.inner.reconnect().await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        self.inner.delete_prefix(prefix).await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix_page(&self, prefix: &str, limit: usize) -> Result<u64> {
+        self.inner.delete_prefix_page(prefix, limit).await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        self.inner.keys_older_than(seconds).await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        self.inner.keys_by_index(value).await
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        self.inner.watch(key).await
+    }
+
+    async fn undelete(&self, key: &str) -> Result<()> {
+        self.inner.undelete(key).await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        self.inner.lock(key, ttl).await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        self.inner.unlock(key, token).await
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        self.inner.capabilities() - KeyvalueCapabilities::SCAN - KeyvalueCapabilities::INCREMENT
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.inner.get_metadata(key).await
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.inner.set_metadata(key, metadata).await
+    }
+
+    async fn get_versioned(&self, key: &str) -> Result<VersionedValue> {
+        let versioned = self.inner.get_versioned(key).await?;
+        Ok(VersionedValue {
+            value: self.verify(key, versioned.value).await?,
+            revision: versioned.revision,
+        })
+    }
+
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        let revision = self.inner.set_if_version(key, value, expected).await?;
+        self.write_checksum(key, value).await?;
+        Ok(revision)
+    }
+
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        let swapped = self.inner.cas(key, expected, new).await?;
+        if swapped {
+            self.write_checksum(key, new).await?;
+        }
+        Ok(swapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use super::*;
+
+    /// A store keeping values and metadata in memory, whose values can be
+    /// overwritten underneath the wrapper to simulate corruption.
+    #[derive(Debug, Default)]
+    struct MetadataStore {
+        values: Mutex<HashMap<String, Vec<u8>>>,
+        metadata: Mutex<HashMap<String, KeyMetadata>>,
+    }
+
+    #[async_trait]
+    impl KeyvalueImplementor for MetadataStore {
+        async fn get(&self, key: &str) -> Result<Vec<u8>> {
+            self.values
+                .lock()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .ok_or_else(|| KeyvalueError::KeyNotFound(key.to_string()).into())
+        }
+
+        async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+            self.values
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), value.to_vec());
+            Ok(())
+        }
+
+        async fn keys(&self) -> Result<Vec<String>> {
+            Ok(self.values.lock().unwrap().keys().cloned().collect())
+        }
+
+        async fn delete(&self, key: &str) -> Result<()> {
+            self.values.lock().unwrap().remove(key);
+            self.metadata.lock().unwrap().remove(key);
+            Ok(())
+        }
+
+        async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+            Ok(self
+                .metadata
+                .lock()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+            self.metadata
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), metadata.clone());
+            Ok(())
+        }
+    }
+
+    fn checked_store(checksum: Checksum) -> (Arc<MetadataStore>, IntegrityImplementor) {
+        let inner = Arc::new(MetadataStore::default());
+        (inner.clone(), IntegrityImplementor::new(inner, checksum))
+    }
+
+    fn is_mismatch(e: &anyhow::Error) -> bool {
+        matches!(
+            e.downcast_ref::<KeyvalueError>(),
+            Some(KeyvalueError::IntegrityMismatch(_))
+        )
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(Checksum::parse("crc32")?, Checksum::Crc32);
+        assert_eq!(Checksum::parse("sha256")?, Checksum::Sha256);
+        assert!(Checksum::parse("md5").is_err());
+        assert!(Checksum::parse("CRC32").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute() {
+        assert_eq!(Checksum::Crc32.compute(b"123456789"), "crc32:cbf43926");
+        assert_eq!(
+            Checksum::Sha256.compute(b"abc"),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify() -> Result<()> {
+        let (_, store) = checked_store(Checksum::Sha256);
+        store.set("my-key", b"value").await?;
+        assert_eq!(store.get("my-key").await?, b"value");
+        assert_eq!(store.get_range("my-key", 1, 3).await?, b"alu");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_mismatch() -> Result<()> {
+        let (inner, store) = checked_store(Checksum::Crc32);
+        store.set("my-key", b"value").await?;
+        inner.set("my-key", b"corrupted").await?;
+
+        let e = store
+            .get("my-key")
+            .await
+            .expect_err("corrupted value was read");
+        assert!(is_mismatch(&e), "unexpected error: {e}");
+        let e = store
+            .get_range("my-key", 0, 1)
+            .await
+            .expect_err("corrupted value was read");
+        assert!(is_mismatch(&e), "unexpected error: {e}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_with_previous_algorithm() -> Result<()> {
+        let (inner, store) = checked_store(Checksum::Crc32);
+        store.set("my-key", b"value").await?;
+
+        let store = IntegrityImplementor::new(inner, Checksum::Sha256);
+        assert_eq!(store.get("my-key").await?, b"value");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_without_checksum() -> Result<()> {
+        let (inner, store) = checked_store(Checksum::Sha256);
+        inner.set("my-key", b"value").await?;
+        assert_eq!(store.get("my-key").await?, b"value");
+
+        inner
+            .set_metadata(
+                "my-key",
+                &KeyMetadata {
+                    checksum: Some("md5:00".into()),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let e = store
+            .get("my-key")
+            .await
+            .expect_err("unknown checksum algorithm was accepted");
+        assert!(!is_mismatch(&e), "unexpected error: {e}");
+        Ok(())
+    }
+}
@@ -1,21 +1,841 @@
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use anyhow::Result;
 use async_trait::async_trait;
 
+use crate::{
+    keyvalue::{
+        ConnectionStatus, KeyvalueCapabilities, KeyvalueError, Operation, SetOutcome,
+        VersionedValue,
+    },
+    metadata::KeyMetadata,
+    stream::{BufferedReadStream, KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
+#[cfg(feature = "kv-stream")]
+use crate::{scan::KeyvalueScanImplementor, watch::KeyvalueWatchImplementor};
+
+pub mod allow_list;
 #[cfg(feature = "awsdynamodb")]
 pub mod awsdynamodb;
 #[cfg(feature = "azblob")]
 pub mod azblob;
+pub mod chain;
+pub mod error_mapping;
 #[cfg(feature = "filesystem")]
 pub mod filesystem;
+#[cfg(feature = "firestore")]
+pub mod firestore;
+#[cfg(feature = "gcpstorage")]
+pub mod gcpstorage;
+#[cfg(feature = "inmemory")]
+pub mod inmemory;
+pub mod integrity;
+#[cfg(feature = "mongodb")]
+pub mod mongodb;
+pub mod normalized;
+#[cfg(feature = "null")]
+pub mod null;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod prefixed;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+#[cfg(feature = "recording")]
+pub mod recording;
 #[cfg(feature = "redis")]
 pub mod redis;
+pub mod retrying;
+pub mod soft_delete;
+pub mod traced;
+
+/// The key the default `KeyvalueImplementor::health` reads, which is valid on
+/// every backend.
+const HEALTH_CHECK_KEY: &str = "slight-health-check";
 
+/// A page of keys, along with the cursor to list the next page from, if any
+/// (see `KeyvalueImplementor::list_keys`).
+pub type KeysPage = (Vec<String>, Option<String>);
+
+/// The operations every implementor must provide.
+///
+/// Not every backend can perform every operation efficiently (e.g., enumerating
+/// all keys). An implementor that can't perform an operation must:
+///   - return the error produced by `unsupported` from it (which guests see as
+///   `KeyvalueError::OperationNotSupported`) rather than failing opaquely or
+///   blocking, and
+///   - leave it out of its `capabilities`.
+///
+/// Operations not listed for an implementor below are supported by it:
+///
+/// | implementor | unsupported operations                                                                                         |
+/// |-------------|----------------------------------------------------------------------------------------------------------------|
+/// | filesystem  | `undelete`, `lock`, `keys_by_index`                                                                            |
+/// | azblob      | `undelete`, `lock`, `keys_by_index`, `scan`, `set_with_expiry`, `cas`, `watch`, `increment`                    |
+/// | awsdynamodb | `undelete`, `keys_by_index` (unless an index is set), `watch`                                                  |
+/// | redis       | `keys_older_than`, `undelete`, `keys_by_index`                                                                 |
+/// | firestore   | `undelete`, `lock`, `keys_by_index`, `scan`, `set_with_expiry`, `cas`, `watch`, `increment`                    |
+/// | gcpstorage  | `undelete`, `lock`, `keys_by_index`, `scan`, `set_with_expiry`, `cas`, `watch`, `increment`                    |
+/// | inmemory    | `keys_older_than`, `undelete`, `lock`, `keys_by_index`, `scan`, `set_with_expiry`, `watch`                     |
+/// | mongodb     | `keys_older_than`, `undelete`, `lock`, `keys_by_index`, `scan`, `set_with_expiry`, `cas`, `watch`, `increment` |
+/// | null        | `keys_older_than`, `undelete`, `lock`, `keys_by_index`, `scan`, `set_with_expiry`, `cas`, `watch`, `increment` |
+/// | postgres    | `keys_older_than`, `undelete`, `lock`, `keys_by_index`, `scan`, `set_with_expiry`, `cas`, `watch`, `increment` |
+/// | proxy       | `keys_older_than`, `undelete`, `lock`, `keys_by_index`, `scan`, `set_with_expiry`, `cas`, `watch`, `increment` |
+///
+/// `undelete` is provided by `soft_delete::SoftDeleteImplementor` on top of any
+/// of them, which in turn doesn't support `scan`, `cas`, `watch` or `increment`.
+///
+/// On top of that, builds without the `kv-keys`, `kv-batch` or `kv-stream`
+/// features support none of the operations of that feature, on any implementor
+/// (see `compiled_capabilities`).
+///
+/// Every implementor supports `get_stream` and `set_stream`, but only
+/// filesystem (unless `WAL` is set) and azblob stream values to and from their
+/// backend; the others buffer the whole value in the host.
+///
+/// Keys are always UTF-8 (binary keys from guests reach implementors base64url
+/// encoded), and each implementor rejects the keys its backend can't store with
+/// `KeyvalueError::InvalidKey` (see `validate_key`). Values are
+/// arbitrary bytes on every implementor:
+///
+/// | implementor | key restrictions                                            | binary values              |
+/// |-------------|-------------------------------------------------------------|----------------------------|
+/// | filesystem  | non-empty, no `/`, `\` or NUL, not `.`, `..` or `.slight-*` | safe                       |
+/// | azblob      | 1 to 1024 characters                                        | safe                       |
+/// | awsdynamodb | 1 to 2048 bytes                                             | safe (`B` attributes)      |
+/// | redis       | none                                                        | safe                       |
+/// | firestore   | 1 to 1500 bytes, no `/`, not `.`, `..` or `__.*__`          | safe (base64 `bytesValue`) |
+/// | gcpstorage  | 1 to 1024 bytes with the prefix, no CR or LF, not `.`, `..` | safe                       |
+/// | inmemory    | none                                                        | safe                       |
+/// | mongodb     | none                                                        | safe (`BinData`)           |
+/// | null        | none                                                        | discarded                  |
+/// | postgres    | no NUL                                                      | safe (`bytea`)             |
+/// | proxy       | those of the remote store                                   | safe                       |
 #[async_trait]
 pub trait KeyvalueImplementor {
+    /// Checks that `key` can be stored by the backend, answering with
+    /// `KeyvalueError::InvalidKey` (see `invalid_key`) otherwise.
+    ///
+    /// The host calls this before every operation that takes a key, so
+    /// operations can assume their key is valid.
+    fn validate_key(&self, _key: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Answers a missing key with `KeyvalueError::KeyNotFound`, which the host
+    /// maps to the store's `MissingKeyBehavior`.
     async fn get(&self, key: &str) -> Result<Vec<u8>>;
+
+    /// Like `get`, but requests a strongly-consistent read when `strong` is set,
+    /// and an eventually-consistent one otherwise.
+    ///
+    /// Only implementors whose backend offers a choice override this, the others
+    /// ignore `strong` (e.g., Redis, or backends whose reads are always strongly
+    /// consistent).
+    async fn get_consistent(&self, key: &str, _strong: bool) -> Result<Vec<u8>> {
+        self.get(key).await
+    }
+
+    /// Returns up to `len` bytes of the value of `key`, starting at `offset`.
+    /// The range is clamped to the value, so reading past its end returns
+    /// fewer bytes (or none) instead of failing.
+    ///
+    /// The default implementation reads the whole value and slices it, so
+    /// implementors whose backend can read ranges should override this.
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        Ok(value_range(&self.get(key).await?, offset, len))
+    }
+
+    /// Opens a stream reading the value of `key` a chunk at a time, which
+    /// answers a missing key with `KeyvalueError::KeyNotFound` when opened.
+    ///
+    /// The default implementation reads the whole value with `get`, and streams
+    /// it from memory (see `BufferedReadStream`), so implementors whose backend
+    /// can stream (or read ranges of) values should override this.
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        Ok(Box::new(BufferedReadStream::new(self.get(key).await?)))
+    }
+
+    /// Whether `key` holds a value.
+    ///
+    /// The default implementation reads the whole value with `get`, so
+    /// implementors whose backend can check for a key without transferring its
+    /// value should override this.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        match self.get(key).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_key_not_found(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// When the backend throttles the write, implementors return
+    /// `KeyvalueError::Throttled` (with the backend's suggested retry delay, if
+    /// any) rather than a generic error, so that guests can back off.
     async fn set(&self, key: &str, value: &[u8]) -> Result<()>;
+
+    /// Opens a stream writing the value of `key` a chunk at a time, which
+    /// replaces it like `set` once the stream is closed.
+    ///
+    /// Implementors whose backend can't stream writes return `None` (the
+    /// default), in which case the host buffers the value, and `set`s it on
+    /// close (see `BufferedWriteStream`). So do wrappers that transform values
+    /// (e.g., checksum them), so that the whole value goes through their `set`.
+    async fn set_stream(
+        &self,
+        _key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        Ok(None)
+    }
+
+    /// Like `set`, but also reports whether `key` already held a value.
+    ///
+    /// The default implementation looks the key up with `get` first, so
+    /// implementors whose backend can report it from the write itself should
+    /// override this.
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        let existed_before = self.get(key).await.is_ok();
+        self.set(key, value).await?;
+        Ok(SetOutcome {
+            existed_before,
+            bytes_written: value.len() as u64,
+        })
+    }
+
+    /// Like `set`, but also records the content type (e.g., `text/html`) of the
+    /// value and, for values the guest already encoded, their content encoding
+    /// (e.g., `gzip`) in the key's metadata. The value is stored as given, and
+    /// `get` returns it as stored.
+    ///
+    /// The default implementation writes the metadata after the value, so
+    /// implementors whose backend serves values with native content headers
+    /// (e.g., azblob over HTTP) should override this to set them instead.
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        self.set(key, value).await?;
+        let metadata = KeyMetadata {
+            content_type: Some(content_type.to_string()),
+            content_encoding: content_encoding.map(String::from),
+            ..Default::default()
+        };
+        self.set_metadata(key, &metadata).await
+    }
+
+    /// Like `set`, but `key` expires `expiry` from now. From then on, every
+    /// operation treats it as absent (e.g., `get` answers with
+    /// `KeyvalueError::KeyNotFound`, and `keys` doesn't list it), even if the
+    /// backend hasn't removed it yet. A later `set` makes the key permanent
+    /// again.
+    ///
+    /// Backends that can't expire keys don't override this, and answer with
+    /// `unsupported` rather than storing a value that never expires.
+    async fn set_with_expiry(&self, _key: &str, _value: &[u8], _expiry: Duration) -> Result<()> {
+        Err(unsupported(Operation::SetWithExpiry))
+    }
+
     async fn keys(&self) -> Result<Vec<String>>;
     async fn delete(&self, key: &str) -> Result<()>;
+
+    /// Lists a page of up to `limit` of the keys that start with `prefix`,
+    /// continuing from `cursor` (as returned with the previous page, or `None`
+    /// for the first page). The listing is over once the returned cursor is
+    /// `None`. Cursors are opaque to guests, and only valid for the same
+    /// `prefix` on the same store.
+    ///
+    /// Backends that list keys by page on the server side may return fewer
+    /// keys than `limit` (even none) while more remain, and keys changed during
+    /// the listing may be listed twice or not at all.
+    ///
+    /// The default implementation lists all keys with `keys`, and pages over
+    /// the sorted matching ones, using the last key of a page as its cursor.
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        let mut keys: Vec<String> = self
+            .keys()
+            .await?
+            .into_iter()
+            .filter(|key| key.starts_with(prefix) && cursor.map_or(true, |c| key.as_str() > c))
+            .collect();
+        keys.sort();
+        if keys.len() <= limit as usize {
+            return Ok((keys, None));
+        }
+        keys.truncate(limit as usize);
+        let cursor = keys.last().cloned();
+        Ok((keys, cursor))
+    }
+
+    /// Gets the values of `keys`, in the same order, with `None` for the keys
+    /// that don't exist.
+    ///
+    /// The default implementation gets each key in turn, so implementors whose
+    /// backend can read many keys at once should override this. Wrappers that
+    /// check or hide values (e.g., `integrity::IntegrityImplementor`) rely on
+    /// the default.
+    async fn get_bulk(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            match self.get(key).await {
+                Ok(value) => values.push(Some(value)),
+                Err(e) if is_key_not_found(&e) => values.push(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Sets the value of each of `pairs`, as `set` does. When a key is given
+    /// more than once, the last value is kept.
+    ///
+    /// The default implementation sets each key in turn, so implementors whose
+    /// backend can write many keys at once should override this.
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        for (key, value) in pairs {
+            self.set(key, value).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes each of `keys`, as `delete` does. Keys that don't exist are
+    /// skipped, rather than failing the call.
+    ///
+    /// The default implementation deletes each key in turn, so implementors
+    /// whose backend can delete many keys at once should override this.
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        for key in keys {
+            match self.delete(key).await {
+                Err(e) if !is_key_not_found(&e) => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes every key of the store (e.g., to reset it between test runs).
+    ///
+    /// The default implementation deletes the keys listed by `keys` with
+    /// `delete_bulk`. Wrappers that restrict or rewrite the keys reaching their
+    /// inner implementor (e.g., `prefixed::PrefixedImplementor`) rely on it, so
+    /// that only their own keys are deleted, never the whole backend. Backends
+    /// holding each store on its own (e.g., in a directory, or a table) should
+    /// override this to delete the store's keys in one go.
+    async fn clear(&self) -> Result<()> {
+        let keys = self.keys().await?;
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.delete_bulk(&keys).await
+    }
+
+    /// Replaces every key of the store with `entries`, so that a whole dataset
+    /// (e.g., a new version of a configuration) can be published at once.
+    ///
+    /// The default implementation isn't atomic: it sets each of `entries`, then
+    /// deletes the other keys, so readers may see a mix of old and new values
+    /// meanwhile (though never miss a key that is in both). Implementors whose
+    /// backend has transactions should override this to swap the keys in one
+    /// step. Wrappers that restrict or rewrite the keys reaching their inner
+    /// implementor (e.g., `prefixed::PrefixedImplementor`) rely on the default.
+    async fn replace_all(&self, entries: &[(&str, &[u8])]) -> Result<()> {
+        for (key, value) in entries {
+            self.set(key, value).await?;
+        }
+        let new_keys: HashSet<&str> = entries.iter().map(|(key, _)| *key).collect();
+        for key in self.keys().await? {
+            if !new_keys.contains(key.as_str()) {
+                self.delete(&key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the backend is reachable and accepts the store's
+    /// credentials, without changing any data.
+    ///
+    /// The default implementation reads a key that isn't expected to exist,
+    /// which passes as long as the backend answers (with a value, or with
+    /// `KeyvalueError::KeyNotFound`).
+    async fn health(&self) -> Result<()> {
+        match self.get(HEALTH_CHECK_KEY).await {
+            Err(e) if !is_key_not_found(&e) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes every key that starts with `prefix`, returning how many were removed.
+    ///
+    /// The default implementation lists all keys and deletes the matching ones
+    /// one at a time. Only keys visible through `keys` (i.e., keys in this store's
+    /// namespace) are ever considered.
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        let mut deleted = 0;
+        for key in self.keys().await? {
+            if key.starts_with(prefix) {
+                self.delete(&key).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Deletes up to `limit` of the keys that start with `prefix`, returning how
+    /// many were removed, or 0 once none is left, so that deleting a large
+    /// prefix can be spread over many short calls (see `crate::delete_jobs`).
+    ///
+    /// The default implementation lists all keys and deletes the first `limit`
+    /// matching ones one at a time, so implementors whose backend can list keys
+    /// by page should override this.
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix_page(&self, prefix: &str, limit: usize) -> Result<u64> {
+        let mut deleted = 0;
+        for key in self
+            .keys()
+            .await?
+            .iter()
+            .filter(|key| key.starts_with(prefix))
+        {
+            if deleted == limit as u64 {
+                break;
+            }
+            self.delete(key).await?;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Lists the keys whose value was last written more than `seconds` ago,
+    /// based on the backend's own timestamps.
+    ///
+    /// Backends that don't keep timestamps don't override this, and answer
+    /// with `unsupported`.
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, _seconds: u64) -> Result<Vec<String>> {
+        Err(unsupported(Operation::KeysOlderThan))
+    }
+
+    /// Lists the keys whose item carries `value` in the attribute of the
+    /// store's secondary index, without listing every key.
+    ///
+    /// Backends without secondary indexes don't override this, and answer
+    /// with `unsupported`.
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, _value: &str) -> Result<Vec<String>> {
+        Err(unsupported(Operation::KeysByIndex))
+    }
+
+    /// Starts streaming every key of the store along with its value, reading
+    /// the store a batch at a time rather than all at once.
+    ///
+    /// Backends that can't list keys and values together don't override this,
+    /// and answer with `unsupported`.
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        Err(unsupported(Operation::Scan))
+    }
+
+    /// Starts watching `key` for changes, i.e., it being set, deleted or
+    /// expiring, including the changes made by other clients of the backend.
+    /// Changes made before this returns aren't reported.
+    ///
+    /// Backends that can neither notify their changes nor be polled for them
+    /// (see `watch::poll`) don't override this, and answer with `unsupported`.
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, _key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        Err(unsupported(Operation::Watch))
+    }
+
+    /// Restores a key removed by `delete`. Only stores with soft-delete
+    /// enabled (see `soft_delete::SoftDeleteImplementor`) support this.
+    async fn undelete(&self, _key: &str) -> Result<()> {
+        Err(unsupported(Operation::Undelete))
+    }
+
+    /// Acquires a lock scoped to `key` that expires after `ttl`, returning the
+    /// token that releases it.
+    ///
+    /// Answers with `KeyvalueError::LockHeld` while another holder's lock on
+    /// `key` hasn't expired. Locks are independent of the key's value, which
+    /// they don't require to exist.
+    async fn lock(&self, _key: &str, _ttl: Duration) -> Result<String> {
+        Err(unsupported(Operation::Lock))
+    }
+
+    /// Releases the lock on `key` if it is still held with `token`, and does
+    /// nothing otherwise (e.g., it has expired).
+    async fn unlock(&self, _key: &str, _token: &str) -> Result<()> {
+        Err(unsupported(Operation::Lock))
+    }
+
+    /// The operations this implementor supports.
+    ///
+    /// Defaults to `default_capabilities`. Implementors that override operations
+    /// answering with `unsupported` by default, or that answer any other operation
+    /// with `unsupported`, must override this as well.
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        default_capabilities()
+    }
+
+    /// The state of the connection to the backend, as seen by the latest
+    /// operations (see `ConnectionTracker`).
+    ///
+    /// Defaults to `ConnectionStatus::Connected`, for implementors that don't
+    /// hold a connection of their own.
+    fn connection_status(&self) -> ConnectionStatus {
+        ConnectionStatus::Connected
+    }
+
+    /// Drops the connection to the backend, if any, and opens a new one.
+    ///
+    /// The default implementation does nothing, for implementors that don't
+    /// hold a connection of their own.
+    async fn reconnect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether this implementor supports `op`, according to its `capabilities`.
+    fn supports(&self, op: Operation) -> bool {
+        self.capabilities().contains(capability(op))
+    }
+
+    /// Returns the metadata of `key`, which is empty if none was ever set.
+    ///
+    /// Metadata is laid out as described in `crate::metadata`, and only its
+    /// location is up to the implementor. A `set` resets the key's metadata,
+    /// and a `delete` removes it.
+    async fn get_metadata(&self, _key: &str) -> Result<KeyMetadata> {
+        Err(metadata_unsupported())
+    }
+
+    /// Replaces the metadata of `key`, which must already hold a value.
+    async fn set_metadata(&self, _key: &str, _metadata: &KeyMetadata) -> Result<()> {
+        Err(metadata_unsupported())
+    }
+
+    /// Returns the value of `key` along with its revision (see `set_if_version`).
+    async fn get_versioned(&self, key: &str) -> Result<VersionedValue> {
+        let value = self.get(key).await?;
+        let revision = revision_of(&self.get_metadata(key).await?);
+        Ok(VersionedValue { value, revision })
+    }
+
+    /// Sets `key` only if its current revision is `expected`, answering with
+    /// `KeyvalueError::VersionConflict` otherwise, and returns the new revision.
+    ///
+    /// A missing key is at revision 0, and a key last written by a plain `set`
+    /// at revision 1 (a `set` resets metadata, and with it the revision), so
+    /// writers relying on revisions should only use `set_if_version`.
+    ///
+    /// The default implementation keeps the revision in the key's metadata and
+    /// checks it before writing, which isn't atomic: implementors whose backend
+    /// can check and write in one step should override this.
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        // every implementor answers `get_metadata` on a missing key with
+        // `KeyvalueError::KeyNotFound`, which `get` doesn't guarantee
+        let current = match self.get_metadata(key).await {
+            // a soft-deleted key is as good as missing
+            Ok(metadata) if metadata.deleted_at.is_some() => 0,
+            Ok(metadata) => revision_of(&metadata),
+            Err(e) if is_key_not_found(&e) => 0,
+            Err(e) => return Err(e),
+        };
+        if current != expected {
+            return Err(KeyvalueError::VersionConflict(current).into());
+        }
+        self.set(key, value).await?;
+        let metadata = KeyMetadata {
+            revision: Some(current + 1),
+            ..Default::default()
+        };
+        self.set_metadata(key, &metadata).await?;
+        Ok(current + 1)
+    }
+
+    /// Sets `key` to `new` only if its current value is `expected` (or, for
+    /// `None`, only if it doesn't exist), in one atomic step, and returns
+    /// whether it did. Like `set`, a swap resets the key's metadata.
+    ///
+    /// Backends that can't compare and write in one step don't override this,
+    /// and answer with `unsupported`, since a `get` followed by a `set` would
+    /// race with other writers.
+    async fn cas(&self, _key: &str, _expected: Option<&[u8]>, _new: &[u8]) -> Result<bool> {
+        Err(unsupported(Operation::CompareAndSwap))
+    }
+
+    /// Adds `delta` to the integer `key` holds, in one atomic step, and returns
+    /// the new value. Counters are stored as decimal text (e.g., `b"42"`, see
+    /// `parse_counter`), so they read back with `get`. A missing (or expired)
+    /// key counts from 0. Unlike `set`, an increment keeps the key's expiry and
+    /// metadata (e.g., the window of a rate limit set with `set_with_expiry`).
+    ///
+    /// Backends that can't add in one step don't override this, and answer
+    /// with `unsupported`, since a `get` followed by a `set` would race with
+    /// other writers.
+    async fn increment(&self, _key: &str, _delta: i64) -> Result<i64> {
+        Err(unsupported(Operation::Increment))
+    }
+}
+
+/// The capabilities of an implementor that relies on the trait's default
+/// implementations, i.e., all but the operations whose default implementation
+/// answers with `unsupported`.
+pub fn default_capabilities() -> KeyvalueCapabilities {
+    KeyvalueCapabilities::all()
+        - KeyvalueCapabilities::KEYS_OLDER_THAN
+        - KeyvalueCapabilities::UNDELETE
+        - KeyvalueCapabilities::LOCK
+        - KeyvalueCapabilities::KEYS_BY_INDEX
+        - KeyvalueCapabilities::SCAN
+        - KeyvalueCapabilities::SET_WITH_EXPIRY
+        - KeyvalueCapabilities::COMPARE_AND_SWAP
+        - KeyvalueCapabilities::WATCH
+        - KeyvalueCapabilities::INCREMENT
+}
+
+/// The operations compiled into the host. Each feature below compiles in
+/// the heavier operations of one kind, on every implementor and wrapper:
+///   - `kv-keys`: `keys` (and `keys-in`), `keys_older_than` and `keys_by_index`,
+///   - `kv-batch`: `delete_prefix` (and `delete-prefix-in-background`), and
+///   - `kv-stream`: `scan` and `watch`.
+///
+/// Operations that aren't compiled in are left out of the capabilities guests
+/// see, and answer with `unsupported`. The interface guests import is the same
+/// either way, so that guests linked against every function still instantiate.
+pub fn compiled_capabilities() -> KeyvalueCapabilities {
+    #[allow(unused_mut)]
+    let mut capabilities = KeyvalueCapabilities::all();
+    #[cfg(not(feature = "kv-keys"))]
+    {
+        capabilities -= KeyvalueCapabilities::KEYS
+            | KeyvalueCapabilities::KEYS_OLDER_THAN
+            | KeyvalueCapabilities::KEYS_BY_INDEX;
+    }
+    #[cfg(not(feature = "kv-batch"))]
+    {
+        capabilities -= KeyvalueCapabilities::DELETE_PREFIX;
+    }
+    #[cfg(not(feature = "kv-stream"))]
+    {
+        capabilities -= KeyvalueCapabilities::SCAN | KeyvalueCapabilities::WATCH;
+    }
+    capabilities
+}
+
+/// The capability flag of `op`.
+pub fn capability(op: Operation) -> KeyvalueCapabilities {
+    match op {
+        Operation::Get => KeyvalueCapabilities::GET,
+        Operation::Set => KeyvalueCapabilities::SET,
+        Operation::Keys => KeyvalueCapabilities::KEYS,
+        Operation::Delete => KeyvalueCapabilities::DELETE,
+        Operation::DeletePrefix => KeyvalueCapabilities::DELETE_PREFIX,
+        Operation::KeysOlderThan => KeyvalueCapabilities::KEYS_OLDER_THAN,
+        Operation::Undelete => KeyvalueCapabilities::UNDELETE,
+        Operation::Lock => KeyvalueCapabilities::LOCK,
+        Operation::KeysByIndex => KeyvalueCapabilities::KEYS_BY_INDEX,
+        Operation::Scan => KeyvalueCapabilities::SCAN,
+        Operation::SetWithExpiry => KeyvalueCapabilities::SET_WITH_EXPIRY,
+        Operation::CompareAndSwap => KeyvalueCapabilities::COMPARE_AND_SWAP,
+        Operation::Watch => KeyvalueCapabilities::WATCH,
+        Operation::Increment => KeyvalueCapabilities::INCREMENT,
+    }
+}
+
+/// Returns the current time, as seconds since the unix epoch.
+pub fn now_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Returns the current time, as milliseconds since the unix epoch.
+pub fn now_timestamp_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+/// Returns the point in time `seconds` ago, as seconds since the unix epoch.
+pub fn cutoff_timestamp(seconds: u64) -> i64 {
+    now_timestamp() - seconds as i64
+}
+
+/// Returns the point in time `expiry` from now, as seconds since the unix
+/// epoch (see `KeyvalueImplementor::set_with_expiry`).
+pub fn expiry_timestamp(expiry: Duration) -> i64 {
+    now_timestamp() + expiry.as_secs() as i64
+}
+
+/// Whether a key expiring at `expires_at` (in seconds since the unix epoch)
+/// has expired.
+pub fn has_expired(expires_at: i64) -> bool {
+    expires_at <= now_timestamp()
+}
+
+/// The revision of a key holding a value, given its metadata (see
+/// `KeyvalueImplementor::set_if_version`).
+pub fn revision_of(metadata: &KeyMetadata) -> u64 {
+    metadata.revision.unwrap_or(1)
+}
+
+/// Returns up to `len` bytes of `value` starting at `offset`, clamped to the
+/// value (see `KeyvalueImplementor::get_range`).
+pub fn value_range(value: &[u8], offset: u64, len: u64) -> Vec<u8> {
+    let start = offset.min(value.len() as u64) as usize;
+    let end = offset.saturating_add(len).min(value.len() as u64) as usize;
+    value[start..end].to_vec()
+}
+
+/// How many operations in a row must fail to reach the backend before a
+/// `ConnectionTracker` reports `ConnectionStatus::Disconnected`.
+pub const DISCONNECTED_AFTER_FAILURES: u32 = 3;
+
+/// Tracks the state of an implementor's connection from the outcome of its
+/// operations, for `KeyvalueImplementor::connection_status`.
+///
+/// Only failures to reach the backend count (e.g., a dropped connection), not
+/// errors the backend answers with (e.g., a missing key).
+#[derive(Debug, Default)]
+pub struct ConnectionTracker {
+    failures: AtomicU32,
+}
+
+impl ConnectionTracker {
+    /// Records an operation that reached the backend.
+    pub fn record_success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Records an operation that failed to reach the backend.
+    pub fn record_failure(&self) {
+        let _ = self
+            .failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |failures| {
+                Some(failures.saturating_add(1))
+            });
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        match self.failures.load(Ordering::Relaxed) {
+            0 => ConnectionStatus::Connected,
+            failures if failures < DISCONNECTED_AFTER_FAILURES => ConnectionStatus::Degraded,
+            _ => ConnectionStatus::Disconnected,
+        }
+    }
+}
+
+/// Whether `e` is a `KeyvalueError::KeyNotFound`.
+pub fn is_key_not_found(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<KeyvalueError>(),
+        Some(KeyvalueError::KeyNotFound(_))
+    )
+}
+
+/// Classifies an error an implementor returned as is (i.e., that isn't already
+/// a `KeyvalueError`) as the `KeyvalueError` guests can act on, if any: each
+/// backend classifies its own errors (e.g., a dropped redis connection as
+/// `KeyvalueError::ConnectionError`), and I/O errors are classified by kind.
+///
+/// Errors that can't be classified return `None`, and become an
+/// `UnexpectedError` (see `crate::default_error_mapping`).
+pub fn classify_error(e: &anyhow::Error) -> Option<KeyvalueError> {
+    #[cfg(feature = "azblob")]
+    if let Some(classified) = azblob::classify_error(e) {
+        return Some(classified);
+    }
+    #[cfg(any(feature = "firestore", feature = "gcpstorage"))]
+    if let Some(classified) = crate::providers::gcp::classify_error(e) {
+        return Some(classified);
+    }
+    #[cfg(feature = "mongodb")]
+    if let Some(classified) = mongodb::classify_error(e) {
+        return Some(classified);
+    }
+    #[cfg(feature = "postgres")]
+    if let Some(classified) = postgres::classify_error(e) {
+        return Some(classified);
+    }
+    #[cfg(feature = "redis")]
+    if let Some(classified) = redis::classify_error(e) {
+        return Some(classified);
+    }
+    let io_error = e.chain().find_map(|e| e.downcast_ref::<std::io::Error>())?;
+    let message = e.to_string();
+    Some(match io_error.kind() {
+        ErrorKind::PermissionDenied => KeyvalueError::AuthenticationError(message),
+        ErrorKind::TimedOut => KeyvalueError::TimeoutError(message),
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::BrokenPipe => KeyvalueError::ConnectionError(message),
+        _ => KeyvalueError::IoError(message),
+    })
+}
+
+/// Returns a new lock token, unique across the processes sharing a backend.
+pub fn lock_token() -> String {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    format!(
+        "{nanos:x}-{:x}-{:x}",
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Reads the integer a counter holds (see `KeyvalueImplementor::increment`),
+/// answering with `KeyvalueError::InvalidValue` if `value` isn't one.
+pub fn parse_counter(key: &str, value: &[u8]) -> Result<i64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| {
+            KeyvalueError::InvalidValue(format!(
+                "the value of key '{key}' is not an integer, so it can't be incremented"
+            ))
+            .into()
+        })
+}
+
+/// Adds `delta` to the `current` value of a counter, answering with
+/// `KeyvalueError::InvalidValue` if that overflows.
+pub fn add_to_counter(key: &str, current: i64, delta: i64) -> Result<i64> {
+    current.checked_add(delta).ok_or_else(|| {
+        KeyvalueError::InvalidValue(format!("incrementing key '{key}' by {delta} overflows")).into()
+    })
+}
+
+/// The error an implementor returns from `validate_key` for a key it can't store.
+pub fn invalid_key(key: &str, reason: &str) -> anyhow::Error {
+    KeyvalueError::InvalidKey(format!("invalid key '{key}': {reason}")).into()
+}
+
+/// The error an implementor returns from an operation it does not support.
+pub fn unsupported(op: Operation) -> anyhow::Error {
+    KeyvalueError::OperationNotSupported(format!(
+        "{op:?} is not supported by this keyvalue implementor"
+    ))
+    .into()
+}
+
+fn metadata_unsupported() -> anyhow::Error {
+    KeyvalueError::OperationNotSupported(
+        "key metadata is not supported by this keyvalue implementor".to_string(),
+    )
+    .into()
 }
 
 impl std::fmt::Debug for dyn KeyvalueImplementor + Send + Sync {
@@ -0,0 +1,409 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+
+use crate::{
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, SetOutcome, VersionedValue},
+    metadata::KeyMetadata,
+    stream::{KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
+
+use super::{KeysPage, KeyvalueImplementor};
+#[cfg(feature = "kv-stream")]
+use crate::{
+    scan::{KeyvalueScanImplementor, MappedScan},
+    watch::KeyvalueWatchImplementor,
+};
+
+/// The placeholder a `KEY_TEMPLATE` replaces with the key.
+const KEY_PLACEHOLDER: &str = "{key}";
+
+/// How a store normalizes keys, as set by its configs:
+///   - `KEY_NORMALIZATION`, a comma-separated list of `trim` (i.e., strip
+///     leading and trailing whitespace) and `lowercase`, and
+///   - `KEY_TEMPLATE`, a template the (trimmed and lowercased) key is placed
+///     into, in place of its single `{key}` (e.g., `app:{key}`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyNormalization {
+    trim: bool,
+    lowercase: bool,
+    /// The parts of the template before and after `{key}`.
+    template: Option<(String, String)>,
+}
+
+impl KeyNormalization {
+    pub fn parse(steps: Option<&str>, template: Option<&str>) -> Result<Self> {
+        let mut normalization = Self::default();
+        for step in steps.unwrap_or_default().split(',').map(str::trim) {
+            match step {
+                "trim" => normalization.trim = true,
+                "lowercase" => normalization.lowercase = true,
+                "" => {}
+                _ => bail!("unknown key normalization '{step}', expected 'trim' or 'lowercase'"),
+            }
+        }
+        if let Some(template) = template {
+            match template.split_once(KEY_PLACEHOLDER) {
+                Some((before, after)) if !after.contains(KEY_PLACEHOLDER) => {
+                    normalization.template = Some((before.to_string(), after.to_string()))
+                }
+                _ => bail!("key template '{template}' must contain '{KEY_PLACEHOLDER}' once"),
+            }
+        }
+        Ok(normalization)
+    }
+
+    /// Whether keys are left as they are.
+    pub fn is_identity(&self) -> bool {
+        self == &Self::default()
+    }
+
+    fn normalize(&self, key: &str) -> String {
+        let key = self.normalize_case(if self.trim { key.trim() } else { key });
+        match &self.template {
+            Some((before, after)) => format!("{before}{key}{after}"),
+            None => key,
+        }
+    }
+
+    /// Normalizes a key prefix, which keeps its trailing whitespace (as it may
+    /// be followed by more of the key) and only gets the start of the template.
+    fn normalize_prefix(&self, prefix: &str) -> String {
+        let prefix = self.normalize_case(if self.trim {
+            prefix.trim_start()
+        } else {
+            prefix
+        });
+        match &self.template {
+            Some((before, _)) => format!("{before}{prefix}"),
+            None => prefix,
+        }
+    }
+
+    fn normalize_case(&self, key: &str) -> String {
+        if self.lowercase {
+            key.to_lowercase()
+        } else {
+            key.to_string()
+        }
+    }
+
+    /// Maps a key listed by the backend to the one guests see, i.e., without
+    /// the template, or `None` if it doesn't fit the template. Keys written
+    /// before the normalization was configured are normalized too.
+    fn listed(&self, key: &str) -> Option<String> {
+        let key = match &self.template {
+            Some((before, after)) => key
+                .strip_prefix(before.as_str())?
+                .strip_suffix(after.as_str())?,
+            None => key,
+        };
+        Some(self.normalize_case(if self.trim { key.trim() } else { key }))
+    }
+
+    fn listed_keys(&self, keys: Vec<String>) -> Vec<String> {
+        let mut seen = HashSet::new();
+        keys.iter()
+            .filter_map(|key| self.listed(key))
+            .filter(|key| seen.insert(key.clone()))
+            .collect()
+    }
+}
+
+/// This is a wrapper around any `KeyvalueImplementor` that normalizes every
+/// key guests pass (see `KeyNormalization`), so that guests disagreeing on
+/// e.g. casing still share keys. `keys`, `keys_older_than`, `keys_by_index` and
+/// `scan` answer with normalized keys.
+///
+/// Normalization is lossy: distinct keys (e.g., `Key` and `key` with
+/// `lowercase`) become the same key. That includes binary keys, which are
+/// normalized in their base64url form, and so shouldn't be combined with
+/// `lowercase`.
+#[derive(Debug, Clone)]
+pub struct NormalizedImplementor {
+    inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    normalization: KeyNormalization,
+}
+
+impl NormalizedImplementor {
+    pub fn new(
+        inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+        normalization: KeyNormalization,
+    ) -> Self {
+        Self {
+            inner,
+            normalization,
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        self.normalization.normalize(key)
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for NormalizedImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        self.inner.validate_key(&self.key(key))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.inner.get(&self.key(key)).await
+    }
+
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        self.inner.get_consistent(&self.key(key), strong).await
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.inner.get_range(&self.key(key), offset, len).await
+    }
+
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        self.inner.get_stream(&self.key(key)).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(&self.key(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.inner.set(&self.key(key), value).await
+    }
+
+    async fn set_stream(
+        &self,
+        key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        self.inner.set_stream(&self.key(key)).await
+    }
+
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        self.inner.set_reporting(&self.key(key), value).await
+    }
+
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        self.inner
+            .set_with_content_type(&self.key(key), value, content_type, content_encoding)
+            .await
+    }
+
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.inner
+            .set_with_expiry(&self.key(key), value, expiry)
+            .await
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.normalization.listed_keys(self.inner.keys().await?))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(&self.key(key)).await
+    }
+
+    /// Keys written before the normalization was configured may be listed on
+    /// more than one page.
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        let (keys, cursor) = self
+            .inner
+            .list_keys(&self.normalization.normalize_prefix(prefix), cursor, limit)
+            .await?;
+        Ok((self.normalization.listed_keys(keys), cursor))
+    }
+
+    async fn get_bulk(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        let keys: Vec<String> = keys.iter().map(|key| self.key(key)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.inner.get_bulk(&keys).await
+    }
+
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        let keys: Vec<String> = pairs.iter().map(|(key, _)| self.key(key)).collect();
+        let pairs: Vec<(&str, &[u8])> = keys
+            .iter()
+            .zip(pairs)
+            .map(|(key, (_, value))| (key.as_str(), *value))
+            .collect();
+        self.inner.set_bulk(&pairs).await
+    }
+
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        let keys: Vec<String> = keys.iter().map(|key| self.key(key)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.inner.delete_bulk(&keys).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn replace_all(&self, entries: &[(&str, &[u8])]) -> Result<()> {
+        let keys: Vec<String> = entries.iter().map(|(key, _)| self.key(key)).collect();
+        let entries: Vec<(&str, &[u8])> = keys
+            .iter()
+            .zip(entries)
+            .map(|(key, (_, value))| (key.as_str(), *value))
+            .collect();
+        self.inner.replace_all(&entries).await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        self.inner
+            .delete_prefix(&self.normalization.normalize_prefix(prefix))
+            .await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix_page(&self, prefix: &str, limit: usize) -> Result<u64> {
+        self.inner
+            .delete_prefix_page(&self.normalization.normalize_prefix(prefix), limit)
+            .await
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.inner.health().await
+    }
+
+    fn connection_status(&self) -> ConnectionStatus {
+        self.inner.connection_status()
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.inner.reconnect().await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        Ok(self
+            .normalization
+            .listed_keys(self.inner.keys_older_than(seconds).await?))
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        Ok(self
+            .normalization
+            .listed_keys(self.inner.keys_by_index(value).await?))
+    }
+
+    /// Keys written before the normalization was configured may be streamed
+    /// more than once, in their normalized form.
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        let normalization = self.normalization.clone();
+        Ok(Box::new(MappedScan::new(
+            self.inner.scan().await?,
+            move |(key, value)| Some((normalization.listed(&key)?, value)),
+        )))
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        self.inner.watch(&self.key(key)).await
+    }
+
+    async fn undelete(&self, key: &str) -> Result<()> {
+        self.inner.undelete(&self.key(key)).await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        self.inner.lock(&self.key(key), ttl).await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        self.inner.unlock(&self.key(key), token).await
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.inner.get_metadata(&self.key(key)).await
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.inner.set_metadata(&self.key(key), metadata).await
+    }
+
+    async fn get_versioned(&self, key: &str) -> Result<VersionedValue> {
+        self.inner.get_versioned(&self.key(key)).await
+    }
+
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        self.inner
+            .set_if_version(&self.key(key), value, expected)
+            .await
+    }
+
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        self.inner.cas(&self.key(key), expected, new).await
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        self.inner.increment(&self.key(key), delta).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert!(KeyNormalization::parse(None, None)?.is_identity());
+        assert!(KeyNormalization::parse(Some(""), None)?.is_identity());
+        assert!(!KeyNormalization::parse(Some("trim, lowercase"), None)?.is_identity());
+        assert!(!KeyNormalization::parse(None, Some("app:{key}"))?.is_identity());
+        assert!(KeyNormalization::parse(Some("uppercase"), None).is_err());
+        assert!(KeyNormalization::parse(None, Some("app:")).is_err());
+        assert!(KeyNormalization::parse(None, Some("{key}:{key}")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize() -> Result<()> {
+        let normalization = KeyNormalization::parse(Some("trim,lowercase"), Some("app:{key}:v1"))?;
+        assert_eq!(normalization.normalize("  My-Key "), "app:my-key:v1");
+        assert_eq!(normalization.normalize_prefix(" My- "), "app:my- ");
+
+        let normalization = KeyNormalization::parse(Some("lowercase"), None)?;
+        assert_eq!(normalization.normalize("  My-Key "), "  my-key ");
+        assert_eq!(normalization.normalize_prefix("My-"), "my-");
+        Ok(())
+    }
+
+    #[test]
+    fn test_listed() -> Result<()> {
+        let normalization = KeyNormalization::parse(Some("lowercase"), Some("app:{key}:v1"))?;
+        assert_eq!(normalization.listed("app:my-key:v1"), Some("my-key".into()));
+        assert_eq!(normalization.listed("app:My-Key:v1"), Some("my-key".into()));
+        assert_eq!(normalization.listed("my-key"), None);
+        assert_eq!(normalization.listed("app:my-key"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_listed_keys_are_deduplicated() -> Result<()> {
+        let normalization = KeyNormalization::parse(Some("trim,lowercase"), None)?;
+        let keys = vec![
+            "my-key".to_string(),
+            " My-Key".to_string(),
+            "other-key".to_string(),
+        ];
+        assert_eq!(normalization.listed_keys(keys), ["my-key", "other-key"]);
+        Ok(())
+    }
+}
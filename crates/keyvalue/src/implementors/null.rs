@@ -0,0 +1,47 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::log;
+
+use crate::keyvalue::KeyvalueError;
+
+use super::KeyvalueImplementor;
+
+/// This is the underlying struct behind the `Null` variant of the `KeyvalueImplementor` enum.
+///
+/// It stores nothing: writes and deletes succeed without effect, `get` answers
+/// every key with `KeyvalueError::KeyNotFound`, and `keys` is always empty. This
+/// turns persistence off without changing the guest (e.g., to test guests that
+/// must tolerate a missing store, or to measure the cost of the host boundary
+/// alone).
+#[derive(Debug, Clone, Default)]
+pub struct NullImplementor;
+
+impl NullImplementor {
+    pub fn new(name: &str) -> Self {
+        log::info!("Creating a new null keyvalue resource: {}", name);
+        Self
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for NullImplementor {
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        Err(KeyvalueError::KeyNotFound(key.to_string()).into())
+    }
+
+    async fn exists(&self, _key: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    async fn set(&self, _key: &str, _value: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    async fn delete(&self, _key: &str) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, SetOutcome, VersionedValue},
+    metadata::KeyMetadata,
+    stream::{KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
+
+use super::{KeysPage, KeyvalueImplementor};
+#[cfg(feature = "kv-stream")]
+use crate::{
+    scan::{KeyvalueScanImplementor, MappedScan},
+    watch::KeyvalueWatchImplementor,
+};
+
+/// This is a wrapper around a backend's `KeyvalueImplementor` that prepends a
+/// prefix to every key: a deployment-wide one, set with
+/// `Keyvalue::with_global_prefix`, and a store's own, set by its `KEY_PREFIX`
+/// config.
+///
+/// It sits directly on top of the backend (i.e., beneath soft-delete, chains,
+/// allow-lists, and namespaces), so that several slight deployments (or apps)
+/// can share one backend without their keys colliding. `keys`,
+/// `keys_older_than` and `scan` only list the keys under the prefix, with the
+/// prefix stripped, so keys written by others sharing the backend are skipped.
+///
+/// The prefix must itself be valid in the backend's keys (e.g., it can't
+/// contain '/' on the filesystem backend).
+#[derive(Debug, Clone)]
+pub struct PrefixedImplementor {
+    inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    prefix: String,
+}
+
+impl PrefixedImplementor {
+    pub fn new(inner: Arc<dyn KeyvalueImplementor + Send + Sync>, prefix: String) -> Self {
+        Self { inner, prefix }
+    }
+
+    fn prefixed(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+
+    fn stripped(&self, keys: Vec<String>) -> Vec<String> {
+        keys.into_iter()
+            .filter_map(|key| key.strip_prefix(self.prefix.as_str()).map(String::from))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for PrefixedImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        self.inner.validate_key(&self.prefixed(key))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.inner.get(&self.prefixed(key)).await
+    }
+
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        self.inner.get_consistent(&self.prefixed(key), strong).await
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.inner.get_range(&self.prefixed(key), offset, len).await
+    }
+
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        self.inner.get_stream(&self.prefixed(key)).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(&self.prefixed(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.inner.set(&self.prefixed(key), value).await
+    }
+
+    async fn set_stream(
+        &self,
+        key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        self.inner.set_stream(&self.prefixed(key)).await
+    }
+
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        self.inner.set_reporting(&self.prefixed(key), value).await
+    }
+
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        self.inner
+            .set_with_content_type(&self.prefixed(key), value, content_type, content_encoding)
+            .await
+    }
+
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.inner
+            .set_with_expiry(&self.prefixed(key), value, expiry)
+            .await
+    }
+
+    async fn get_bulk(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        let keys: Vec<String> = keys.iter().map(|key| self.prefixed(key)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.inner.get_bulk(&keys).await
+    }
+
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        let keys: Vec<String> = pairs.iter().map(|(key, _)| self.prefixed(key)).collect();
+        let pairs: Vec<(&str, &[u8])> = keys
+            .iter()
+            .zip(pairs)
+            .map(|(key, (_, value))| (key.as_str(), *value))
+            .collect();
+        self.inner.set_bulk(&pairs).await
+    }
+
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        let keys: Vec<String> = keys.iter().map(|key| self.prefixed(key)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.inner.delete_bulk(&keys).await
+    }
+
+    /// Only the keys under the prefix are listed, without it.
+    async fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.stripped(self.inner.keys().await?))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(&self.prefixed(key)).await
+    }
+
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        let (keys, cursor) = self
+            .inner
+            .list_keys(&self.prefixed(prefix), cursor, limit)
+            .await?;
+        Ok((self.stripped(keys), cursor))
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        self.inner.delete_prefix(&self.prefixed(prefix)).await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix_page(&self, prefix: &str, limit: usize) -> Result<u64> {
+        self.inner
+            .delete_prefix_page(&self.prefixed(prefix), limit)
+            .await
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.inner.health().await
+    }
+
+    fn connection_status(&self) -> ConnectionStatus {
+        self.inner.connection_status()
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.inner.reconnect().await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        Ok(self.stripped(self.inner.keys_older_than(seconds).await?))
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        Ok(self.stripped(self.inner.keys_by_index(value).await?))
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        let prefix = self.prefix.clone();
+        Ok(Box::new(MappedScan::new(
+            self.inner.scan().await?,
+            move |(key, value)| Some((key.strip_prefix(prefix.as_str())?.to_string(), value)),
+        )))
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        self.inner.watch(&self.prefixed(key)).await
+    }
+
+    async fn undelete(&self, key: &str) -> Result<()> {
+        self.inner.undelete(&self.prefixed(key)).await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        self.inner.lock(&self.prefixed(key), ttl).await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        self.inner.unlock(&self.prefixed(key), token).await
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.inner.get_metadata(&self.prefixed(key)).await
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.inner.set_metadata(&self.prefixed(key), metadata).await
+    }
+
+    async fn get_versioned(&self, key: &str) -> Result<VersionedValue> {
+        self.inner.get_versioned(&self.prefixed(key)).await
+    }
+
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        self.inner
+            .set_if_version(&self.prefixed(key), value, expected)
+            .await
+    }
+
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        self.inner.cas(&self.prefixed(key), expected, new).await
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        self.inner.increment(&self.prefixed(key), delta).await
+    }
+}
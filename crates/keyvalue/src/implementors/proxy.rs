@@ -0,0 +1,250 @@
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use slight_common::BasicState;
+use slight_runtime_configs::configs_from_state;
+use tonic::{
+    transport::{server::TcpIncoming, Channel, Endpoint, Server},
+    Code, Request, Response, Status,
+};
+use tracing::log;
+
+use crate::keyvalue::KeyvalueError;
+
+use super::KeyvalueImplementor;
+
+mod pb {
+    tonic::include_proto!("slight.keyvalue.proxy");
+}
+
+use pb::{
+    keyvalue_proxy_client::KeyvalueProxyClient,
+    keyvalue_proxy_server::{KeyvalueProxy, KeyvalueProxyServer},
+    DeleteRequest, DeleteResponse, GetRequest, GetResponse, KeysRequest, KeysResponse, SetRequest,
+    SetResponse,
+};
+
+/// This is the underlying struct behind the `Proxy` variant of the `KeyvalueImplementor` enum.
+///
+/// It forwards `get`, `set`, `keys` and `delete` over gRPC to another slight
+/// instance serving one of its stores with a `ProxyService`, so that only that
+/// instance holds the backend's credentials and connections. Other operations
+/// go through the trait's default implementations, on top of those four.
+///
+/// It is only available with the `proxy` feature.
+///
+/// As per its' usage in `KeyvalueImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct ProxyImplementor {
+    client: KeyvalueProxyClient<Channel>,
+}
+
+/// The configs of a proxy store (see `ProxyImplementor::new`).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct ProxyConfig {
+    proxy_endpoint: String,
+}
+
+impl ProxyImplementor {
+    /// Creates a new `ProxyImplementor` instance.
+    ///
+    /// It reads the `PROXY_ENDPOINT` config, the URL of the remote slight's
+    /// `ProxyService` (e.g., `http://keyvalue.internal:50051`). The connection is
+    /// opened on the first operation rather than here, and reopened whenever it
+    /// drops.
+    pub async fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let config: ProxyConfig = configs_from_state(slight_state).await?;
+        log::info!(
+            "Creating a new keyvalue proxy resource for {} at {}",
+            name,
+            config.proxy_endpoint
+        );
+        let channel = Endpoint::from_shared(config.proxy_endpoint)
+            .context("PROXY_ENDPOINT must be a valid URI")?
+            .connect_lazy();
+        Ok(Self {
+            client: KeyvalueProxyClient::new(channel),
+        })
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for ProxyImplementor {
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let request = GetRequest {
+            key: key.to_string(),
+        };
+        let res = self
+            .client
+            .clone()
+            .get(request)
+            .await
+            .map_err(from_status)?;
+        Ok(res.into_inner().value)
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        let request = SetRequest {
+            key: key.to_string(),
+            value: value.to_vec(),
+        };
+        self.client
+            .clone()
+            .set(request)
+            .await
+            .map_err(from_status)?;
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        let res = self
+            .client
+            .clone()
+            .keys(KeysRequest {})
+            .await
+            .map_err(from_status)?;
+        Ok(res.into_inner().keys)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let request = DeleteRequest {
+            key: key.to_string(),
+        };
+        self.client
+            .clone()
+            .delete(request)
+            .await
+            .map_err(from_status)?;
+        Ok(())
+    }
+}
+
+/// The addresses a `ProxyService` was started on by `ProxyService::spawn_once`.
+static SERVED: Mutex<Option<HashSet<SocketAddr>>> = Mutex::new(None);
+
+/// This is the server side of `ProxyImplementor`: it serves the store of
+/// `implementor` (with every wrapper the store was opened with) over gRPC.
+///
+/// The service neither authenticates its clients nor encrypts its traffic, so
+/// anyone who can reach its address can read and write the store. It should
+/// only be served on a loopback address, or on a private network whose peers
+/// are all trusted.
+#[derive(Debug)]
+pub struct ProxyService {
+    implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+}
+
+impl ProxyService {
+    pub fn new(implementor: Arc<dyn KeyvalueImplementor + Send + Sync>) -> Self {
+        Self { implementor }
+    }
+
+    /// Serves the store on `addr`, until the server fails.
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        let incoming = bind(addr)?;
+        self.serve_incoming(addr, incoming).await
+    }
+
+    async fn serve_incoming(self, addr: SocketAddr, incoming: TcpIncoming) -> Result<()> {
+        log::info!("Serving keyvalue proxy on {}", addr);
+        Server::builder()
+            .add_service(KeyvalueProxyServer::new(self))
+            .serve_with_incoming(incoming)
+            .await
+            .with_context(|| format!("failed to serve keyvalue proxy on {addr}"))
+    }
+
+    /// Serves the store on `addr` in the background, unless a store was already
+    /// served there by this process (e.g., when the store is opened again).
+    ///
+    /// Fails if `addr` can't be bound. The address is only recorded once it's
+    /// bound, and forgotten if the server fails, so that a later call can serve
+    /// it again.
+    pub fn spawn_once(self, addr: SocketAddr) -> Result<()> {
+        let mut served = SERVED.lock().unwrap();
+        let served = served.get_or_insert_with(HashSet::new);
+        if served.contains(&addr) {
+            return Ok(());
+        }
+        let incoming = bind(addr)?;
+        served.insert(addr);
+        tokio::spawn(async move {
+            if let Err(e) = self.serve_incoming(addr, incoming).await {
+                log::error!("{e:?}");
+            }
+            if let Some(served) = SERVED.lock().unwrap().as_mut() {
+                served.remove(&addr);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl KeyvalueProxy for ProxyService {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let key = request.into_inner().key;
+        self.implementor.validate_key(&key).map_err(to_status)?;
+        let value = self.implementor.get(&key).await.map_err(to_status)?;
+        Ok(Response::new(GetResponse { value }))
+    }
+
+    async fn set(&self, request: Request<SetRequest>) -> Result<Response<SetResponse>, Status> {
+        let SetRequest { key, value } = request.into_inner();
+        self.implementor.validate_key(&key).map_err(to_status)?;
+        self.implementor
+            .set(&key, &value)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(SetResponse {}))
+    }
+
+    async fn keys(&self, _request: Request<KeysRequest>) -> Result<Response<KeysResponse>, Status> {
+        let keys = self.implementor.keys().await.map_err(to_status)?;
+        Ok(Response::new(KeysResponse { keys }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let key = request.into_inner().key;
+        self.implementor.validate_key(&key).map_err(to_status)?;
+        self.implementor.delete(&key).await.map_err(to_status)?;
+        Ok(Response::new(DeleteResponse {}))
+    }
+}
+
+/// Binds `addr` as `Server::serve` would.
+fn bind(addr: SocketAddr) -> Result<TcpIncoming> {
+    TcpIncoming::new(addr, true, None)
+        .map_err(|e| anyhow::anyhow!("failed to bind keyvalue proxy to {addr}: {e}"))
+}
+
+/// Maps an error of the served store to the status `from_status` maps back.
+fn to_status(e: anyhow::Error) -> Status {
+    match e.downcast_ref::<KeyvalueError>() {
+        Some(KeyvalueError::KeyNotFound(key)) => Status::not_found(key.clone()),
+        Some(KeyvalueError::InvalidKey(reason)) => Status::invalid_argument(reason.clone()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+/// Maps a status answered by the remote store (or by the connection to it).
+fn from_status(status: Status) -> anyhow::Error {
+    let message = status.message().to_string();
+    match status.code() {
+        Code::NotFound => KeyvalueError::KeyNotFound(message),
+        Code::InvalidArgument => KeyvalueError::InvalidKey(message),
+        Code::Unavailable | Code::DeadlineExceeded => KeyvalueError::ConnectionError(message),
+        _ => KeyvalueError::UnexpectedError(message),
+    }
+    .into()
+}
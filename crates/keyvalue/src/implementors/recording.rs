@@ -0,0 +1,99 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::keyvalue::KeyvalueError;
+
+use super::KeyvalueImplementor;
+
+/// A call made to a `RecordingImplementor`, with its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Get(String),
+    Set(String, Vec<u8>),
+    Keys,
+    Delete(String),
+}
+
+/// This is a test double that records every `get`, `set`, `keys` and `delete`
+/// call it receives, so that tests can assert what a guest did (see `recorded`)
+/// without inspecting a backend.
+///
+/// It either wraps another `KeyvalueImplementor`, which serves the calls, or
+/// stands alone, in which case it stores nothing (i.e., writes are discarded
+/// and every key is missing). Other operations go through the trait's default
+/// implementations, and so are recorded as the calls those make.
+///
+/// It is only available with the `recording` feature.
+#[derive(Debug, Default)]
+pub struct RecordingImplementor {
+    inner: Option<Arc<dyn KeyvalueImplementor + Send + Sync>>,
+    recorded: Mutex<Vec<Operation>>,
+}
+
+impl RecordingImplementor {
+    /// Records the calls made to `inner`.
+    pub fn new(inner: Arc<dyn KeyvalueImplementor + Send + Sync>) -> Self {
+        Self {
+            inner: Some(inner),
+            recorded: Mutex::default(),
+        }
+    }
+
+    /// Records calls without serving them from any backend.
+    pub fn standalone() -> Self {
+        Self::default()
+    }
+
+    /// Returns the calls received so far, in order.
+    pub fn recorded(&self) -> Vec<Operation> {
+        self.recorded.lock().unwrap().clone()
+    }
+
+    fn record(&self, operation: Operation) {
+        self.recorded.lock().unwrap().push(operation);
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for RecordingImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        match &self.inner {
+            Some(inner) => inner.validate_key(key),
+            None => Ok(()),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.record(Operation::Get(key.to_string()));
+        match &self.inner {
+            Some(inner) => inner.get(key).await,
+            None => Err(KeyvalueError::KeyNotFound(key.to_string()).into()),
+        }
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.record(Operation::Set(key.to_string(), value.to_vec()));
+        match &self.inner {
+            Some(inner) => inner.set(key, value).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        self.record(Operation::Keys);
+        match &self.inner {
+            Some(inner) => inner.keys().await,
+            None => Ok(vec![]),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.record(Operation::Delete(key.to_string()));
+        match &self.inner {
+            Some(inner) => inner.delete(key).await,
+            None => Ok(()),
+        }
+    }
+}
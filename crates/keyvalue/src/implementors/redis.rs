@@ -1,58 +1,500 @@
-use anyhow::{bail, Result};
+use std::{
+    borrow::Cow,
+    env,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
-use redis::{Client, Commands};
+use redis::{
+    Client, Commands, Connection, ConnectionLike, ErrorKind, IntoConnectionInfo, RedisError,
+    RedisResult, Script,
+};
+use serde::Deserialize;
 use slight_common::BasicState;
-use slight_runtime_configs::get_from_state;
+use slight_runtime_configs::configs_from_state;
+use tracing::log;
+
+use crate::{
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, KeyvalueError, SetOutcome},
+    metadata::KeyMetadata,
+};
+
+use super::{
+    default_capabilities, lock_token, revision_of, value_range, ConnectionTracker, KeysPage,
+    KeyvalueImplementor,
+};
+#[cfg(feature = "kv-stream")]
+use crate::{
+    keyvalue::ChangeKind,
+    scan::{KeyvalueScanImplementor, Pair},
+    watch::{ChannelWatch, KeyvalueWatchImplementor},
+};
+
+/// The prefix of the sidecar keys holding key metadata. The sidecar of
+/// `<container_name>:<key>` is `__metadata__:<container_name>:<key>`, which
+/// keeps it out of the container's `<container_name>:*` keyspace.
+const METADATA_PREFIX: &str = "__metadata__";
+
+/// How many keys `clear` deletes per `DEL`.
+const CLEAR_BATCH_SIZE: usize = 1000;
+
+/// The environment variable read when the slightfile doesn't set `REDIS_ADDRESS`.
+const REDIS_URL: &str = "REDIS_URL";
+
+/// The header of the values written by a store with `REDIS_COMPRESS = "true"`,
+/// followed by `COMPRESSED_LZF` or `COMPRESSED_RAW` (see `RedisImplementor::encode`).
+const COMPRESSED_MAGIC: &[u8] = b"\0slzf";
+
+/// Marks a value compressed with LZF, stored as its uncompressed length (a
+/// big-endian `u32`) followed by the compressed bytes.
+const COMPRESSED_LZF: u8 = b'z';
+
+/// Marks a value stored as is, because LZF couldn't shrink it but it starts
+/// with `COMPRESSED_MAGIC` itself.
+const COMPRESSED_RAW: u8 = b'r';
+
+/// How many keys `scan` asks `SCAN` for at a time (which is only a hint to redis).
+#[cfg(feature = "kv-stream")]
+const SCAN_COUNT: usize = 100;
+
+/// The prefix of the keys holding key locks, laid out like metadata sidecars
+/// (i.e., `__lock__:<container_name>:<key>`).
+const LOCK_PREFIX: &str = "__lock__";
 
-use super::KeyvalueImplementor;
+/// How long the connection of a `watch` waits for a notification before
+/// checking whether the watch was dropped.
+#[cfg(feature = "kv-stream")]
+const WATCH_READ_TIMEOUT: Duration = Duration::from_secs(1);
 
-/// This is the underlying struct behind the `AzBlob` variant of the `KeyvalueImplementor` enum.
+/// Deletes the lock key `KEYS[1]` only if it holds the token `ARGV[1]`, so that
+/// a holder whose lock expired can't release the lock of the next holder.
+const UNLOCK_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+/// Sets the key `KEYS[1]` to `ARGV[3]` and deletes its metadata sidecar
+/// `KEYS[2]`, only if it holds `ARGV[2]` when `ARGV[1]` is `1`, or doesn't
+/// exist when it is `0`. Lua scripts run atomically, so no other client can
+/// write the key in between.
+const CAS_SCRIPT: &str = r"
+local current = redis.call('GET', KEYS[1])
+if ARGV[1] == '1' then
+    if current ~= ARGV[2] then
+        return 0
+    end
+elseif current then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[3])
+redis.call('DEL', KEYS[2])
+return 1
+";
+
+/// This is the underlying struct behind the `Redis` variant of the `KeyvalueImplementor` enum.
 ///
-/// It provides a property that pertains solely to the azblob implementation
+/// It provides properties that pertain solely to the redis implementation
 /// of this capability:
-///     - `container_client`
+///     - `client`,
+///     - `connection` (i.e., the connection shared by every operation on the store),
+///     - `connection_tracker` (i.e., the state of `connection`, as seen by those operations),
+///     - `container_name`,
+///     - `compress` (i.e., whether values are compressed with LZF before they are written), and
+///     - `replica` (i.e., the implementor serving reads instead, if any).
 ///
 /// As per its' usage in `KeyvalueImplementor`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 pub struct RedisImplementor {
     client: Client,
+    connection: Arc<RedisConnection>,
+    connection_tracker: Arc<ConnectionTracker>,
     container_name: String,
+    compress: bool,
+    replica: Option<Arc<RedisImplementor>>,
+}
+
+/// A lazily opened connection to the Redis server.
+///
+/// The connection is closed as soon as the last `RedisImplementor` holding it is
+/// dropped (i.e., when the guest drops its `keyvalue` resource), rather than whenever
+/// the process happens to exit.
+#[derive(Default)]
+struct RedisConnection(Mutex<Option<Connection>>);
+
+impl std::fmt::Debug for RedisConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let open = self.0.lock().map(|c| c.is_some()).unwrap_or(false);
+        f.debug_struct("RedisConnection")
+            .field("open", &open)
+            .finish()
+    }
+}
+
+impl Drop for RedisConnection {
+    fn drop(&mut self) {
+        let connection = match self.0.get_mut() {
+            Ok(connection) => connection.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if connection.is_some() {
+            log::debug!("closing redis connection");
+        }
+    }
+}
+
+/// The configs of a redis store (see `RedisImplementor::new`).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct RedisConfig {
+    redis_address: Option<String>,
+    redis_db: Option<u32>,
+    #[serde(default)]
+    redis_compress: bool,
+    redis_read_address: Option<String>,
+    prefer_replica: Option<bool>,
 }
 
 impl RedisImplementor {
-    pub async fn new(slight_state: &BasicState, name: &str) -> Self {
-        let connection_string = get_from_state("REDIS_ADDRESS", slight_state).await.unwrap();
-        let client = redis::Client::open(connection_string).unwrap();
-        let container_name = name.to_string();
+    /// Creates a new `RedisImplementor` instance.
+    ///
+    /// It reads the following configs:
+    ///   - `REDIS_ADDRESS` (optional) — the connection URL, whose path may select
+    ///   a logical database (e.g., `redis://localhost:6379/2`); when omitted, the
+    ///   `REDIS_URL` environment variable (as injected by platforms like Heroku)
+    ///   is used, and
+    ///   - `REDIS_DB` (optional) — the logical database index, which takes
+    ///   precedence over the one in `REDIS_ADDRESS`, and
+    ///   - `REDIS_COMPRESS` (optional) — `true` to compress values with LZF on
+    ///   the client side (defaults to `false`),
+    ///   - `REDIS_READ_ADDRESS` (optional) — the connection URL of a read
+    ///   replica, which serves `get`, `get_range` and `keys`, while the server
+    ///   at `REDIS_ADDRESS` serves every other operation, and
+    ///   - `PREFER_REPLICA` (optional) — `false` to read from `REDIS_ADDRESS`
+    ///   even when `REDIS_READ_ADDRESS` is set (defaults to `true`).
+    ///
+    /// Redis replicates asynchronously, so reads from a replica may not see the
+    /// latest writes (including the store's own): guests that read their writes
+    /// back should use a store that doesn't prefer the replica, or
+    /// `get_consistent` with `strong` set, which is always served by the primary.
+    ///
+    /// Without either `REDIS_ADDRESS` or `REDIS_DB`, db 0 is used. Every
+    /// connection the client opens issues a `SELECT` for the configured
+    /// database, so reconnects stay pinned to it.
+    ///
+    /// The redis client speaks RESP2 only, so compression can't be negotiated
+    /// with the server and always happens in slight. Compressed values carry a
+    /// slight-specific header (see `RedisImplementor::encode`), so other redis
+    /// clients reading the same keys see that header and the LZF bytes rather
+    /// than the original value. Values written without compression are still
+    /// read as is (unless they happen to start with that header), so
+    /// compression can be turned on for an existing store, but turning it off
+    /// again requires rewriting the compressed values.
+    pub async fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let config: RedisConfig = configs_from_state(slight_state).await?;
+        let connection_string = connection_string(config.redis_address)?;
+        let client = open_client(connection_string, config.redis_db)?;
+        let replica = match config.redis_read_address {
+            Some(read_address) if config.prefer_replica.unwrap_or(true) => {
+                log::info!("Reading from the redis replica at REDIS_READ_ADDRESS");
+                let replica_client = open_client(read_address, config.redis_db)?;
+                Some(Arc::new(Self::with_client(
+                    replica_client,
+                    name,
+                    config.redis_compress,
+                    None,
+                )))
+            }
+            _ => None,
+        };
+        Ok(Self::with_client(
+            client,
+            name,
+            config.redis_compress,
+            replica,
+        ))
+    }
+
+    fn with_client(
+        client: Client,
+        name: &str,
+        compress: bool,
+        replica: Option<Arc<RedisImplementor>>,
+    ) -> Self {
         Self {
             client,
-            container_name,
+            connection: Arc::new(RedisConnection::default()),
+            connection_tracker: Arc::new(ConnectionTracker::default()),
+            container_name: name.to_string(),
+            compress,
+            replica,
+        }
+    }
+
+    /// The implementor serving reads that may be stale (i.e., the replica, if any).
+    fn reads(&self) -> &Self {
+        self.replica.as_deref().unwrap_or(self)
+    }
+
+    /// Encodes `value` as it is written to redis. Without `REDIS_COMPRESS`, the
+    /// value is written as is, and otherwise it is written as:
+    ///   - `COMPRESSED_MAGIC`, `COMPRESSED_LZF`, the value's length, and the LZF
+    ///   bytes, if LZF shrinks the value,
+    ///   - `COMPRESSED_MAGIC`, `COMPRESSED_RAW`, and the value, if it doesn't but
+    ///   the value starts with `COMPRESSED_MAGIC` (which `decode` would mistake
+    ///   for a header), or
+    ///   - the value as is, otherwise.
+    fn encode<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        if !self.compress {
+            return Cow::Borrowed(value);
+        }
+        if let (Ok(compressed), Ok(len)) = (lzf::compress(value), u32::try_from(value.len())) {
+            let mut encoded = COMPRESSED_MAGIC.to_vec();
+            encoded.push(COMPRESSED_LZF);
+            encoded.extend_from_slice(&len.to_be_bytes());
+            encoded.extend_from_slice(&compressed);
+            if encoded.len() < value.len() {
+                return Cow::Owned(encoded);
+            }
+        }
+        if value.starts_with(COMPRESSED_MAGIC) {
+            let mut encoded = COMPRESSED_MAGIC.to_vec();
+            encoded.push(COMPRESSED_RAW);
+            encoded.extend_from_slice(value);
+            return Cow::Owned(encoded);
+        }
+        Cow::Borrowed(value)
+    }
+
+    /// Decodes a value read from redis, as encoded by `encode`.
+    fn decode(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        let encoded = match value.strip_prefix(COMPRESSED_MAGIC) {
+            Some(encoded) if self.compress => encoded,
+            _ => return Ok(value),
+        };
+        match encoded.split_first() {
+            Some((&COMPRESSED_LZF, rest)) if rest.len() >= 4 => {
+                let (len, compressed) = rest.split_at(4);
+                let len = u32::from_be_bytes(len.try_into().expect("length is 4 bytes"));
+                lzf::decompress(compressed, len as usize)
+                    .map_err(|e| anyhow::anyhow!("{e}"))
+                    .with_context(|| "failed to decompress redis value")
+            }
+            Some((&COMPRESSED_RAW, rest)) => Ok(rest.to_vec()),
+            _ => bail!("invalid compressed redis value"),
+        }
+    }
+
+    /// Runs `f` against the store's connection, opening it first if it isn't
+    /// open yet. If the connection turns out to be broken, it is discarded so
+    /// that the next operation reconnects.
+    fn with_connection<T>(&self, f: impl FnOnce(&mut Connection) -> RedisResult<T>) -> Result<T> {
+        let mut guard = self.lock_connection()?;
+        if !guard.as_ref().map_or(false, Connection::is_open) {
+            *guard = Some(self.connect()?);
+        }
+        let res = f(guard.as_mut().expect("redis connection was just opened"));
+        match &res {
+            Err(e) if e.is_connection_dropped() || e.is_io_error() => {
+                self.connection_tracker.record_failure();
+                *guard = None;
+            }
+            _ => self.connection_tracker.record_success(),
         }
+        Ok(res?)
+    }
+
+    fn lock_connection(&self) -> Result<MutexGuard<'_, Option<Connection>>> {
+        self.connection
+            .0
+            .lock()
+            .map_err(|_| anyhow::anyhow!("redis connection lock poisoned"))
+    }
+
+    /// Opens a new connection, recording whether the server could be reached.
+    fn connect(&self) -> Result<Connection> {
+        match self.client.get_connection() {
+            Ok(connection) => {
+                self.connection_tracker.record_success();
+                Ok(connection)
+            }
+            Err(e) => {
+                self.connection_tracker.record_failure();
+                Err(e.into())
+            }
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:{}", self.container_name, key)
+    }
+
+    fn metadata_key(&self, key: &str) -> String {
+        format!("{METADATA_PREFIX}:{}:{}", self.container_name, key)
+    }
+
+    fn lock_key(&self, key: &str) -> String {
+        format!("{LOCK_PREFIX}:{}:{}", self.container_name, key)
     }
 }
 
 #[async_trait]
 impl KeyvalueImplementor for RedisImplementor {
     async fn get(&self, key: &str) -> Result<Vec<u8>> {
-        let mut con = self.client.get_connection()?;
-        let val: Vec<u8> = con.get(format!("{}:{}", self.container_name, key))?;
+        self.get_consistent(key, false).await
+    }
+
+    /// Reads from the primary when `strong` is set, and from the replica (if
+    /// any) otherwise.
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        let source = if strong { self } else { self.reads() };
+        let val: Vec<u8> = source.with_connection(|con| con.get(self.key(key)))?;
         // Redis GET returns [:ok; nil] for non-existent keys
         if val.is_empty() {
-            bail!("key not found");
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        self.decode(val)
+    }
+
+    /// Uses `EXISTS`, from the replica if any.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.reads()
+            .with_connection(|con| con.exists(self.key(key)))
+    }
+
+    /// Uses `GETRANGE`, checking that the key exists in the same transaction
+    /// (`GETRANGE` answers a missing key with an empty value).
+    ///
+    /// With `REDIS_COMPRESS`, the stored bytes don't line up with the value's,
+    /// so the whole value is read and sliced instead.
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        if self.compress {
+            return Ok(value_range(&self.get(key).await?, offset, len));
+        }
+        let value_key = self.key(key);
+        let (exists, value): (bool, Vec<u8>) = if len == 0 {
+            (
+                self.reads().with_connection(|con| con.exists(&value_key))?,
+                vec![],
+            )
+        } else {
+            // `GETRANGE` offsets are inclusive, and clamped to the value by Redis
+            let from = offset.min(isize::MAX as u64) as isize;
+            let to = offset.saturating_add(len - 1).min(isize::MAX as u64) as isize;
+            self.reads().with_connection(|con| {
+                redis::pipe()
+                    .atomic()
+                    .exists(&value_key)
+                    .getrange(&value_key, from, to)
+                    .query(con)
+            })?
+        };
+        if !exists {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
         }
-        Ok(val)
+        Ok(value)
     }
 
     async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
-        let mut con = self.client.get_connection()?;
-        con.set(format!("{}:{}", self.container_name, key), value)?;
+        let encoded = self.encode(value);
+        // a new value starts out without metadata
+        let _: () = self.with_connection(|con| {
+            redis::pipe()
+                .atomic()
+                .set(self.key(key), encoded.as_ref())
+                .ignore()
+                .del(self.metadata_key(key))
+                .ignore()
+                .query(con)
+        })?;
 
         Ok(())
     }
 
+    /// Uses `SETEX`, so the key expires on the server side.
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        let encoded = self.encode(value);
+        let _: () = self.with_connection(|con| {
+            redis::pipe()
+                .atomic()
+                .set_ex(self.key(key), encoded.as_ref(), expiry.as_secs() as usize)
+                .ignore()
+                .del(self.metadata_key(key))
+                .ignore()
+                .query(con)
+        })?;
+        Ok(())
+    }
+
+    /// Uses `SET` with the `GET` option (i.e., Redis 6.2 or later) to learn
+    /// whether the key held a value.
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        let encoded = self.encode(value);
+        let (old,): (Option<Vec<u8>>,) = self.with_connection(|con| {
+            redis::pipe()
+                .atomic()
+                .cmd("SET")
+                .arg(self.key(key))
+                .arg(encoded.as_ref())
+                .arg("GET")
+                .del(self.metadata_key(key))
+                .ignore()
+                .query(con)
+        })?;
+        Ok(SetOutcome {
+            existed_before: old.is_some(),
+            bytes_written: value.len() as u64,
+        })
+    }
+
+    /// Checks and writes in a `WATCH`ed transaction on the key and its metadata,
+    /// which is retried if either changes in between.
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        let (value_key, metadata_key) = (self.key(key), self.metadata_key(key));
+        let value = self.encode(value);
+        let written: std::result::Result<u64, u64> = self.with_connection(|con| {
+            redis::transaction(con, &[&value_key, &metadata_key], |con, pipe| {
+                let (exists, metadata): (bool, Option<Vec<u8>>) = redis::pipe()
+                    .exists(&value_key)
+                    .get(&metadata_key)
+                    .query(con)?;
+                let current = match (exists, metadata) {
+                    (false, _) => 0,
+                    (true, None) => revision_of(&KeyMetadata::default()),
+                    (true, Some(metadata)) => {
+                        revision_of(&KeyMetadata::decode(&metadata).map_err(|e| {
+                            RedisError::from((
+                                ErrorKind::TypeError,
+                                "invalid key metadata",
+                                e.to_string(),
+                            ))
+                        })?)
+                    }
+                };
+                if current != expected {
+                    return Ok(Some(Err(current)));
+                }
+                let metadata = KeyMetadata {
+                    revision: Some(current + 1),
+                    ..Default::default()
+                };
+                let written: Option<((), ())> = pipe
+                    .set(&value_key, value.as_ref())
+                    .set(&metadata_key, metadata.encode())
+                    .query(con)?;
+                Ok(written.map(|_| Ok(current + 1)))
+            })
+        })?;
+        written.map_err(|current| KeyvalueError::VersionConflict(current).into())
+    }
+
     async fn keys(&self) -> Result<Vec<String>> {
-        let mut con = self.client.get_connection()?;
-        let keys: Vec<String> = con.keys(format!("{}:*", self.container_name))?;
+        let keys: Vec<String> = self
+            .reads()
+            .with_connection(|con| con.keys(format!("{}:*", self.container_name)))?;
         // remove prefix
         let keys: Vec<String> = keys
             .iter()
@@ -61,10 +503,533 @@ impl KeyvalueImplementor for RedisImplementor {
         Ok(keys)
     }
 
+    /// Uses a single `SCAN` with `MATCH`, from the replica if any, and its
+    /// cursor as the page's. `limit` is only passed as `COUNT`, which redis
+    /// treats as a hint, so a page may also hold a few more keys than `limit`.
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        let cursor: u64 = match cursor {
+            Some(cursor) => cursor.parse().map_err(|_| {
+                KeyvalueError::InvalidValue(format!("invalid list-keys cursor '{cursor}'"))
+            })?,
+            None => 0,
+        };
+        let container_prefix = self.key("");
+        let (next_cursor, keys): (u64, Vec<String>) = self.reads().with_connection(|con| {
+            redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(format!("{}*", escape_glob(&self.key(prefix))))
+                .arg("COUNT")
+                .arg(limit)
+                .query(con)
+        })?;
+        let keys = keys
+            .iter()
+            .filter_map(|key| key.strip_prefix(container_prefix.as_str()))
+            .map(String::from)
+            .collect();
+        // a cursor of 0 means the whole keyspace was visited
+        Ok((keys, (next_cursor != 0).then(|| next_cursor.to_string())))
+    }
+
     async fn delete(&self, key: &str) -> Result<()> {
-        let mut con = self.client.get_connection()?;
-        con.del(format!("{}:{}", self.container_name, key))?;
+        let _: () =
+            self.with_connection(|con| con.del(vec![self.key(key), self.metadata_key(key)]))?;
+
+        Ok(())
+    }
+
+    /// Uses `MGET`, from the replica if any.
+    async fn get_bulk(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let redis_keys: Vec<String> = keys.iter().map(|key| self.key(key)).collect();
+        let values: Vec<Option<Vec<u8>>> = self
+            .reads()
+            .with_connection(|con| redis::cmd("MGET").arg(redis_keys).query(con))?;
+        values
+            .into_iter()
+            // as with `get`, an empty value means the key doesn't exist
+            .map(|value| match value {
+                Some(value) if !value.is_empty() => self.decode(value).map(Some),
+                _ => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Uses `MSET`, clearing the metadata of the keys in the same transaction.
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let mut mset = redis::cmd("MSET");
+        for (key, value) in pairs {
+            mset.arg(self.key(key)).arg(self.encode(value).as_ref());
+        }
+        let metadata_keys: Vec<String> = pairs
+            .iter()
+            .map(|(key, _)| self.metadata_key(key))
+            .collect();
+        let _: () = self.with_connection(|con| {
+            redis::pipe()
+                .atomic()
+                .add_command(mset)
+                .ignore()
+                .del(metadata_keys)
+                .ignore()
+                .query(con)
+        })?;
 
         Ok(())
     }
+
+    /// Uses a single `DEL`, which deletes the keys' metadata too.
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let redis_keys: Vec<String> = keys
+            .iter()
+            .flat_map(|key| [self.key(key), self.metadata_key(key)])
+            .collect();
+        let _: () = self.with_connection(|con| con.del(redis_keys))?;
+
+        Ok(())
+    }
+
+    /// Uses `SCAN` (rather than `KEYS`, so as not to block the server) to find
+    /// the container's keys and metadata sidecars, then `DEL`s them,
+    /// `CLEAR_BATCH_SIZE` at a time. Other containers of the server are left
+    /// alone, as are the container's locks, which expire on their own.
+    async fn clear(&self) -> Result<()> {
+        let container = escape_glob(&self.container_name);
+        let patterns = [
+            format!("{container}:*"),
+            format!("{METADATA_PREFIX}:{container}:*"),
+        ];
+        self.with_connection(|con| {
+            for pattern in patterns {
+                let keys: Vec<String> = con.scan_match(pattern)?.collect();
+                for batch in keys.chunks(CLEAR_BATCH_SIZE) {
+                    let _: () = con.del(batch)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Deletes the old keys and sets the new ones in a single `MULTI`
+    /// transaction, so readers see either every old key or every new one. Keys
+    /// set by others between listing the old keys and the transaction are kept.
+    async fn replace_all(&self, entries: &[(&str, &[u8])]) -> Result<()> {
+        let pattern = format!("{}:*", self.container_name);
+        let _: () = self.with_connection(|con| {
+            let old_keys: Vec<String> = con.scan_match(pattern)?.collect();
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for key in &old_keys {
+                pipe.del(key)
+                    .ignore()
+                    .del(format!("{METADATA_PREFIX}:{key}"))
+                    .ignore();
+            }
+            for (key, value) in entries {
+                pipe.set(self.key(key), self.encode(value).as_ref())
+                    .ignore()
+                    .del(self.metadata_key(key))
+                    .ignore();
+            }
+            pipe.query(con)
+        })?;
+
+        Ok(())
+    }
+
+    /// Pings the replica as well, if any.
+    async fn health(&self) -> Result<()> {
+        self.with_connection(|con| redis::cmd("PING").query::<String>(con))?;
+        if let Some(replica) = &self.replica {
+            replica.health().await?;
+        }
+        Ok(())
+    }
+
+    /// Degraded if only one of the primary and the replica is connected.
+    fn connection_status(&self) -> ConnectionStatus {
+        let status = self.connection_tracker.status();
+        match self
+            .replica
+            .as_ref()
+            .map(|replica| replica.connection_status())
+        {
+            Some(replica_status) if replica_status != status => ConnectionStatus::Degraded,
+            _ => status,
+        }
+    }
+
+    /// Drops the current connection, even if the new one can't be opened, so
+    /// that the next operation tries again.
+    async fn reconnect(&self) -> Result<()> {
+        {
+            let mut guard = self.lock_connection()?;
+            *guard = None;
+            *guard = Some(self.connect()?);
+        }
+        log::debug!("reconnected to redis");
+        if let Some(replica) = &self.replica {
+            replica.reconnect().await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        // `SCAN` is used rather than `KEYS` so we don't block the server on large keyspaces
+        let pattern = format!("{}:{}*", self.container_name, escape_glob(prefix));
+        self.with_connection(|con| {
+            let keys: Vec<String> = con.scan_match(pattern)?.collect();
+            if keys.is_empty() {
+                return Ok(0);
+            }
+
+            let mut pipe = redis::pipe();
+            let metadata_prefix = format!("{METADATA_PREFIX}:");
+            for key in &keys {
+                pipe.del(key)
+                    .del(format!("{metadata_prefix}{key}"))
+                    .ignore();
+            }
+            let deleted: Vec<u64> = pipe.query(con)?;
+            Ok(deleted.iter().sum())
+        })
+    }
+
+    /// Like `delete_prefix`, but stops scanning once `limit` keys were found.
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix_page(&self, prefix: &str, limit: usize) -> Result<u64> {
+        let pattern = format!("{}:{}*", self.container_name, escape_glob(prefix));
+        self.with_connection(|con| {
+            let keys: Vec<String> = con.scan_match(pattern)?.take(limit).collect();
+            if keys.is_empty() {
+                return Ok(0);
+            }
+
+            let mut pipe = redis::pipe();
+            let metadata_prefix = format!("{METADATA_PREFIX}:");
+            for key in &keys {
+                pipe.del(key)
+                    .del(format!("{metadata_prefix}{key}"))
+                    .ignore();
+            }
+            let deleted: Vec<u64> = pipe.query(con)?;
+            Ok(deleted.iter().sum())
+        })
+    }
+
+    /// Uses `SCAN`, reading the values of each batch of keys with `MGET`, from
+    /// the replica if any. As with `SCAN` itself, keys may be returned more
+    /// than once.
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        Ok(Box::new(RedisScan {
+            implementor: self.clone(),
+            cursor: Some(0),
+        }))
+    }
+
+    /// Uses `SET NX PX`, so the lock expires on the server side.
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        let token = lock_token();
+        // `SET` with `NX` answers `OK` when it sets the key, and nil otherwise
+        let acquired: Option<String> = self.with_connection(|con| {
+            redis::cmd("SET")
+                .arg(self.lock_key(key))
+                .arg(&token)
+                .arg("NX")
+                .arg("PX")
+                .arg(ttl.as_millis() as u64)
+                .query(con)
+        })?;
+        if acquired.is_none() {
+            return Err(KeyvalueError::LockHeld(key.to_string()).into());
+        }
+        Ok(token)
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        let _: i64 = self.with_connection(|con| {
+            Script::new(UNLOCK_SCRIPT)
+                .key(self.lock_key(key))
+                .arg(token)
+                .invoke(con)
+        })?;
+        Ok(())
+    }
+
+    /// Uses a Lua script (`CAS_SCRIPT`), which compares and writes in one step.
+    ///
+    /// With `REDIS_COMPRESS`, `expected` is compared in its compressed form, so
+    /// values written before compression was enabled don't match it.
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        let expected = expected.map(|expected| self.encode(expected));
+        let swapped: i64 = self.with_connection(|con| {
+            Script::new(CAS_SCRIPT)
+                .key(self.key(key))
+                .key(self.metadata_key(key))
+                .arg(if expected.is_some() { "1" } else { "0" })
+                .arg(expected.as_deref().unwrap_or_default())
+                .arg(self.encode(new).as_ref())
+                .invoke(con)
+        })?;
+        Ok(swapped == 1)
+    }
+
+    /// Uses `INCRBY`, which keeps the key's expiry. Redis refuses to increment
+    /// values that aren't integers, or that would overflow.
+    ///
+    /// Counters are stored uncompressed, whatever `REDIS_COMPRESS` says, as
+    /// redis has to read them.
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        self.with_connection(|con| con.incr(self.key(key), delta))
+            .map_err(|e| match e.downcast_ref::<RedisError>() {
+                Some(redis_error) if redis_error.kind() == ErrorKind::ResponseError => {
+                    KeyvalueError::InvalidValue(format!(
+                        "the value of key '{key}' is not an integer, or incrementing it by {delta} overflows"
+                    ))
+                    .into()
+                }
+                _ => e,
+            })
+    }
+
+    /// Subscribes to the keyspace notifications of the key (i.e., the
+    /// `__keyspace@<db>__:<container_name>:<key>` channel) on a connection of
+    /// its own, read by a dedicated thread. Expiries are reported when redis
+    /// removes the expired key, which may be a while after it expired.
+    ///
+    /// Redis only publishes these notifications if its `notify-keyspace-events`
+    /// setting includes keyspace events (`K`) for generic (`g`), string (`$`)
+    /// and expiry (`x`) commands (e.g., `K$gx`), which it doesn't by default.
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        let mut con = self.connect()?;
+        let events: Vec<String> = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("notify-keyspace-events")
+            .query(&mut con)
+            .unwrap_or_default();
+        match events.get(1) {
+            Some(flags) if !notifies_changes(flags) => log::warn!(
+                "redis notify-keyspace-events is '{flags}', so watching keys won't see every change; set it to at least 'K$gx'"
+            ),
+            // servers may not allow `CONFIG` (e.g., managed ones), which doesn't
+            // mean notifications are off
+            _ => {}
+        }
+        con.set_read_timeout(Some(WATCH_READ_TIMEOUT))?;
+        let channel = format!(
+            "__keyspace@{}__:{}",
+            self.client.get_connection_info().redis.db,
+            escape_glob(&self.key(key))
+        );
+        let (subscribed, subscription) = tokio::sync::oneshot::channel();
+        let (sender, watch) = ChannelWatch::new();
+        std::thread::spawn(move || {
+            let mut pubsub = con.as_pubsub();
+            if let Err(e) = pubsub.psubscribe(&channel) {
+                let _ = subscribed.send(Err(e));
+                return;
+            }
+            let _ = subscribed.send(Ok(()));
+            while !sender.is_closed() {
+                let msg = match pubsub.get_message() {
+                    Ok(msg) => msg,
+                    Err(e) if e.is_timeout() => continue,
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into()));
+                        return;
+                    }
+                };
+                let kind = match msg.get_payload::<String>().as_deref() {
+                    Ok("set") => ChangeKind::Set,
+                    Ok("del" | "evicted") => ChangeKind::Delete,
+                    Ok("expired") => ChangeKind::Expire,
+                    // other events (e.g., `expire` when an expiry is set) don't
+                    // change the value
+                    _ => continue,
+                };
+                let _ = sender.send(Ok(kind));
+            }
+        });
+        // subscribing before returning ensures no later change is missed
+        subscription
+            .await
+            .context("redis watch thread stopped before subscribing")??;
+        Ok(Box::new(watch))
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        default_capabilities()
+            | KeyvalueCapabilities::LOCK
+            | KeyvalueCapabilities::SCAN
+            | KeyvalueCapabilities::SET_WITH_EXPIRY
+            | KeyvalueCapabilities::COMPARE_AND_SWAP
+            | KeyvalueCapabilities::WATCH
+            | KeyvalueCapabilities::INCREMENT
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        let (exists, metadata): (bool, Option<Vec<u8>>) = self.with_connection(|con| {
+            redis::pipe()
+                .exists(self.key(key))
+                .get(self.metadata_key(key))
+                .query(con)
+        })?;
+        if !exists {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        match metadata {
+            Some(metadata) => KeyMetadata::decode(&metadata),
+            None => Ok(KeyMetadata::default()),
+        }
+    }
+
+    /// The sidecar expires along with the key, if it was set with an expiry.
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        // `PTTL` answers -2 for a missing key, and -1 for one that doesn't expire
+        let ttl: i64 = self.with_connection(|con| con.pttl(self.key(key)))?;
+        if ttl == -2 {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        let _: () = self.with_connection(|con| {
+            if ttl > 0 {
+                con.pset_ex(self.metadata_key(key), metadata.encode(), ttl as usize)
+            } else {
+                con.set(self.metadata_key(key), metadata.encode())
+            }
+        })?;
+        Ok(())
+    }
+}
+
+/// A scan over the keys of a `RedisImplementor`'s container.
+#[cfg(feature = "kv-stream")]
+struct RedisScan {
+    implementor: RedisImplementor,
+    /// The `SCAN` cursor to continue from, or `None` once redis answered with
+    /// the final cursor.
+    cursor: Option<u64>,
+}
+
+#[cfg(feature = "kv-stream")]
+#[async_trait]
+impl KeyvalueScanImplementor for RedisScan {
+    async fn next_batch(&mut self) -> Result<Option<Vec<Pair>>> {
+        let cursor = match self.cursor {
+            Some(cursor) => cursor,
+            None => return Ok(None),
+        };
+        let implementor = &self.implementor;
+        let prefix = format!("{}:", implementor.container_name);
+        let (next_cursor, keys): (u64, Vec<String>) =
+            implementor.reads().with_connection(|con| {
+                redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(format!("{}*", escape_glob(&prefix)))
+                    .arg("COUNT")
+                    .arg(SCAN_COUNT)
+                    .query(con)
+            })?;
+        // a cursor of 0 means the whole keyspace was visited
+        self.cursor = (next_cursor != 0).then_some(next_cursor);
+        if keys.is_empty() {
+            return Ok(Some(vec![]));
+        }
+
+        let values: Vec<Option<Vec<u8>>> = implementor
+            .reads()
+            .with_connection(|con| redis::cmd("MGET").arg(&keys).query(con))?;
+        let mut pairs = vec![];
+        for (key, value) in keys.iter().zip(values) {
+            // keys deleted since `SCAN` listed them are skipped
+            if let (Some(key), Some(value)) = (key.strip_prefix(prefix.as_str()), value) {
+                pairs.push((key.to_string(), implementor.decode(value)?));
+            }
+        }
+        Ok(Some(pairs))
+    }
+}
+
+/// Whether a `notify-keyspace-events` setting publishes the keyspace events
+/// `watch` listens to.
+#[cfg(feature = "kv-stream")]
+fn notifies_changes(flags: &str) -> bool {
+    // `A` stands for every kind of command, `g`, `$` and `x` included
+    flags.contains('K') && (flags.contains('A') || "g$x".chars().all(|f| flags.contains(f)))
+}
+
+/// Escapes the characters Redis treats as special in glob-style patterns
+/// (i.e., `*`, `?`, `[`, `]`, and `\`) so that user-provided prefixes match literally.
+fn escape_glob(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Opens a client for the server at `connection_string`, on the logical
+/// database `db` if set.
+fn open_client(connection_string: String, db: Option<u32>) -> Result<Client> {
+    let mut connection_info = connection_string.into_connection_info()?;
+    if let Some(db) = db {
+        connection_info.redis.db = db.into();
+    }
+    log::info!("Using redis database {}", connection_info.redis.db);
+    Ok(redis::Client::open(connection_info)?)
+}
+
+/// Picks the connection URL of the `REDIS_ADDRESS` config, falling back to the
+/// `REDIS_URL` environment variable.
+fn connection_string(redis_address: Option<String>) -> Result<String> {
+    if let Some(address) = redis_address {
+        log::info!("Using redis address from the REDIS_ADDRESS config");
+        return Ok(address);
+    }
+    match env::var(REDIS_URL) {
+        Ok(url) => {
+            log::info!("Using redis address from the {REDIS_URL} environment variable");
+            Ok(url)
+        }
+        Err(_) => bail!(
+            "either the REDIS_ADDRESS config or the {REDIS_URL} environment variable must be set"
+        ),
+    }
+}
+
+/// Classifies the redis errors returned as is (see `super::classify_error`):
+/// rejected credentials, and failing to reach the server, or to hear back in
+/// time.
+pub(crate) fn classify_error(e: &anyhow::Error) -> Option<KeyvalueError> {
+    let redis_error = e.downcast_ref::<RedisError>()?;
+    let message = e.to_string();
+    if redis_error.kind() == ErrorKind::AuthenticationFailed
+        || matches!(redis_error.code(), Some("NOAUTH" | "WRONGPASS" | "NOPERM"))
+    {
+        Some(KeyvalueError::AuthenticationError(message))
+    } else if redis_error.is_timeout() {
+        Some(KeyvalueError::TimeoutError(message))
+    } else if redis_error.is_connection_dropped()
+        || redis_error.is_connection_refusal()
+        || redis_error.is_io_error()
+    {
+        Some(KeyvalueError::ConnectionError(message))
+    } else {
+        None
+    }
 }
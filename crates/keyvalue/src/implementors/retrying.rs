@@ -0,0 +1,530 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::Rng;
+use tracing::log;
+
+use crate::{
+    default_error_mapping,
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, KeyvalueError, SetOutcome, VersionedValue},
+    metadata::KeyMetadata,
+    stream::{KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
+
+use super::{KeysPage, KeyvalueImplementor};
+#[cfg(feature = "kv-stream")]
+use crate::{scan::KeyvalueScanImplementor, watch::KeyvalueWatchImplementor};
+
+/// How many times an operation is attempted when only `RETRY_BASE_DELAY_MS`
+/// is set.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// The delay before the first retry when only `RETRY_MAX_ATTEMPTS` is set.
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// The longest delay between two attempts, however many failed before.
+pub const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// How a `RetryingImplementor` retries, as set by the `RETRY_MAX_ATTEMPTS` and
+/// `RETRY_BASE_DELAY_MS` configs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times an operation is attempted, the first one included.
+    pub max_attempts: u32,
+    /// The delay before the first retry, which doubles with each retry after
+    /// it (up to `MAX_DELAY`).
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retrying after `attempt` (counting from 1) failed: the
+    /// exponential backoff, of which a random half is jitter, so that guests
+    /// retrying at once spread out. It is never shorter than the delay the
+    /// backend suggested, if any.
+    pub(crate) fn delay(&self, attempt: u32, suggested: Option<Duration>) -> Duration {
+        let backoff = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt - 1))
+            .map_or(MAX_DELAY, |backoff| backoff.min(MAX_DELAY));
+        let half = backoff / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        (half + Duration::from_millis(jitter)).max(suggested.unwrap_or_default())
+    }
+}
+
+/// Whether an operation can be repeated after it failed without knowing
+/// whether the backend applied it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Idempotency {
+    /// Repeating it leaves the store as a single attempt would (e.g., `set`).
+    Idempotent,
+    /// Repeating it after it was applied answers differently (e.g., a `cas`
+    /// that swapped the value compares against the new one), so it is only
+    /// retried when the backend rejected it before applying it.
+    NonIdempotent,
+}
+
+/// What an error says about retrying the operation that returned it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// The backend throttled the operation, so it wasn't applied, with the
+    /// delay it suggested waiting for, if any.
+    Throttled(Option<Duration>),
+    /// The backend couldn't be reached, or didn't answer in time, so the
+    /// operation may or may not have been applied.
+    Transient,
+    /// Retrying wouldn't help (e.g., `KeyvalueError::KeyNotFound`).
+    Permanent,
+}
+
+impl Failure {
+    fn of(e: &anyhow::Error) -> Self {
+        match default_error_mapping(e) {
+            KeyvalueError::Throttled(suggested) => {
+                Self::Throttled(suggested.map(Duration::from_millis))
+            }
+            KeyvalueError::ConnectionError(_) | KeyvalueError::TimeoutError(_) => Self::Transient,
+            _ => Self::Permanent,
+        }
+    }
+
+    /// The delay the backend suggested, if the operation can be retried.
+    fn retryable(self, idempotency: Idempotency) -> Option<Option<Duration>> {
+        match (self, idempotency) {
+            (Self::Throttled(suggested), _) => Some(suggested),
+            (Self::Transient, Idempotency::Idempotent) => Some(None),
+            _ => None,
+        }
+    }
+}
+
+/// This is a wrapper around any `KeyvalueImplementor` that retries the
+/// operations of a flaky backend, enabled with the `RETRY_MAX_ATTEMPTS` and
+/// `RETRY_BASE_DELAY_MS` configs (see `RetryPolicy`).
+///
+/// Only transient errors are retried: `KeyvalueError::Throttled`,
+/// `KeyvalueError::ConnectionError` and `KeyvalueError::TimeoutError`, including
+/// the backend errors classified as such (e.g., a dropped redis connection, see
+/// `super::classify_error`). Every other error is returned right away, and so
+/// is the last one once every attempt failed.
+///
+/// Operations that aren't idempotent (`set_reporting`, `cas`,
+/// `set_if_version`, `lock`, `unlock`, `undelete` and the `delete_prefix`
+/// ones) are only retried when throttled, since a connection lost after the
+/// backend applied them would retry them into another answer. Nor are `health`
+/// and `reconnect` retried, as they report on the backend. `scan` and `watch`
+/// retry starting, but not the batches or changes they read later.
+#[derive(Debug, Clone)]
+pub struct RetryingImplementor {
+    inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    policy: RetryPolicy,
+}
+
+impl RetryingImplementor {
+    pub fn new(inner: Arc<dyn KeyvalueImplementor + Send + Sync>, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    async fn retrying<T, F>(
+        &self,
+        operation: &str,
+        idempotency: Idempotency,
+        op: impl Fn() -> F,
+    ) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let e = match op().await {
+                Ok(res) => return Ok(res),
+                Err(e) => e,
+            };
+            let suggested = match Failure::of(&e).retryable(idempotency) {
+                Some(suggested) if attempt < self.policy.max_attempts => suggested,
+                _ => return Err(e),
+            };
+            let delay = self.policy.delay(attempt, suggested);
+            log::warn!(
+                "keyvalue {operation} failed (attempt {attempt} of {}), retrying in {delay:?}: {e}",
+                self.policy.max_attempts
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for RetryingImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        self.inner.validate_key(key)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.retrying("get", Idempotency::Idempotent, || self.inner.get(key))
+            .await
+    }
+
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        self.retrying("get_consistent", Idempotency::Idempotent, || {
+            self.inner.get_consistent(key, strong)
+        })
+        .await
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.retrying("get_range", Idempotency::Idempotent, || {
+            self.inner.get_range(key, offset, len)
+        })
+        .await
+    }
+
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        self.retrying("get_stream", Idempotency::Idempotent, || {
+            self.inner.get_stream(key)
+        })
+        .await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.retrying("exists", Idempotency::Idempotent, || self.inner.exists(key))
+            .await
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.retrying("set", Idempotency::Idempotent, || {
+            self.inner.set(key, value)
+        })
+        .await
+    }
+
+    async fn set_stream(
+        &self,
+        key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        self.retrying("set_stream", Idempotency::Idempotent, || {
+            self.inner.set_stream(key)
+        })
+        .await
+    }
+
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        self.retrying("set_reporting", Idempotency::NonIdempotent, || {
+            self.inner.set_reporting(key, value)
+        })
+        .await
+    }
+
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        self.retrying("set_with_content_type", Idempotency::Idempotent, || {
+            self.inner
+                .set_with_content_type(key, value, content_type, content_encoding)
+        })
+        .await
+    }
+
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.retrying("set_with_expiry", Idempotency::Idempotent, || {
+            self.inner.set_with_expiry(key, value, expiry)
+        })
+        .await
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        self.retrying("keys", Idempotency::Idempotent, || self.inner.keys())
+            .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.retrying("delete", Idempotency::Idempotent, || self.inner.delete(key))
+            .await
+    }
+
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        self.retrying("list_keys", Idempotency::Idempotent, || {
+            self.inner.list_keys(prefix, cursor, limit)
+        })
+        .await
+    }
+
+    async fn get_bulk(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.retrying("get_bulk", Idempotency::Idempotent, || {
+            self.inner.get_bulk(keys)
+        })
+        .await
+    }
+
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        self.retrying("set_bulk", Idempotency::Idempotent, || {
+            self.inner.set_bulk(pairs)
+        })
+        .await
+    }
+
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        self.retrying("delete_bulk", Idempotency::Idempotent, || {
+            self.inner.delete_bulk(keys)
+        })
+        .await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.retrying("clear", Idempotency::Idempotent, || self.inner.clear())
+            .await
+    }
+
+    async fn replace_all(&self, entries: &[(&str, &[u8])]) -> Result<()> {
+        self.retrying("replace_all", Idempotency::Idempotent, || {
+            self.inner.replace_all(entries)
+        })
+        .await
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.inner.health().await
+    }
+
+    fn connection_status(&self) -> ConnectionStatus {
+        self.inner.connection_status()
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.inner.reconnect().await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        self.retrying("delete_prefix", Idempotency::NonIdempotent, || {
+            self.inner.delete_prefix(prefix)
+        })
+        .await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix_page(&self, prefix: &str, limit: usize) -> Result<u64> {
+        self.retrying("delete_prefix_page", Idempotency::NonIdempotent, || {
+            self.inner.delete_prefix_page(prefix, limit)
+        })
+        .await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        self.retrying("keys_older_than", Idempotency::Idempotent, || {
+            self.inner.keys_older_than(seconds)
+        })
+        .await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        self.retrying("keys_by_index", Idempotency::Idempotent, || {
+            self.inner.keys_by_index(value)
+        })
+        .await
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        self.retrying("scan", Idempotency::Idempotent, || self.inner.scan())
+            .await
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        self.retrying("watch", Idempotency::Idempotent, || self.inner.watch(key))
+            .await
+    }
+
+    async fn undelete(&self, key: &str) -> Result<()> {
+        self.retrying("undelete", Idempotency::NonIdempotent, || {
+            self.inner.undelete(key)
+        })
+        .await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        self.retrying("lock", Idempotency::NonIdempotent, || {
+            self.inner.lock(key, ttl)
+        })
+        .await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        self.retrying("unlock", Idempotency::NonIdempotent, || {
+            self.inner.unlock(key, token)
+        })
+        .await
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.retrying("get_metadata", Idempotency::Idempotent, || {
+            self.inner.get_metadata(key)
+        })
+        .await
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.retrying("set_metadata", Idempotency::Idempotent, || {
+            self.inner.set_metadata(key, metadata)
+        })
+        .await
+    }
+
+    async fn get_versioned(&self, key: &str) -> Result<VersionedValue> {
+        self.retrying("get_versioned", Idempotency::Idempotent, || {
+            self.inner.get_versioned(key)
+        })
+        .await
+    }
+
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        self.retrying("set_if_version", Idempotency::NonIdempotent, || {
+            self.inner.set_if_version(key, value, expected)
+        })
+        .await
+    }
+
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        self.retrying("cas", Idempotency::NonIdempotent, || {
+            self.inner.cas(key, expected, new)
+        })
+        .await
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        self.retrying("increment", Idempotency::NonIdempotent, || {
+            self.inner.increment(key, delta)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, ErrorKind};
+
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    fn io_error(kind: ErrorKind) -> anyhow::Error {
+        anyhow::Error::from(io::Error::from(kind)).context("failed to read 'my-key'")
+    }
+
+    #[test]
+    fn test_failure_of() {
+        assert_eq!(
+            Failure::of(&KeyvalueError::Throttled(Some(250)).into()),
+            Failure::Throttled(Some(Duration::from_millis(250)))
+        );
+        assert_eq!(
+            Failure::of(&KeyvalueError::Throttled(None).into()),
+            Failure::Throttled(None)
+        );
+        assert_eq!(
+            Failure::of(&KeyvalueError::ConnectionError("reset".into()).into()),
+            Failure::Transient
+        );
+        assert_eq!(
+            Failure::of(&KeyvalueError::TimeoutError("timed out".into()).into()),
+            Failure::Transient
+        );
+        assert_eq!(
+            Failure::of(&KeyvalueError::KeyNotFound("my-key".into()).into()),
+            Failure::Permanent
+        );
+        assert_eq!(Failure::of(&anyhow!("malformed value")), Failure::Permanent);
+    }
+
+    #[test]
+    fn test_failure_of_io_errors() {
+        assert_eq!(
+            Failure::of(&io_error(ErrorKind::ConnectionReset)),
+            Failure::Transient
+        );
+        assert_eq!(
+            Failure::of(&io_error(ErrorKind::TimedOut)),
+            Failure::Transient
+        );
+        assert_eq!(
+            Failure::of(&io_error(ErrorKind::PermissionDenied)),
+            Failure::Permanent
+        );
+        assert_eq!(
+            Failure::of(&io_error(ErrorKind::NotFound)),
+            Failure::Permanent
+        );
+    }
+
+    #[test]
+    fn test_failure_of_wrapped_errors() {
+        let e = Err::<(), _>(KeyvalueError::Throttled(None))
+            .context("failed to set 'my-key'")
+            .unwrap_err();
+        assert_eq!(Failure::of(&e), Failure::Throttled(None));
+    }
+
+    #[test]
+    fn test_retryable() {
+        let suggested = Some(Duration::from_millis(250));
+        for idempotency in [Idempotency::Idempotent, Idempotency::NonIdempotent] {
+            assert_eq!(
+                Failure::Throttled(suggested).retryable(idempotency),
+                Some(suggested)
+            );
+            assert_eq!(Failure::Permanent.retryable(idempotency), None);
+        }
+        assert_eq!(
+            Failure::Transient.retryable(Idempotency::Idempotent),
+            Some(None)
+        );
+        assert_eq!(
+            Failure::Transient.retryable(Idempotency::NonIdempotent),
+            None
+        );
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(1, None);
+            assert!((Duration::from_millis(50)..=Duration::from_millis(100)).contains(&delay));
+            let delay = policy.delay(3, None);
+            assert!((Duration::from_millis(200)..=Duration::from_millis(400)).contains(&delay));
+            assert!(policy.delay(u32::MAX, None) <= MAX_DELAY);
+            assert_eq!(
+                policy.delay(1, Some(Duration::from_secs(1))),
+                Duration::from_secs(1)
+            );
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::log;
+
+use crate::{
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, KeyvalueError},
+    metadata::KeyMetadata,
+};
+
+use super::{is_key_not_found, now_timestamp, KeysPage, KeyvalueImplementor};
+
+/// How long tombstones are kept when `TOMBSTONE_TTL` isn't set (i.e., a day).
+pub const DEFAULT_TOMBSTONE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// This is a wrapper around any `KeyvalueImplementor` that turns `delete` into a
+/// soft-delete, enabled with the `SOFT_DELETE = "true"` config.
+///
+/// Deleting a key writes a tombstone (the `deleted_at` field of its metadata)
+/// instead of removing it, which hides the key from `get`, `keys` and
+/// `keys_older_than`. Within the retention window (`TOMBSTONE_TTL` seconds),
+/// `undelete` restores the key, and past it a background purge removes the key
+/// from the wrapped implementor for good. Tombstoned keys stay visible through
+/// `get_metadata`, and so through `stat` (as its `deleted-at`), which is how
+/// guests find the keys they can undelete.
+///
+/// `scan` isn't supported, as telling tombstoned keys apart would take a
+/// metadata read per key, which is what streaming the store avoids. Nor is
+/// `cas`, since the wrapped implementor compares against tombstoned values as
+/// if they were live, or `watch`, since tombstoning a key only writes its
+/// metadata, which the wrapped implementor doesn't report as a change, or
+/// `increment`, since it would add to tombstoned counters without reviving them.
+///
+/// The wrapped implementor must support key metadata.
+#[derive(Debug, Clone)]
+pub struct SoftDeleteImplementor {
+    inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    tombstone_ttl: Duration,
+}
+
+impl SoftDeleteImplementor {
+    pub fn new(inner: Arc<dyn KeyvalueImplementor + Send + Sync>, tombstone_ttl: Duration) -> Self {
+        Self {
+            inner,
+            tombstone_ttl,
+        }
+    }
+
+    /// Starts purging expired tombstones every `tombstone_ttl`, for as long as
+    /// `this` is alive.
+    pub fn spawn_purge(this: &Arc<Self>) {
+        let this: Weak<Self> = Arc::downgrade(this);
+        tokio::spawn(async move {
+            let period = match this.upgrade() {
+                Some(this) => this.tombstone_ttl,
+                None => return,
+            };
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match this.upgrade() {
+                    Some(this) => {
+                        if let Err(e) = this.purge().await {
+                            log::warn!("failed to purge keyvalue tombstones: {e:?}");
+                        }
+                    }
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Removes the keys whose tombstone is past the retention window,
+    /// returning how many were removed.
+    pub async fn purge(&self) -> Result<u64> {
+        let mut purged = 0;
+        for key in self.inner.keys().await? {
+            if self
+                .tombstone(&key)
+                .await?
+                .map_or(false, |t| self.is_expired(t))
+            {
+                self.inner.delete(&key).await?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Returns when `key` was soft-deleted, if it was.
+    async fn tombstone(&self, key: &str) -> Result<Option<i64>> {
+        Ok(self.inner.get_metadata(key).await?.deleted_at)
+    }
+
+    fn is_expired(&self, deleted_at: i64) -> bool {
+        now_timestamp() - deleted_at >= self.tombstone_ttl.as_secs() as i64
+    }
+
+    async fn live_keys(&self, keys: Vec<String>) -> Result<Vec<String>> {
+        let mut live = vec![];
+        for key in keys {
+            if self.tombstone(&key).await?.is_none() {
+                live.push(key);
+            }
+        }
+        Ok(live)
+    }
+}
+
+#[async_trait]
+impl KeyvalueImplementor for SoftDeleteImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        self.inner.validate_key(key)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.get_consistent(key, false).await
+    }
+
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        let value = self.inner.get_consistent(key, strong).await?;
+        if self.tombstone(key).await?.is_some() {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        Ok(value)
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let value = self.inner.get_range(key, offset, len).await?;
+        if self.tombstone(key).await?.is_some() {
+            return Err(KeyvalueError::KeyNotFound(key.to_string()).into());
+        }
+        Ok(value)
+    }
+
+    /// Reads the key's metadata rather than its value, as a soft-deleted key
+    /// still holds its value along with a tombstone.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        match self.tombstone(key).await {
+            Ok(tombstone) => Ok(tombstone.is_none()),
+            Err(e) if is_key_not_found(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        // setting a value resets its metadata, which clears any tombstone
+        self.inner.set(key, value).await
+    }
+
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.inner.set_with_expiry(key, value, expiry).await
+    }
+
+    /// Tombstoned keys are excluded.
+    async fn keys(&self) -> Result<Vec<String>> {
+        let keys = self.inner.keys().await?;
+        self.live_keys(keys).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let mut metadata = match self.inner.get_metadata(key).await {
+            // there's nothing to tombstone, and deleting a missing key succeeds
+            // as it does without soft-delete
+            Err(e) if is_key_not_found(&e) => return Ok(()),
+            res => res?,
+        };
+        if metadata.deleted_at.is_none() {
+            metadata.deleted_at = Some(now_timestamp());
+            self.inner.set_metadata(key, &metadata).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        let (keys, cursor) = self.inner.list_keys(prefix, cursor, limit).await?;
+        Ok((self.live_keys(keys).await?, cursor))
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.inner.health().await
+    }
+
+    fn connection_status(&self) -> ConnectionStatus {
+        self.inner.connection_status()
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.inner.reconnect().await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        let keys = self.inner.keys_older_than(seconds).await?;
+        self.live_keys(keys).await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        let keys = self.inner.keys_by_index(value).await?;
+        self.live_keys(keys).await
+    }
+
+    async fn undelete(&self, key: &str) -> Result<()> {
+        let mut metadata = self.inner.get_metadata(key).await?;
+        match metadata.deleted_at {
+            Some(deleted_at) if !self.is_expired(deleted_at) => {
+                metadata.deleted_at = None;
+                self.inner.set_metadata(key, &metadata).await
+            }
+            // a key that was never deleted has nothing to restore
+            None => Ok(()),
+            Some(_) => Err(KeyvalueError::KeyNotFound(key.to_string()).into()),
+        }
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        self.inner.lock(key, ttl).await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        self.inner.unlock(key, token).await
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        (self.inner.capabilities() | KeyvalueCapabilities::UNDELETE)
+            - KeyvalueCapabilities::SCAN
+            - KeyvalueCapabilities::COMPARE_AND_SWAP
+            - KeyvalueCapabilities::WATCH
+            - KeyvalueCapabilities::INCREMENT
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.inner.get_metadata(key).await
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.inner.set_metadata(key, metadata).await
+    }
+}
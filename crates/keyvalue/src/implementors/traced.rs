@@ -0,0 +1,437 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use opentelemetry::{
+    propagation::TextMapPropagator,
+    sdk::propagation::TraceContextPropagator,
+    trace::{TraceContextExt, TraceId},
+    Context,
+};
+use sha2::{Digest, Sha256};
+use tracing::{field, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{
+    keyvalue::{ConnectionStatus, KeyvalueCapabilities, KeyvalueError, SetOutcome, VersionedValue},
+    metadata::KeyMetadata,
+    stream::{KeyvalueReadStreamImplementor, KeyvalueWriteStreamImplementor},
+};
+
+use super::{KeysPage, KeyvalueImplementor};
+#[cfg(feature = "kv-stream")]
+use crate::{scan::KeyvalueScanImplementor, watch::KeyvalueWatchImplementor};
+
+/// The trace context a guest passed with `set-trace-context`, which the spans
+/// of its later operations on the store are children of.
+#[derive(Debug, Default)]
+pub struct TraceContext {
+    context: Mutex<Option<Context>>,
+}
+
+impl TraceContext {
+    /// Replaces the trace context with the one of a W3C `traceparent` header
+    /// (e.g., `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`), along
+    /// with its `tracestate`, if any.
+    ///
+    /// Fails with `KeyvalueError::InvalidValue` if `traceparent` isn't valid.
+    pub fn set(&self, traceparent: &str, tracestate: Option<&str>) -> Result<()> {
+        let mut carrier = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+        if let Some(tracestate) = tracestate {
+            carrier.insert("tracestate".to_string(), tracestate.to_string());
+        }
+        let context = TraceContextPropagator::new().extract(&carrier);
+        if context.span().span_context().trace_id() == TraceId::INVALID {
+            return Err(KeyvalueError::InvalidValue(format!(
+                "'{traceparent}' is not a valid traceparent"
+            ))
+            .into());
+        }
+        *self.context.lock().unwrap() = Some(context);
+        Ok(())
+    }
+
+    fn get(&self) -> Option<Context> {
+        self.context.lock().unwrap().clone()
+    }
+}
+
+/// The histogram of how long operations take, in seconds, labeled by
+/// `operation`, `backend` (e.g., `keyvalue.redis`), `layer` (see `Layer`) and
+/// `result` (`ok` or `error`), as recorded through the `metrics` facade (and
+/// exported by slight as per its `SLIGHT_METRICS_ADDRESS`).
+pub const DURATION_METRIC: &str = "keyvalue_operation_duration_seconds";
+
+/// Where a `TracedImplementor` sits in the store's stack of wrappers.
+#[derive(Debug, Clone)]
+pub enum Layer {
+    /// Above every other wrapper, tracing the operations the guest calls.
+    /// Their spans are children of the guest's trace context, if it passed
+    /// one, and of the current span otherwise.
+    Operation(Arc<TraceContext>),
+    /// Right above the backend, tracing the calls that reach it, whose spans
+    /// are children of the span of the operation they are part of.
+    Backend,
+}
+
+impl Layer {
+    /// The `layer` label of the `DURATION_METRIC` samples recorded by it.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Operation(_) => "operation",
+            Self::Backend => "backend",
+        }
+    }
+}
+
+/// This is a wrapper around any `KeyvalueImplementor` that records a span for
+/// each of its operations, which `tracing-opentelemetry` exports (e.g., over
+/// OTLP, see slight's `OTEL_EXPORTER_OTLP_ENDPOINT`), along with its duration
+/// in the `DURATION_METRIC` histogram.
+///
+/// Spans are tagged with the backend (e.g., `keyvalue.redis`), the store name,
+/// the operation, and a hash and the length of the key rather than the key
+/// itself, which may be sensitive (values are never recorded). Once the
+/// operation is done, its span records how long it took and whether it
+/// failed, and failed operations are marked as errors. The span of a `scan`
+/// only covers starting it.
+#[derive(Debug, Clone)]
+pub struct TracedImplementor {
+    inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    layer: Layer,
+    backend: String,
+    store: String,
+}
+
+impl TracedImplementor {
+    pub fn new(
+        inner: Arc<dyn KeyvalueImplementor + Send + Sync>,
+        layer: Layer,
+        backend: &str,
+        store: &str,
+    ) -> Self {
+        Self {
+            inner,
+            layer,
+            backend: backend.to_string(),
+            store: store.to_string(),
+        }
+    }
+
+    fn span(&self, operation: &str, key: Option<&str>) -> Span {
+        let span = match &self.layer {
+            Layer::Operation(_) => tracing::info_span!(
+                "keyvalue",
+                otel.name = format!("keyvalue.{operation}"),
+                otel.status_code = field::Empty,
+                backend = self.backend.as_str(),
+                store = self.store.as_str(),
+                operation,
+                key_hash = field::Empty,
+                key_len = field::Empty,
+                elapsed_ms = field::Empty,
+                error = field::Empty,
+            ),
+            Layer::Backend => tracing::info_span!(
+                "keyvalue.backend",
+                otel.name = format!("{}.{operation}", self.backend),
+                otel.status_code = field::Empty,
+                backend = self.backend.as_str(),
+                store = self.store.as_str(),
+                operation,
+                key_hash = field::Empty,
+                key_len = field::Empty,
+                elapsed_ms = field::Empty,
+                error = field::Empty,
+            ),
+        };
+        if let Some(key) = key {
+            span.record("key_hash", key_hash(key).as_str());
+            span.record("key_len", key.len());
+        }
+        if let Layer::Operation(trace_context) = &self.layer {
+            if let Some(context) = trace_context.get() {
+                span.set_parent(context);
+            }
+        }
+        span
+    }
+
+    async fn traced<T>(
+        &self,
+        operation: &str,
+        key: Option<&str>,
+        op: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let span = self.span(operation, key);
+        let started = Instant::now();
+        let res = op.instrument(span.clone()).await;
+        let elapsed = started.elapsed();
+        span.record("elapsed_ms", elapsed.as_secs_f64() * 1000.0);
+        span.record("error", res.is_err());
+        if res.is_err() {
+            span.record("otel.status_code", "ERROR");
+        }
+        metrics::histogram!(
+            DURATION_METRIC,
+            elapsed.as_secs_f64(),
+            "operation" => operation.to_string(),
+            "backend" => self.backend.clone(),
+            "layer" => self.layer.name(),
+            "result" => if res.is_err() { "error" } else { "ok" },
+        );
+        res
+    }
+}
+
+/// The first 8 bytes of the SHA-256 of `key`, hex encoded, which tell spans on
+/// the same key apart without exporting it.
+fn key_hash(key: &str) -> String {
+    Sha256::digest(key.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[async_trait]
+impl KeyvalueImplementor for TracedImplementor {
+    fn validate_key(&self, key: &str) -> Result<()> {
+        self.inner.validate_key(key)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.traced("get", Some(key), self.inner.get(key)).await
+    }
+
+    async fn get_consistent(&self, key: &str, strong: bool) -> Result<Vec<u8>> {
+        self.traced(
+            "get_consistent",
+            Some(key),
+            self.inner.get_consistent(key, strong),
+        )
+        .await
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.traced(
+            "get_range",
+            Some(key),
+            self.inner.get_range(key, offset, len),
+        )
+        .await
+    }
+
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        self.traced("get_stream", Some(key), self.inner.get_stream(key))
+            .await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.traced("exists", Some(key), self.inner.exists(key))
+            .await
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.traced("set", Some(key), self.inner.set(key, value))
+            .await
+    }
+
+    async fn set_stream(
+        &self,
+        key: &str,
+    ) -> Result<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>> {
+        self.traced("set_stream", Some(key), self.inner.set_stream(key))
+            .await
+    }
+
+    async fn set_reporting(&self, key: &str, value: &[u8]) -> Result<SetOutcome> {
+        self.traced(
+            "set_reporting",
+            Some(key),
+            self.inner.set_reporting(key, value),
+        )
+        .await
+    }
+
+    async fn set_with_content_type(
+        &self,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<()> {
+        self.traced(
+            "set_with_content_type",
+            Some(key),
+            self.inner
+                .set_with_content_type(key, value, content_type, content_encoding),
+        )
+        .await
+    }
+
+    async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Duration) -> Result<()> {
+        self.traced(
+            "set_with_expiry",
+            Some(key),
+            self.inner.set_with_expiry(key, value, expiry),
+        )
+        .await
+    }
+
+    async fn keys(&self) -> Result<Vec<String>> {
+        self.traced("keys", None, self.inner.keys()).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.traced("delete", Some(key), self.inner.delete(key))
+            .await
+    }
+
+    async fn list_keys(&self, prefix: &str, cursor: Option<&str>, limit: u32) -> Result<KeysPage> {
+        self.traced(
+            "list_keys",
+            None,
+            self.inner.list_keys(prefix, cursor, limit),
+        )
+        .await
+    }
+
+    async fn get_bulk(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.traced("get_bulk", None, self.inner.get_bulk(keys))
+            .await
+    }
+
+    async fn set_bulk(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        self.traced("set_bulk", None, self.inner.set_bulk(pairs))
+            .await
+    }
+
+    async fn delete_bulk(&self, keys: &[&str]) -> Result<()> {
+        self.traced("delete_bulk", None, self.inner.delete_bulk(keys))
+            .await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.traced("clear", None, self.inner.clear()).await
+    }
+
+    async fn replace_all(&self, entries: &[(&str, &[u8])]) -> Result<()> {
+        self.traced("replace_all", None, self.inner.replace_all(entries))
+            .await
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.traced("health", None, self.inner.health()).await
+    }
+
+    fn connection_status(&self) -> ConnectionStatus {
+        self.inner.connection_status()
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.traced("reconnect", None, self.inner.reconnect()).await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64> {
+        self.traced("delete_prefix", None, self.inner.delete_prefix(prefix))
+            .await
+    }
+
+    #[cfg(feature = "kv-batch")]
+    async fn delete_prefix_page(&self, prefix: &str, limit: usize) -> Result<u64> {
+        self.traced(
+            "delete_prefix_page",
+            None,
+            self.inner.delete_prefix_page(prefix, limit),
+        )
+        .await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_older_than(&self, seconds: u64) -> Result<Vec<String>> {
+        self.traced("keys_older_than", None, self.inner.keys_older_than(seconds))
+            .await
+    }
+
+    #[cfg(feature = "kv-keys")]
+    async fn keys_by_index(&self, value: &str) -> Result<Vec<String>> {
+        self.traced("keys_by_index", None, self.inner.keys_by_index(value))
+            .await
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn scan(&self) -> Result<Box<dyn KeyvalueScanImplementor + Send + Sync>> {
+        self.traced("scan", None, self.inner.scan()).await
+    }
+
+    #[cfg(feature = "kv-stream")]
+    async fn watch(&self, key: &str) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+        self.traced("watch", Some(key), self.inner.watch(key)).await
+    }
+
+    async fn undelete(&self, key: &str) -> Result<()> {
+        self.traced("undelete", Some(key), self.inner.undelete(key))
+            .await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<String> {
+        self.traced("lock", Some(key), self.inner.lock(key, ttl))
+            .await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<()> {
+        self.traced("unlock", Some(key), self.inner.unlock(key, token))
+            .await
+    }
+
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<KeyMetadata> {
+        self.traced("get_metadata", Some(key), self.inner.get_metadata(key))
+            .await
+    }
+
+    async fn set_metadata(&self, key: &str, metadata: &KeyMetadata) -> Result<()> {
+        self.traced(
+            "set_metadata",
+            Some(key),
+            self.inner.set_metadata(key, metadata),
+        )
+        .await
+    }
+
+    async fn get_versioned(&self, key: &str) -> Result<VersionedValue> {
+        self.traced("get_versioned", Some(key), self.inner.get_versioned(key))
+            .await
+    }
+
+    async fn set_if_version(&self, key: &str, value: &[u8], expected: u64) -> Result<u64> {
+        self.traced(
+            "set_if_version",
+            Some(key),
+            self.inner.set_if_version(key, value, expected),
+        )
+        .await
+    }
+
+    async fn cas(&self, key: &str, expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        self.traced("cas", Some(key), self.inner.cas(key, expected, new))
+            .await
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        self.traced("increment", Some(key), self.inner.increment(key, delta))
+            .await
+    }
+}
@@ -1,23 +1,74 @@
-mod implementors;
+pub mod blocking;
+#[cfg(feature = "kv-batch")]
+pub mod delete_jobs;
+pub mod idempotency;
+pub mod implementors;
+pub mod metadata;
+pub mod observer;
 pub mod providers;
+pub mod scan;
+pub mod stream;
+pub mod watch;
 
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use idempotency::{IdempotencyTable, Outcome};
 use implementors::*;
 
 /// It is mandatory to `use <interface>::*` due to `impl_resource!`.
 /// That is because `impl_resource!` accesses the `crate`'s
 /// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
 use keyvalue::*;
+use observer::{KeyvalueObserver, OpenStore};
+use scan::KeyvalueScanInner;
 use slight_common::{impl_resource, BasicState};
-use slight_file::capability_store::CapabilityStore;
-use slight_file::resource::KeyvalueResource::*;
-use slight_file::Resource;
+use slight_file::capability_store::{CapabilityStore, ReloadableCapabilityStore};
+use slight_file::resource::KeyvalueResource::{self, *};
+use slight_file::{Resource, ResourceName};
+use slight_runtime_configs::maybe_get_from_state;
+use stream::{
+    BufferedReadStream, BufferedWriteStream, KeyvalueReadStreamImplementor,
+    KeyvalueReadStreamInner, KeyvalueWriteStreamInner,
+};
+use watch::KeyvalueWatchInner;
 wit_bindgen_wasmtime::export!({paths: ["../../wit/keyvalue.wit"], async: *});
 wit_error_rs::impl_error!(keyvalue::KeyvalueError);
-wit_error_rs::impl_from!(anyhow::Error, keyvalue::KeyvalueError::UnexpectedError);
+
+/// Implementors report errors as `anyhow::Error`s. To surface a specific
+/// `KeyvalueError` variant to the guest (e.g., `KeyNotFound`), an implementor
+/// returns that variant wrapped in the `anyhow::Error`. Backend errors returned
+/// as is are classified by `implementors::classify_error` (e.g., a refused
+/// connection becomes a `ConnectionError`), and anything else becomes an
+/// `UnexpectedError`.
+///
+/// Embedders can replace this mapping with `Keyvalue::with_error_mapper`.
+impl From<anyhow::Error> for keyvalue::KeyvalueError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<keyvalue::KeyvalueError>() {
+            Ok(e) => e,
+            Err(e) => implementors::classify_error(&e)
+                .unwrap_or_else(|| keyvalue::KeyvalueError::UnexpectedError(e.to_string())),
+        }
+    }
+}
+
+/// The built-in mapping of implementor errors to `KeyvalueError`s (see the
+/// `From<anyhow::Error>` implementation), for error mappers to fall back to.
+pub fn default_error_mapping(e: &anyhow::Error) -> KeyvalueError {
+    match e.downcast_ref::<KeyvalueError>() {
+        Some(e) => e.clone(),
+        None => implementors::classify_error(e)
+            .unwrap_or_else(|| KeyvalueError::UnexpectedError(e.to_string())),
+    }
+}
 
 /// The `Keyvalue` structure is what will implement the `keyvalue::Keyvalue` trait
 /// coming from the generated code of off `keyvalue.wit`.
@@ -26,22 +77,184 @@ wit_error_rs::impl_from!(anyhow::Error, keyvalue::KeyvalueError::UnexpectedError
 ///     - a `keyvalue_implementor` `String` — this comes directly from a
 ///     user's `slightfile` and it is what allows us to dynamically
 ///     dispatch to a specific implementor's implentation, and
-///     - the `slight_state` (of type `BasicState`) that contains common
+///     - the `capability_store` of the `BasicState`s that contain common
 ///     things received from the slight binary (i.e., the `config_type`
-///     and the `config_toml_file_path`).
+///     and the `config_toml_file_path`), which slight can reload at runtime
+///     (see `ReloadableCapabilityStore`), and
+///     - an optional `observer` of the stores' lifecycle,
+///     - an optional `global_prefix` prepended to the keys of every store, and
+///     - an optional `error_mapper` of the errors guests see, and
+///     - the `idempotency_tables` of the stores opened so far, by name, so
+///     that idempotency keys outlive the guest's handle to the store, and
+///     - the `delete_jobs` guests started, which outlive it too.
 #[derive(Clone, Default)]
 pub struct Keyvalue {
     implementor: Resource,
-    capability_store: CapabilityStore<BasicState>,
+    capability_store: ReloadableCapabilityStore<BasicState>,
+    observer: Option<Arc<dyn KeyvalueObserver + Send + Sync>>,
+    global_prefix: Option<String>,
+    error_mapper: Option<error_mapping::ErrorMapper>,
+    idempotency_tables: Arc<Mutex<HashMap<String, Arc<IdempotencyTable>>>>,
+    #[cfg(feature = "kv-batch")]
+    delete_jobs: Arc<delete_jobs::DeleteJobs>,
 }
 
 impl Keyvalue {
-    pub fn new(implementor: Resource, keyvalue_store: CapabilityStore<BasicState>) -> Self {
+    /// Stores are opened from `keyvalue_store` as it is when they're opened, so
+    /// passing a `ReloadableCapabilityStore` lets the next `keyvalue_open` of a
+    /// store pick up a reloaded config, while the stores already open keep
+    /// their implementor.
+    pub fn new(
+        implementor: Resource,
+        keyvalue_store: impl Into<ReloadableCapabilityStore<BasicState>>,
+    ) -> Self {
         Self {
             implementor,
-            capability_store: keyvalue_store,
+            capability_store: keyvalue_store.into(),
+            observer: None,
+            global_prefix: None,
+            error_mapper: None,
+            idempotency_tables: Arc::default(),
+            #[cfg(feature = "kv-batch")]
+            delete_jobs: Arc::default(),
         }
     }
+
+    /// Registers an observer to be told when guests open and close stores (see
+    /// `observer::KeyvalueObserver`).
+    pub fn with_observer(mut self, observer: Arc<dyn KeyvalueObserver + Send + Sync>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Prepends `prefix` to every key of every store before it reaches the
+    /// backend, and strips it from listed keys (see
+    /// `prefixed::PrefixedImplementor`), so that deployments sharing a backend
+    /// don't collide.
+    pub fn with_global_prefix(mut self, prefix: String) -> Self {
+        self.global_prefix = Some(prefix);
+        self
+    }
+
+    /// Maps the errors of every store's implementor with `error_mapper`, rather
+    /// than the built-in mapping (see `default_error_mapping`), e.g., to treat
+    /// a backend error as retryable in one deployment but not in another.
+    pub fn with_error_mapper(mut self, error_mapper: error_mapping::ErrorMapper) -> Self {
+        self.error_mapper = Some(error_mapper);
+        self
+    }
+
+    fn map_error(&self, e: anyhow::Error) -> KeyvalueError {
+        match &self.error_mapper {
+            Some(error_mapper) => error_mapper(&e),
+            None => e.into(),
+        }
+    }
+
+    /// Opens every keyvalue capability of the slightfile and runs its `health`
+    /// check, without running the guest (e.g., as a pre-deploy step catching bad
+    /// credentials or unreachable endpoints), as `slight validate` does.
+    ///
+    /// Returns the outcome for each capability, after logging a pass/fail
+    /// summary. Implementors that fail to even be constructed (e.g., on bad
+    /// configs, or when they aren't compiled into the host) are reported as
+    /// failing too.
+    pub async fn validate_all(&self) -> Vec<(String, Result<(), KeyvalueError>)> {
+        let capability_store = self.capability_store.load();
+        let mut capabilities = (*capability_store)
+            .as_ref()
+            .get("keyvalue")
+            .map(|resources| {
+                resources
+                    .iter()
+                    .map(|(name, state)| match name {
+                        ResourceName::Specific(name) => (name.clone(), state.clone()),
+                        ResourceName::Any => (state.implementor.to_string(), state.clone()),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        capabilities.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut outcomes = vec![];
+        for (name, state) in capabilities {
+            let capability = name.clone();
+            let global_prefix = self.global_prefix.clone();
+            let outcome = tokio::spawn(async move {
+                let inner = KeyvalueInner::new(
+                    KeyvalueImplementors::try_from(state.implementor)?,
+                    &state,
+                    &capability,
+                    global_prefix.as_deref(),
+                )
+                .await?;
+                inner.keyvalue_implementor.health().await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to open the store: {e}"))
+            .and_then(|outcome| outcome)
+            .map_err(|e| self.map_error(e));
+            match &outcome {
+                Ok(()) => tracing::log::info!("keyvalue capability '{name}': pass"),
+                Err(e) => tracing::log::error!("keyvalue capability '{name}': fail ({e})"),
+            }
+            outcomes.push((name, outcome));
+        }
+        let failed = outcomes.iter().filter(|(_, o)| o.is_err()).count();
+        tracing::log::info!(
+            "validated {} keyvalue capabilities: {} passed, {failed} failed",
+            outcomes.len(),
+            outcomes.len() - failed
+        );
+        outcomes
+    }
+
+    /// Builds the `ChainImplementor` of a store whose capability sets `CHAIN`, a
+    /// comma-separated list of other keyvalue capabilities to fall back to, in
+    /// order, after the store's own implementor.
+    ///
+    /// `CHAIN_WRITE` selects the links that receive writes: `first` (the default)
+    /// or `all`, and `CHAIN_CONCURRENCY` bounds how many links are listed at once
+    /// by `keys` (defaults to `chain::DEFAULT_CONCURRENCY`).
+    async fn chained(
+        &self,
+        first: Arc<dyn KeyvalueImplementor + Send + Sync>,
+        chain: &str,
+        slight_state: &BasicState,
+        capability_store: &CapabilityStore<BasicState>,
+    ) -> Result<Arc<dyn KeyvalueImplementor + Send + Sync>> {
+        let mut links = vec![first];
+        for link in chain.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+            let state = capability_store.get(link, "keyvalue").with_context(|| {
+                format!("CHAIN lists '{link}', which is not a keyvalue capability")
+            })?;
+            let inner = KeyvalueInner::new(
+                KeyvalueImplementors::try_from(state.implementor)?,
+                state,
+                link,
+                self.global_prefix.as_deref(),
+            )
+            .await?;
+            links.push(inner.keyvalue_implementor);
+        }
+        let write = match maybe_get_from_state("CHAIN_WRITE", slight_state)
+            .await?
+            .as_deref()
+        {
+            None | Some("first") => chain::ChainWrite::First,
+            Some("all") => chain::ChainWrite::All,
+            Some(other) => bail!("CHAIN_WRITE must be either 'first' or 'all', got '{other}'"),
+        };
+        let concurrency = match maybe_get_from_state("CHAIN_CONCURRENCY", slight_state).await? {
+            Some(concurrency) => concurrency
+                .parse()
+                .with_context(|| "CHAIN_CONCURRENCY must be a positive number")?,
+            None => chain::DEFAULT_CONCURRENCY,
+        };
+        Ok(Arc::new(
+            chain::ChainImplementor::new(links, write).with_concurrency(concurrency),
+        ))
+    }
 }
 
 /// This is the type of the associated type coming from the `keyvalue::Keyvalue` trait
@@ -62,6 +275,13 @@ impl Keyvalue {
 #[derive(Clone, Debug)]
 pub struct KeyvalueInner {
     keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    /// Tells the observer, if any, that the store was closed once the last
+    /// clone is dropped.
+    open_store: Option<Arc<OpenStore>>,
+    missing_key_behavior: MissingKeyBehavior,
+    max_value_size: Option<u64>,
+    idempotency: Arc<IdempotencyTable>,
+    trace_context: Arc<traced::TraceContext>,
 }
 
 impl KeyvalueInner {
@@ -69,30 +289,381 @@ impl KeyvalueInner {
         keyvalue_implementor: KeyvalueImplementors,
         slight_state: &BasicState,
         name: &str,
-    ) -> Self {
-        Self {
-            keyvalue_implementor: match keyvalue_implementor {
+        global_prefix: Option<&str>,
+    ) -> Result<Self> {
+        let keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync> =
+            match keyvalue_implementor {
                 #[cfg(feature = "filesystem")]
                 KeyvalueImplementors::Filesystem => {
-                    Arc::new(filesystem::FilesystemImplementor::new(slight_state, name).await)
+                    Arc::new(filesystem::FilesystemImplementor::new(slight_state, name).await?)
                 }
                 #[cfg(feature = "azblob")]
                 KeyvalueImplementors::AzBlob => {
-                    Arc::new(azblob::AzBlobImplementor::new(slight_state, name).await)
+                    Arc::new(azblob::AzBlobImplementor::new(slight_state, name).await?)
                 }
                 #[cfg(feature = "awsdynamodb")]
                 KeyvalueImplementors::AwsDynamoDb => {
-                    Arc::new(awsdynamodb::AwsDynamoDbImplementor::new(slight_state, name).await)
+                    Arc::new(awsdynamodb::AwsDynamoDbImplementor::new(slight_state, name).await?)
                 }
                 #[cfg(feature = "redis")]
                 KeyvalueImplementors::Redis => {
-                    Arc::new(redis::RedisImplementor::new(slight_state, name).await)
+                    Arc::new(redis::RedisImplementor::new(slight_state, name).await?)
+                }
+                #[cfg(feature = "firestore")]
+                KeyvalueImplementors::Firestore => {
+                    Arc::new(firestore::FirestoreImplementor::new(slight_state, name).await?)
+                }
+                #[cfg(feature = "gcpstorage")]
+                KeyvalueImplementors::GcpStorage => {
+                    Arc::new(gcpstorage::GcpStorageImplementor::new(slight_state, name).await?)
+                }
+                #[cfg(feature = "inmemory")]
+                KeyvalueImplementors::InMemory => {
+                    Arc::new(inmemory::InMemoryImplementor::new(name))
+                }
+                #[cfg(feature = "mongodb")]
+                KeyvalueImplementors::MongoDb => {
+                    Arc::new(mongodb::MongoDbImplementor::new(slight_state, name).await?)
+                }
+                #[cfg(feature = "null")]
+                KeyvalueImplementors::Null => Arc::new(null::NullImplementor::new(name)),
+                #[cfg(feature = "postgres")]
+                KeyvalueImplementors::Postgres => {
+                    Arc::new(postgres::PostgresImplementor::new(slight_state, name).await?)
+                }
+                #[cfg(feature = "proxy")]
+                KeyvalueImplementors::Proxy => {
+                    Arc::new(proxy::ProxyImplementor::new(slight_state, name).await?)
                 }
+            };
+        let keyvalue_implementor = Arc::new(traced::TracedImplementor::new(
+            keyvalue_implementor,
+            traced::Layer::Backend,
+            &slight_state.implementor.to_string(),
+            name,
+        ));
+        // retries go right above the backend's spans, so that each attempt is
+        // traced
+        let keyvalue_implementor = with_retry(keyvalue_implementor, slight_state).await?;
+        // the global prefix goes right above the backend, so that no other
+        // wrapper (e.g., soft-delete's purge) sees another deployment's keys
+        let keyvalue_implementor = match global_prefix {
+            Some(prefix) => Arc::new(prefixed::PrefixedImplementor::new(
+                keyvalue_implementor,
+                prefix.to_string(),
+            )),
+            None => keyvalue_implementor,
+        };
+        let keyvalue_implementor = with_key_prefix(keyvalue_implementor, slight_state).await?;
+
+        let keyvalue_implementor = with_integrity(keyvalue_implementor, slight_state).await?;
+
+        Ok(Self {
+            keyvalue_implementor: with_soft_delete(keyvalue_implementor, slight_state).await?,
+            open_store: None,
+            missing_key_behavior: MissingKeyBehavior::from_state(slight_state).await?,
+            max_value_size: max_value_size(slight_state).await?,
+            idempotency: Arc::new(IdempotencyTable::from_state(slight_state).await?),
+            trace_context: Arc::new(traced::TraceContext::default()),
+        })
+    }
+
+    /// The operations of the store's implementor that are compiled in (see
+    /// `compiled_capabilities`).
+    fn capabilities(&self) -> KeyvalueCapabilities {
+        self.keyvalue_implementor.capabilities() & compiled_capabilities()
+    }
+
+    /// Gets the value of `key`, answering a missing key as per the store's
+    /// `MissingKeyBehavior`.
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.missing_key_behavior
+            .apply(self.keyvalue_implementor.get(key).await)
+    }
+
+    /// Opens a stream reading the value of `key`, answering a missing key as
+    /// per the store's `MissingKeyBehavior`.
+    async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>> {
+        match self.keyvalue_implementor.get_stream(key).await {
+            Err(e) if is_key_not_found(&e) => Ok(Box::new(BufferedReadStream::new(
+                self.missing_key_behavior.apply(Err(e))?,
+            ))),
+            res => res,
+        }
+    }
+
+    /// Fails with `KeyvalueError::InvalidValue` if `value` is over the store's
+    /// `MAX_VALUE_SIZE`, which only values written with `set-stream` may be.
+    fn check_value_size(&self, key: &str, value: &[u8]) -> Result<(), KeyvalueError> {
+        match self.max_value_size {
+            Some(max) if value.len() as u64 > max => Err(KeyvalueError::InvalidValue(format!(
+                "the value of '{key}' is {} bytes, over the store's MAX_VALUE_SIZE of {max} bytes; write it with set-stream instead",
+                value.len()
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The largest value (in bytes) the non-streaming `set`s of a store accept, as
+/// set by the capability's `MAX_VALUE_SIZE` config, so that large values go
+/// through `set-stream` rather than through a single buffer in and out of the
+/// guest's memory. There's no limit by default.
+async fn max_value_size(slight_state: &BasicState) -> Result<Option<u64>> {
+    maybe_get_from_state("MAX_VALUE_SIZE", slight_state)
+        .await?
+        .map(|s| {
+            s.parse()
+                .with_context(|| format!("MAX_VALUE_SIZE must be a number of bytes, got '{s}'"))
+        })
+        .transpose()
+}
+
+/// What `get` answers for a key that doesn't exist, as set by the capability's
+/// `MISSING_KEY_BEHAVIOR` config:
+///   - `error` (the default) — `KeyvalueError::KeyNotFound`,
+///   - `empty` — an empty value, or
+///   - any other string — that string, as the value.
+///
+/// Guests that relied on a backend answering missing keys with an empty value
+/// should pin `MISSING_KEY_BEHAVIOR = "empty"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MissingKeyBehavior {
+    #[default]
+    Error,
+    Empty,
+    Default(Vec<u8>),
+}
+
+impl MissingKeyBehavior {
+    async fn from_state(slight_state: &BasicState) -> Result<Self> {
+        Ok(
+            match maybe_get_from_state("MISSING_KEY_BEHAVIOR", slight_state)
+                .await?
+                .as_deref()
+            {
+                None | Some("error") => Self::Error,
+                Some("empty") => Self::Empty,
+                Some(value) => Self::Default(value.as_bytes().to_vec()),
             },
+        )
+    }
+
+    fn apply(&self, res: Result<Vec<u8>>) -> Result<Vec<u8>> {
+        match (self, res) {
+            (Self::Empty, Err(e)) if is_key_not_found(&e) => Ok(vec![]),
+            (Self::Default(value), Err(e)) if is_key_not_found(&e) => Ok(value.clone()),
+            (_, res) => res,
         }
     }
 }
 
+/// Wraps `keyvalue_implementor` in a `RetryingImplementor` if the capability
+/// sets `RETRY_MAX_ATTEMPTS` (how many times an operation is attempted,
+/// defaults to `retrying::DEFAULT_MAX_ATTEMPTS`) or `RETRY_BASE_DELAY_MS`
+/// (the delay before the first retry, defaults to
+/// `retrying::DEFAULT_BASE_DELAY`).
+async fn with_retry(
+    keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    slight_state: &BasicState,
+) -> Result<Arc<dyn KeyvalueImplementor + Send + Sync>> {
+    let max_attempts = maybe_get_from_state("RETRY_MAX_ATTEMPTS", slight_state)
+        .await?
+        .map(|s| {
+            s.parse::<u32>()
+                .ok()
+                .filter(|max_attempts| *max_attempts > 0)
+                .with_context(|| format!("RETRY_MAX_ATTEMPTS must be a positive number, got '{s}'"))
+        })
+        .transpose()?;
+    let base_delay = maybe_get_from_state("RETRY_BASE_DELAY_MS", slight_state)
+        .await?
+        .map(|s| {
+            s.parse().map(Duration::from_millis).with_context(|| {
+                format!("RETRY_BASE_DELAY_MS must be a number of milliseconds, got '{s}'")
+            })
+        })
+        .transpose()?;
+    if max_attempts.is_none() && base_delay.is_none() {
+        return Ok(keyvalue_implementor);
+    }
+
+    let default = retrying::RetryPolicy::default();
+    Ok(Arc::new(retrying::RetryingImplementor::new(
+        keyvalue_implementor,
+        retrying::RetryPolicy {
+            max_attempts: max_attempts.unwrap_or(default.max_attempts),
+            base_delay: base_delay.unwrap_or(default.base_delay),
+        },
+    )))
+}
+
+/// Wraps `keyvalue_implementor` in a `PrefixedImplementor` if the capability
+/// sets `KEY_PREFIX`, which is prepended to the store's keys as `<prefix>:`,
+/// so that apps sharing a backend (e.g., a redis server, or a DynamoDB table)
+/// don't collide. It goes beneath the deployment's global prefix, if any.
+async fn with_key_prefix(
+    keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    slight_state: &BasicState,
+) -> Result<Arc<dyn KeyvalueImplementor + Send + Sync>> {
+    Ok(
+        match maybe_get_from_state("KEY_PREFIX", slight_state)
+            .await?
+            .filter(|prefix| !prefix.is_empty())
+        {
+            Some(prefix) => Arc::new(prefixed::PrefixedImplementor::new(
+                keyvalue_implementor,
+                format!("{prefix}:"),
+            )),
+            None => keyvalue_implementor,
+        },
+    )
+}
+
+/// Wraps `keyvalue_implementor` in an `IntegrityImplementor` if the capability
+/// sets `INTEGRITY`, the checksum algorithm (`crc32` or `sha256`).
+async fn with_integrity(
+    keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    slight_state: &BasicState,
+) -> Result<Arc<dyn KeyvalueImplementor + Send + Sync>> {
+    Ok(
+        match maybe_get_from_state("INTEGRITY", slight_state).await? {
+            Some(algorithm) => Arc::new(integrity::IntegrityImplementor::new(
+                keyvalue_implementor,
+                integrity::Checksum::parse(&algorithm)?,
+            )),
+            None => keyvalue_implementor,
+        },
+    )
+}
+
+/// Wraps `keyvalue_implementor` in a `SoftDeleteImplementor` if the capability
+/// sets `SOFT_DELETE = "true"`, with a retention window of `TOMBSTONE_TTL`
+/// seconds (defaults to `soft_delete::DEFAULT_TOMBSTONE_TTL`).
+async fn with_soft_delete(
+    keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    slight_state: &BasicState,
+) -> Result<Arc<dyn KeyvalueImplementor + Send + Sync>> {
+    let soft_delete = maybe_get_from_state("SOFT_DELETE", slight_state)
+        .await?
+        .map(|s| {
+            s.parse::<bool>()
+                .with_context(|| format!("SOFT_DELETE must be either 'true' or 'false', got '{s}'"))
+        })
+        .transpose()?
+        .unwrap_or_default();
+    if !soft_delete {
+        return Ok(keyvalue_implementor);
+    }
+
+    let soft_delete = Arc::new(soft_delete::SoftDeleteImplementor::new(
+        keyvalue_implementor,
+        tombstone_ttl(slight_state).await?,
+    ));
+    soft_delete::SoftDeleteImplementor::spawn_purge(&soft_delete);
+    Ok(soft_delete)
+}
+
+/// Reads the `TOMBSTONE_TTL` config, which must be positive, as it is also the
+/// period of the purge.
+async fn tombstone_ttl(slight_state: &BasicState) -> Result<Duration> {
+    Ok(maybe_get_from_state("TOMBSTONE_TTL", slight_state)
+        .await?
+        .map(|s| {
+            s.parse::<u64>()
+                .ok()
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs)
+                .with_context(|| {
+                    format!("TOMBSTONE_TTL must be a positive number of seconds, got '{s}'")
+                })
+        })
+        .transpose()?
+        .unwrap_or(soft_delete::DEFAULT_TOMBSTONE_TTL))
+}
+
+/// Wraps `keyvalue_implementor` in an `AllowListImplementor` if the capability
+/// sets `ALLOWED_PREFIXES`, a comma-separated list of the key prefixes guests
+/// may access.
+pub(crate) async fn with_allowed_prefixes(
+    keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    slight_state: &BasicState,
+) -> Result<Arc<dyn KeyvalueImplementor + Send + Sync>> {
+    Ok(
+        match maybe_get_from_state("ALLOWED_PREFIXES", slight_state).await? {
+            Some(prefixes) => Arc::new(allow_list::AllowListImplementor::new(
+                keyvalue_implementor,
+                prefixes
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|prefix| !prefix.is_empty())
+                    .map(String::from)
+                    .collect(),
+            )),
+            None => keyvalue_implementor,
+        },
+    )
+}
+
+/// Wraps `keyvalue_implementor` in a `NormalizedImplementor` if the capability
+/// sets `KEY_NORMALIZATION` or `KEY_TEMPLATE` (see `normalized::KeyNormalization`).
+pub(crate) async fn with_normalized_keys(
+    keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    slight_state: &BasicState,
+) -> Result<Arc<dyn KeyvalueImplementor + Send + Sync>> {
+    let steps = maybe_get_from_state("KEY_NORMALIZATION", slight_state).await?;
+    let template = maybe_get_from_state("KEY_TEMPLATE", slight_state).await?;
+    let normalization = normalized::KeyNormalization::parse(steps.as_deref(), template.as_deref())?;
+    if normalization.is_identity() {
+        return Ok(keyvalue_implementor);
+    }
+    Ok(Arc::new(normalized::NormalizedImplementor::new(
+        keyvalue_implementor,
+        normalization,
+    )))
+}
+
+/// Serves the store of `keyvalue_implementor` to remote `proxy` stores if the
+/// capability sets `PROXY_LISTEN` (e.g., `127.0.0.1:50051`), as exposed to
+/// guests (i.e., with every wrapper). The store keeps being served once the
+/// guest closes it.
+///
+/// The proxy has no authentication nor TLS (see `proxy::ProxyService`), so
+/// `PROXY_LISTEN` must be a loopback address unless the capability also sets
+/// `PROXY_ALLOW_REMOTE = "true"`, which exposes the store to every host that
+/// can reach the address.
+#[cfg(feature = "proxy")]
+async fn serve_proxy(
+    keyvalue_implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    slight_state: &BasicState,
+) -> Result<()> {
+    if let Some(addr) = maybe_get_from_state("PROXY_LISTEN", slight_state).await? {
+        let addr: std::net::SocketAddr = addr
+            .parse()
+            .with_context(|| format!("failed to parse PROXY_LISTEN '{addr}'"))?;
+        let allow_remote = maybe_get_from_state("PROXY_ALLOW_REMOTE", slight_state)
+            .await?
+            .map(|s| {
+                s.parse::<bool>().with_context(|| {
+                    format!("PROXY_ALLOW_REMOTE must be either 'true' or 'false', got '{s}'")
+                })
+            })
+            .transpose()?
+            .unwrap_or_default();
+        if !addr.ip().is_loopback() && !allow_remote {
+            bail!(
+                "PROXY_LISTEN '{addr}' isn't a loopback address, and the proxy has no \
+                 authentication nor TLS; set PROXY_ALLOW_REMOTE = \"true\" to serve it anyway"
+            );
+        }
+        proxy::ProxyService::new(keyvalue_implementor).spawn_once(addr)?;
+    }
+    Ok(())
+}
+
 /// This defines the available implementor implementations for the `Keyvalue` interface.
 ///
 /// As per its' usage in `KeyvalueInner`, it must `derive` `Debug`, and `Clone`.
@@ -107,11 +678,30 @@ pub enum KeyvalueImplementors {
     AwsDynamoDb,
     #[cfg(feature = "redis")]
     Redis,
+    #[cfg(feature = "firestore")]
+    Firestore,
+    #[cfg(feature = "gcpstorage")]
+    GcpStorage,
+    #[cfg(feature = "inmemory")]
+    InMemory,
+    #[cfg(feature = "mongodb")]
+    MongoDb,
+    #[cfg(feature = "null")]
+    Null,
+    #[cfg(feature = "postgres")]
+    Postgres,
+    #[cfg(feature = "proxy")]
+    Proxy,
 }
 
-impl From<Resource> for KeyvalueImplementors {
-    fn from(s: Resource) -> Self {
-        match s {
+impl TryFrom<Resource> for KeyvalueImplementors {
+    type Error = KeyvalueError;
+
+    /// Fails with `KeyvalueError::UnexpectedError` for resources that aren't
+    /// keyvalue implementors compiled into this host (e.g., a backend whose
+    /// feature is off).
+    fn try_from(s: Resource) -> Result<Self, Self::Error> {
+        Ok(match s {
             #[cfg(feature = "filesystem")]
             Resource::Keyvalue(Filesystem) | Resource::Keyvalue(V1Filesystem) => Self::Filesystem,
             #[cfg(feature = "azblob")]
@@ -122,13 +712,60 @@ impl From<Resource> for KeyvalueImplementors {
             }
             #[cfg(feature = "redis")]
             Resource::Keyvalue(Redis) | Resource::Keyvalue(V1Redis) => Self::Redis,
-            p => panic!(
-                "failed to match provided name (i.e., '{p}') to any known host implementations"
-            ),
-        }
+            #[cfg(feature = "firestore")]
+            Resource::Keyvalue(Firestore) => Self::Firestore,
+            #[cfg(feature = "gcpstorage")]
+            Resource::Keyvalue(GcpStorage) => Self::GcpStorage,
+            #[cfg(feature = "inmemory")]
+            Resource::Keyvalue(InMemory) => Self::InMemory,
+            #[cfg(feature = "mongodb")]
+            Resource::Keyvalue(MongoDb) => Self::MongoDb,
+            #[cfg(feature = "null")]
+            Resource::Keyvalue(Null) => Self::Null,
+            #[cfg(feature = "postgres")]
+            Resource::Keyvalue(Postgres) => Self::Postgres,
+            #[cfg(feature = "proxy")]
+            Resource::Keyvalue(Proxy) => Self::Proxy,
+            p => {
+                return Err(KeyvalueError::UnexpectedError(format!(
+                    "failed to match provided name (i.e., '{p}') to any known host implementations (i.e., {})",
+                    known_implementors().join(", ")
+                )))
+            }
+        })
     }
 }
 
+/// The names of the keyvalue implementors compiled into this host, as written
+/// in slightfiles.
+pub fn known_implementors() -> Vec<String> {
+    let known: &[KeyvalueResource] = &[
+        #[cfg(feature = "filesystem")]
+        Filesystem,
+        #[cfg(feature = "azblob")]
+        Azblob,
+        #[cfg(feature = "awsdynamodb")]
+        AwsDynamoDb,
+        #[cfg(feature = "redis")]
+        Redis,
+        #[cfg(feature = "firestore")]
+        Firestore,
+        #[cfg(feature = "gcpstorage")]
+        GcpStorage,
+        #[cfg(feature = "inmemory")]
+        InMemory,
+        #[cfg(feature = "mongodb")]
+        MongoDb,
+        #[cfg(feature = "null")]
+        Null,
+        #[cfg(feature = "postgres")]
+        Postgres,
+        #[cfg(feature = "proxy")]
+        Proxy,
+    ];
+    known.iter().map(ToString::to_string).collect()
+}
+
 // This implements the `CapabilityBuilder`, and `Capability` trait
 // for our `Keyvalue` `struct`, and `CapabilityIndexTable` for our `keyvalue::KeyvalueTables` object.
 //
@@ -149,26 +786,89 @@ impl_resource!(
 #[async_trait]
 impl keyvalue::Keyvalue for Keyvalue {
     type Keyvalue = KeyvalueInner;
+    type KeyvalueReadStream = KeyvalueReadStreamInner;
+    type KeyvalueWriteStream = KeyvalueWriteStreamInner;
+    type KeyvalueScan = KeyvalueScanInner;
+    type KeyvalueWatch = KeyvalueWatchInner;
 
     async fn keyvalue_open(&mut self, name: &str) -> Result<Self::Keyvalue, KeyvalueError> {
         // populate our inner keyvalue object w/ the state received from `slight`
         // (i.e., what type of keyvalue implementor we are using), and the assigned
-        // name of the object.
-        let s = self.implementor.to_string();
-        let state = if let Some(r) = self.capability_store.get(name, "keyvalue") {
-            r.clone()
-        } else if let Some(r) = self.capability_store.get(&s, "keyvalue") {
-            r.clone()
-        } else {
-            panic!(
-                "could not find capability under name '{}' for implementor '{}'",
-                name, &s
-            );
-        };
+        // name of the object. The store (and the links of its chain) are
+        // resolved from a single snapshot, so that a concurrent reload can't
+        // mix the old config with the new one.
+        let capability_store = self.capability_store.load();
+        let state = BasicState::resolve(
+            &capability_store,
+            name,
+            &self.implementor.to_string(),
+            "keyvalue",
+        )?;
 
         tracing::log::info!("Opening implementor {}", &state.implementor);
 
-        let inner = Self::Keyvalue::new(state.implementor.into(), &state, name).await;
+        let implementor = state.implementor.to_string();
+        let keyvalue_implementor = KeyvalueImplementors::try_from(state.implementor)?;
+        // the guard is created as soon as `on_open` succeeds, so that an open
+        // that fails past this point still reports `on_close` when it's dropped
+        let open_store = match &self.observer {
+            Some(observer) => {
+                observer.on_open(name, &implementor)?;
+                Some(Arc::new(OpenStore::new(
+                    observer.clone(),
+                    name,
+                    &implementor,
+                )))
+            }
+            None => None,
+        };
+        let mut inner = Self::Keyvalue::new(
+            keyvalue_implementor,
+            &state,
+            name,
+            self.global_prefix.as_deref(),
+        )
+        .await?;
+        if let Some(chain) = maybe_get_from_state("CHAIN", &state).await? {
+            inner.keyvalue_implementor = self
+                .chained(
+                    inner.keyvalue_implementor,
+                    &chain,
+                    &state,
+                    &capability_store,
+                )
+                .await?;
+        }
+        // applied last, so that the keys of every link of a chain are restricted
+        inner.keyvalue_implementor =
+            with_allowed_prefixes(inner.keyvalue_implementor, &state).await?;
+        // above the allow-list, so that it checks the keys as normalized
+        inner.keyvalue_implementor =
+            with_normalized_keys(inner.keyvalue_implementor, &state).await?;
+        if let Some(error_mapper) = &self.error_mapper {
+            inner.keyvalue_implementor = Arc::new(error_mapping::ErrorMappingImplementor::new(
+                inner.keyvalue_implementor,
+                error_mapper.clone(),
+            ));
+        }
+        #[cfg(feature = "proxy")]
+        serve_proxy(inner.keyvalue_implementor.clone(), &state).await?;
+        // above every other wrapper, so that the spans of the backend calls an
+        // operation makes are children of the operation's span
+        inner.keyvalue_implementor = Arc::new(traced::TracedImplementor::new(
+            inner.keyvalue_implementor,
+            traced::Layer::Operation(inner.trace_context.clone()),
+            &implementor,
+            name,
+        ));
+        inner.idempotency = self
+            .idempotency_tables
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| inner.idempotency.clone())
+            .clone();
+        inner.open_store = open_store;
 
         Ok(inner)
     }
@@ -178,7 +878,52 @@ impl keyvalue::Keyvalue for Keyvalue {
         self_: &Self::Keyvalue,
         key: &str,
     ) -> Result<Vec<u8>, KeyvalueError> {
-        Ok(self_.keyvalue_implementor.get(key).await?)
+        self_.keyvalue_implementor.validate_key(key)?;
+        Ok(self_.get(key).await?)
+    }
+
+    async fn keyvalue_get_consistent(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        strong: bool,
+    ) -> Result<Vec<u8>, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        Ok(self_
+            .missing_key_behavior
+            .apply(self_.keyvalue_implementor.get_consistent(key, strong).await)?)
+    }
+
+    async fn keyvalue_get_range(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        Ok(self_
+            .keyvalue_implementor
+            .get_range(key, offset, len)
+            .await?)
+    }
+
+    async fn keyvalue_get_stream(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+    ) -> Result<Self::KeyvalueReadStream, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        Ok(KeyvalueReadStreamInner::new(self_.get_stream(key).await?))
+    }
+
+    async fn keyvalue_exists(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+    ) -> Result<bool, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        Ok(self_.keyvalue_implementor.exists(key).await?)
     }
 
     async fn keyvalue_set(
@@ -187,15 +932,257 @@ impl keyvalue::Keyvalue for Keyvalue {
         key: &str,
         value: &[u8],
     ) -> Result<(), KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.check_value_size(key, value)?;
         self_.keyvalue_implementor.set(key, value).await?;
         Ok(())
     }
 
+    async fn keyvalue_set_stream(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+    ) -> Result<Self::KeyvalueWriteStream, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        let implementor = match self_.keyvalue_implementor.set_stream(key).await? {
+            Some(implementor) => implementor,
+            None => Box::new(BufferedWriteStream::new(
+                self_.keyvalue_implementor.clone(),
+                key,
+            )),
+        };
+        Ok(KeyvalueWriteStreamInner::new(key, implementor))
+    }
+
+    async fn keyvalue_set_reporting(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        value: &[u8],
+    ) -> Result<SetOutcome, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.check_value_size(key, value)?;
+        Ok(self_.keyvalue_implementor.set_reporting(key, value).await?)
+    }
+
+    async fn keyvalue_set_with_expiry(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        value: &[u8],
+        expiry_seconds: u64,
+    ) -> Result<(), KeyvalueError> {
+        ensure_supported(self_, Operation::SetWithExpiry)?;
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.check_value_size(key, value)?;
+        if expiry_seconds == 0 {
+            return Err(KeyvalueError::InvalidValue(
+                "expiry must be at least 1 second".to_string(),
+            ));
+        }
+        self_
+            .keyvalue_implementor
+            .set_with_expiry(key, value, Duration::from_secs(expiry_seconds))
+            .await?;
+        Ok(())
+    }
+
+    async fn keyvalue_set_with_content_type(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        value: &[u8],
+        content_type: &str,
+        content_encoding: Option<&str>,
+    ) -> Result<(), KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.check_value_size(key, value)?;
+        if content_type.is_empty() || content_encoding == Some("") {
+            return Err(KeyvalueError::InvalidValue(
+                "content type and encoding must not be empty".to_string(),
+            ));
+        }
+        self_
+            .keyvalue_implementor
+            .set_with_content_type(key, value, content_type, content_encoding)
+            .await?;
+        Ok(())
+    }
+
+    async fn keyvalue_stat(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+    ) -> Result<KeyStat, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        let metadata = self_.keyvalue_implementor.get_metadata(key).await?;
+        Ok(KeyStat {
+            content_type: metadata.content_type,
+            content_encoding: metadata.content_encoding,
+            deleted_at: metadata.deleted_at,
+        })
+    }
+
+    async fn keyvalue_set_idempotent(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        value: &[u8],
+        idempotency_key: &str,
+    ) -> Result<(), KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.check_value_size(key, value)?;
+        self_
+            .idempotency
+            .run(idempotency_key, format!("set on '{key}'"), || async {
+                self_.keyvalue_implementor.set(key, value).await?;
+                Ok(Outcome::Done)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn keyvalue_get_versioned(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+    ) -> Result<VersionedValue, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        Ok(self_.keyvalue_implementor.get_versioned(key).await?)
+    }
+
+    async fn keyvalue_set_if_version(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        value: &[u8],
+        expected_rev: u64,
+    ) -> Result<u64, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.check_value_size(key, value)?;
+        Ok(self_
+            .keyvalue_implementor
+            .set_if_version(key, value, expected_rev)
+            .await?)
+    }
+
+    async fn keyvalue_cas(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: &[u8],
+    ) -> Result<bool, KeyvalueError> {
+        ensure_supported(self_, Operation::CompareAndSwap)?;
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.check_value_size(key, new)?;
+        Ok(self_.keyvalue_implementor.cas(key, expected, new).await?)
+    }
+
+    async fn keyvalue_increment(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        delta: i64,
+    ) -> Result<i64, KeyvalueError> {
+        ensure_supported(self_, Operation::Increment)?;
+        self_.keyvalue_implementor.validate_key(key)?;
+        Ok(self_.keyvalue_implementor.increment(key, delta).await?)
+    }
+
+    async fn keyvalue_set_if_version_idempotent(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        value: &[u8],
+        expected_rev: u64,
+        idempotency_key: &str,
+    ) -> Result<u64, KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.check_value_size(key, value)?;
+        let request = format!("set-if-version on '{key}' at revision {expected_rev}");
+        let outcome = self_
+            .idempotency
+            .run(idempotency_key, request, || async {
+                let revision = self_
+                    .keyvalue_implementor
+                    .set_if_version(key, value, expected_rev)
+                    .await?;
+                Ok(Outcome::Revision(revision))
+            })
+            .await?;
+        match outcome {
+            Outcome::Revision(revision) => Ok(revision),
+            Outcome::Done => unreachable!("set-if-version outcomes carry a revision"),
+        }
+    }
+
+    async fn keyvalue_get_raw(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &[u8],
+    ) -> Result<Vec<u8>, KeyvalueError> {
+        let key = raw_key(key);
+        self_.keyvalue_implementor.validate_key(&key)?;
+        Ok(self_.get(&key).await?)
+    }
+
+    async fn keyvalue_set_raw(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), KeyvalueError> {
+        let key = raw_key(key);
+        self_.keyvalue_implementor.validate_key(&key)?;
+        self_.check_value_size(&key, value)?;
+        self_.keyvalue_implementor.set(&key, value).await?;
+        Ok(())
+    }
+
+    async fn keyvalue_delete_raw(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &[u8],
+    ) -> Result<(), KeyvalueError> {
+        let key = raw_key(key);
+        self_.keyvalue_implementor.validate_key(&key)?;
+        self_.keyvalue_implementor.delete(&key).await?;
+        Ok(())
+    }
+
     async fn keyvalue_keys(
         &mut self,
         self_: &Self::Keyvalue,
     ) -> Result<Vec<String>, KeyvalueError> {
-        Ok(self_.keyvalue_implementor.keys().await?)
+        ensure_supported(self_, Operation::Keys)?;
+        #[cfg(feature = "kv-keys")]
+        return Ok(self_.keyvalue_implementor.keys().await?);
+        #[cfg(not(feature = "kv-keys"))]
+        Err(unsupported(Operation::Keys).into())
+    }
+
+    #[cfg_attr(not(feature = "kv-keys"), allow(unused_variables))]
+    async fn keyvalue_list_keys(
+        &mut self,
+        self_: &Self::Keyvalue,
+        prefix: Option<&str>,
+        cursor: Option<&str>,
+        limit: u32,
+    ) -> Result<(Vec<String>, Option<String>), KeyvalueError> {
+        ensure_supported(self_, Operation::Keys)?;
+        if limit == 0 {
+            return Err(KeyvalueError::InvalidValue(
+                "list-keys must list at least 1 key at a time".to_string(),
+            ));
+        }
+        #[cfg(feature = "kv-keys")]
+        return Ok(self_
+            .keyvalue_implementor
+            .list_keys(prefix.unwrap_or_default(), cursor, limit)
+            .await?);
+        #[cfg(not(feature = "kv-keys"))]
+        Err(unsupported(Operation::Keys).into())
     }
 
     async fn keyvalue_delete(
@@ -203,7 +1190,447 @@ impl keyvalue::Keyvalue for Keyvalue {
         self_: &Self::Keyvalue,
         key: &str,
     ) -> Result<(), KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
         self_.keyvalue_implementor.delete(key).await?;
         Ok(())
     }
+
+    async fn keyvalue_delete_idempotent(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        idempotency_key: &str,
+    ) -> Result<(), KeyvalueError> {
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_
+            .idempotency
+            .run(idempotency_key, format!("delete on '{key}'"), || async {
+                self_.keyvalue_implementor.delete(key).await?;
+                Ok(Outcome::Done)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn keyvalue_get_bulk(
+        &mut self,
+        self_: &Self::Keyvalue,
+        keys: Vec<&str>,
+    ) -> Result<Vec<(String, Option<Vec<u8>>)>, KeyvalueError> {
+        for key in &keys {
+            self_.keyvalue_implementor.validate_key(key)?;
+        }
+        let values = self_.keyvalue_implementor.get_bulk(&keys).await?;
+        Ok(keys.into_iter().map(String::from).zip(values).collect())
+    }
+
+    async fn keyvalue_set_bulk(
+        &mut self,
+        self_: &Self::Keyvalue,
+        pairs: Vec<(&str, &[u8])>,
+    ) -> Result<(), KeyvalueError> {
+        for (key, value) in &pairs {
+            self_.keyvalue_implementor.validate_key(key)?;
+            self_.check_value_size(key, value)?;
+        }
+        self_.keyvalue_implementor.set_bulk(&pairs).await?;
+        Ok(())
+    }
+
+    async fn keyvalue_delete_bulk(
+        &mut self,
+        self_: &Self::Keyvalue,
+        keys: Vec<&str>,
+    ) -> Result<(), KeyvalueError> {
+        for key in &keys {
+            self_.keyvalue_implementor.validate_key(key)?;
+        }
+        self_.keyvalue_implementor.delete_bulk(&keys).await?;
+        Ok(())
+    }
+
+    async fn keyvalue_clear(&mut self, self_: &Self::Keyvalue) -> Result<(), KeyvalueError> {
+        self_.keyvalue_implementor.clear().await?;
+        Ok(())
+    }
+
+    async fn keyvalue_replace_all(
+        &mut self,
+        self_: &Self::Keyvalue,
+        entries: Vec<(&str, &[u8])>,
+    ) -> Result<(), KeyvalueError> {
+        for (key, value) in &entries {
+            self_.keyvalue_implementor.validate_key(key)?;
+            self_.check_value_size(key, value)?;
+        }
+        self_.keyvalue_implementor.replace_all(&entries).await?;
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "kv-batch"), allow(unused_variables))]
+    async fn keyvalue_delete_prefix(
+        &mut self,
+        self_: &Self::Keyvalue,
+        prefix: &str,
+    ) -> Result<u64, KeyvalueError> {
+        ensure_supported(self_, Operation::DeletePrefix)?;
+        #[cfg(feature = "kv-batch")]
+        return Ok(self_.keyvalue_implementor.delete_prefix(prefix).await?);
+        #[cfg(not(feature = "kv-batch"))]
+        Err(unsupported(Operation::DeletePrefix).into())
+    }
+
+    #[cfg_attr(not(feature = "kv-batch"), allow(unused_variables))]
+    async fn keyvalue_delete_prefix_in_background(
+        &mut self,
+        self_: &Self::Keyvalue,
+        prefix: &str,
+    ) -> Result<u64, KeyvalueError> {
+        ensure_supported(self_, Operation::DeletePrefix)?;
+        #[cfg(feature = "kv-batch")]
+        return Ok(self
+            .delete_jobs
+            .start(self_.keyvalue_implementor.clone(), prefix));
+        #[cfg(not(feature = "kv-batch"))]
+        Err(unsupported(Operation::DeletePrefix).into())
+    }
+
+    #[cfg_attr(not(feature = "kv-batch"), allow(unused_variables))]
+    async fn keyvalue_delete_status(
+        &mut self,
+        self_: &Self::Keyvalue,
+        job: u64,
+    ) -> Result<DeleteStatus, KeyvalueError> {
+        ensure_supported(self_, Operation::DeletePrefix)?;
+        #[cfg(feature = "kv-batch")]
+        return Ok(self.delete_jobs.status(job).map(|progress| DeleteStatus {
+            deleted: progress.deleted,
+            done: progress.done,
+            error: progress.error,
+        })?);
+        #[cfg(not(feature = "kv-batch"))]
+        Err(unsupported(Operation::DeletePrefix).into())
+    }
+
+    #[cfg_attr(not(feature = "kv-keys"), allow(unused_variables))]
+    async fn keyvalue_keys_older_than(
+        &mut self,
+        self_: &Self::Keyvalue,
+        seconds: u64,
+    ) -> Result<Vec<String>, KeyvalueError> {
+        ensure_supported(self_, Operation::KeysOlderThan)?;
+        #[cfg(feature = "kv-keys")]
+        return Ok(self_.keyvalue_implementor.keys_older_than(seconds).await?);
+        #[cfg(not(feature = "kv-keys"))]
+        Err(unsupported(Operation::KeysOlderThan).into())
+    }
+
+    #[cfg_attr(not(feature = "kv-keys"), allow(unused_variables))]
+    async fn keyvalue_keys_by_index(
+        &mut self,
+        self_: &Self::Keyvalue,
+        value: &str,
+    ) -> Result<Vec<String>, KeyvalueError> {
+        ensure_supported(self_, Operation::KeysByIndex)?;
+        #[cfg(feature = "kv-keys")]
+        return Ok(self_.keyvalue_implementor.keys_by_index(value).await?);
+        #[cfg(not(feature = "kv-keys"))]
+        Err(unsupported(Operation::KeysByIndex).into())
+    }
+
+    async fn keyvalue_scan(
+        &mut self,
+        self_: &Self::Keyvalue,
+    ) -> Result<Self::KeyvalueScan, KeyvalueError> {
+        ensure_supported(self_, Operation::Scan)?;
+        #[cfg(feature = "kv-stream")]
+        return Ok(KeyvalueScanInner::new(
+            self_.keyvalue_implementor.scan().await?,
+        ));
+        #[cfg(not(feature = "kv-stream"))]
+        Err(unsupported(Operation::Scan).into())
+    }
+
+    async fn keyvalue_scan_next(
+        &mut self,
+        self_: &Self::KeyvalueScan,
+        max: u32,
+    ) -> Result<Option<Vec<KeyValue>>, KeyvalueError> {
+        if max == 0 {
+            return Err(KeyvalueError::InvalidValue(
+                "scans must read at least 1 pair at a time".to_string(),
+            ));
+        }
+        let pairs = self_.next(max as usize).await?;
+        Ok(pairs.map(|pairs| {
+            pairs
+                .into_iter()
+                .map(|(key, value)| KeyValue { key, value })
+                .collect()
+        }))
+    }
+
+    async fn keyvalue_watch(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+    ) -> Result<Self::KeyvalueWatch, KeyvalueError> {
+        ensure_supported(self_, Operation::Watch)?;
+        self_.keyvalue_implementor.validate_key(key)?;
+        #[cfg(feature = "kv-stream")]
+        return Ok(KeyvalueWatchInner::new(
+            key,
+            self_.keyvalue_implementor.watch(key).await?,
+        ));
+        #[cfg(not(feature = "kv-stream"))]
+        Err(unsupported(Operation::Watch).into())
+    }
+
+    async fn keyvalue_watch_next(
+        &mut self,
+        self_: &Self::KeyvalueWatch,
+        timeout_ms: u64,
+    ) -> Result<Option<KeyChange>, KeyvalueError> {
+        Ok(self_.next(Duration::from_millis(timeout_ms)).await?)
+    }
+
+    async fn keyvalue_read_stream_read(
+        &mut self,
+        self_: &Self::KeyvalueReadStream,
+        size: u64,
+    ) -> Result<Option<Vec<u8>>, KeyvalueError> {
+        if size == 0 {
+            return Err(KeyvalueError::InvalidValue(
+                "streams must read at least 1 byte at a time".to_string(),
+            ));
+        }
+        Ok(self_.read(size).await?)
+    }
+
+    async fn keyvalue_read_stream_available(
+        &mut self,
+        self_: &Self::KeyvalueReadStream,
+    ) -> Result<u64, KeyvalueError> {
+        Ok(self_.available().await)
+    }
+
+    async fn keyvalue_write_stream_write(
+        &mut self,
+        self_: &Self::KeyvalueWriteStream,
+        data: &[u8],
+    ) -> Result<(), KeyvalueError> {
+        Ok(self_.write(data).await?)
+    }
+
+    async fn keyvalue_write_stream_close(
+        &mut self,
+        self_: &Self::KeyvalueWriteStream,
+    ) -> Result<(), KeyvalueError> {
+        Ok(self_.close().await?)
+    }
+
+    async fn keyvalue_undelete(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+    ) -> Result<(), KeyvalueError> {
+        ensure_supported(self_, Operation::Undelete)?;
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.keyvalue_implementor.undelete(key).await?;
+        Ok(())
+    }
+
+    async fn keyvalue_get_in(
+        &mut self,
+        self_: &Self::Keyvalue,
+        ns: &str,
+        key: &str,
+    ) -> Result<Vec<u8>, KeyvalueError> {
+        let key = namespaced(ns, key)?;
+        self_.keyvalue_implementor.validate_key(&key)?;
+        Ok(self_.get(&key).await?)
+    }
+
+    async fn keyvalue_set_in(
+        &mut self,
+        self_: &Self::Keyvalue,
+        ns: &str,
+        key: &str,
+        value: &[u8],
+    ) -> Result<(), KeyvalueError> {
+        let key = namespaced(ns, key)?;
+        self_.keyvalue_implementor.validate_key(&key)?;
+        self_.check_value_size(&key, value)?;
+        self_.keyvalue_implementor.set(&key, value).await?;
+        Ok(())
+    }
+
+    async fn keyvalue_delete_in(
+        &mut self,
+        self_: &Self::Keyvalue,
+        ns: &str,
+        key: &str,
+    ) -> Result<(), KeyvalueError> {
+        let key = namespaced(ns, key)?;
+        self_.keyvalue_implementor.validate_key(&key)?;
+        self_.keyvalue_implementor.delete(&key).await?;
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "kv-keys"), allow(unused_variables))]
+    async fn keyvalue_keys_in(
+        &mut self,
+        self_: &Self::Keyvalue,
+        ns: &str,
+    ) -> Result<Vec<String>, KeyvalueError> {
+        let prefix = namespaced(ns, "")?;
+        ensure_supported(self_, Operation::Keys)?;
+        #[cfg(feature = "kv-keys")]
+        return Ok(self_
+            .keyvalue_implementor
+            .keys()
+            .await?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&prefix).map(String::from))
+            .collect());
+        #[cfg(not(feature = "kv-keys"))]
+        Err(unsupported(Operation::Keys).into())
+    }
+
+    async fn keyvalue_lock(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        ttl_ms: u64,
+    ) -> Result<LockGuard, KeyvalueError> {
+        ensure_supported(self_, Operation::Lock)?;
+        self_.keyvalue_implementor.validate_key(key)?;
+        if ttl_ms == 0 {
+            return Err(KeyvalueError::InvalidValue(
+                "lock ttl must be at least 1 millisecond".to_string(),
+            ));
+        }
+        let token = self_
+            .keyvalue_implementor
+            .lock(key, Duration::from_millis(ttl_ms))
+            .await?;
+        Ok(LockGuard {
+            key: key.to_string(),
+            token,
+        })
+    }
+
+    async fn keyvalue_unlock(
+        &mut self,
+        self_: &Self::Keyvalue,
+        key: &str,
+        token: &str,
+    ) -> Result<(), KeyvalueError> {
+        ensure_supported(self_, Operation::Lock)?;
+        self_.keyvalue_implementor.validate_key(key)?;
+        self_.keyvalue_implementor.unlock(key, token).await?;
+        Ok(())
+    }
+
+    async fn keyvalue_supports(&mut self, self_: &Self::Keyvalue, op: Operation) -> bool {
+        self_.capabilities().contains(capability(op))
+    }
+
+    async fn keyvalue_capabilities(&mut self, self_: &Self::Keyvalue) -> KeyvalueCapabilities {
+        self_.capabilities()
+    }
+
+    async fn keyvalue_connection_status(&mut self, self_: &Self::Keyvalue) -> ConnectionStatus {
+        self_.keyvalue_implementor.connection_status()
+    }
+
+    async fn keyvalue_reconnect(&mut self, self_: &Self::Keyvalue) -> Result<(), KeyvalueError> {
+        self_.keyvalue_implementor.reconnect().await?;
+        Ok(())
+    }
+
+    async fn keyvalue_set_trace_context(
+        &mut self,
+        self_: &Self::Keyvalue,
+        traceparent: &str,
+        tracestate: Option<&str>,
+    ) -> Result<(), KeyvalueError> {
+        self_.trace_context.set(traceparent, tracestate)?;
+        Ok(())
+    }
+}
+
+/// Answers with `KeyvalueError::OperationNotSupported` when the store's
+/// implementor doesn't list `op` in its capabilities, or `op` isn't compiled
+/// in, so that optional operations fail the same way on every implementor.
+fn ensure_supported(inner: &KeyvalueInner, op: Operation) -> Result<(), KeyvalueError> {
+    if inner.capabilities().contains(capability(op)) {
+        Ok(())
+    } else {
+        Err(unsupported(op).into())
+    }
+}
+
+/// Maps a binary key to the string key it is stored under, i.e., its unpadded
+/// base64url encoding.
+///
+/// The encoding only uses `A-Z`, `a-z`, `0-9`, `-` and `_`, which every
+/// implementor accepts in keys (although it still enforces its length limits).
+fn raw_key(key: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(key)
+}
+
+/// Maps `key` within namespace `ns` to the store's key `<ns>:<key>`.
+///
+/// Namespaces can't contain `:`, so that a key in one namespace never
+/// collides with a key in another (e.g., `a:b` + `c` and `a` + `b:c`).
+fn namespaced(ns: &str, key: &str) -> Result<String, KeyvalueError> {
+    if ns.is_empty() || ns.contains(':') {
+        return Err(KeyvalueError::InvalidKey(format!(
+            "namespace '{ns}' must be non-empty and must not contain ':'"
+        )));
+    }
+    Ok(format!("{ns}:{key}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(configs: &[(&str, &str)]) -> BasicState {
+        BasicState::new(
+            None,
+            Resource::Keyvalue(Filesystem),
+            "slight-keyvalue-test".to_string(),
+            Some(
+                configs
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            "./slightfile.toml",
+        )
+    }
+
+    #[tokio::test]
+    async fn test_tombstone_ttl() -> Result<()> {
+        assert_eq!(
+            tombstone_ttl(&state(&[])).await?,
+            soft_delete::DEFAULT_TOMBSTONE_TTL
+        );
+        assert_eq!(
+            tombstone_ttl(&state(&[("TOMBSTONE_TTL", "60")])).await?,
+            Duration::from_secs(60)
+        );
+        assert!(tombstone_ttl(&state(&[("TOMBSTONE_TTL", "0")]))
+            .await
+            .is_err());
+        assert!(tombstone_ttl(&state(&[("TOMBSTONE_TTL", "-1")]))
+            .await
+            .is_err());
+        assert!(tombstone_ttl(&state(&[("TOMBSTONE_TTL", "a day")]))
+            .await
+            .is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,275 @@
+//! Per-key metadata shared by every keyvalue implementor.
+//!
+//! Features that need to remember something about a key besides its value
+//! (e.g., when it expires, or what its content type is) store it as a
+//! `KeyMetadata`. Each implementor decides *where* metadata lives (see
+//! `KeyvalueImplementor::get_metadata`), but not *how* it is laid out:
+//!   - backends with native metadata slots (azblob blob metadata, DynamoDB
+//!   attributes, Firestore fields) store the name/value pairs from
+//!   `KeyMetadata::to_pairs` there, and
+//!   - backends without them (filesystem, redis) store the bytes produced by
+//!   `KeyMetadata::encode` in a sidecar next to the value.
+//!
+//! Adding a field here therefore makes it available on every backend at once.
+//! Sidecars start with the version of their layout, so that a layout change
+//! doesn't make existing sidecars unreadable.
+
+use anyhow::{bail, Context, Result};
+
+use crate::keyvalue::KeyvalueError;
+
+const CREATED_AT: &str = "created_at";
+const EXPIRES_AT: &str = "expires_at";
+const CONTENT_TYPE: &str = "content_type";
+const CONTENT_ENCODING: &str = "content_encoding";
+const DELETED_AT: &str = "deleted_at";
+const REVISION: &str = "revision";
+const CHECKSUM: &str = "checksum";
+
+/// The version of the sidecar layout `KeyMetadata::encode` writes.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// The first byte of sidecars written before they were versioned, which is the
+/// high byte of the length of their first name (as names are shorter than 256
+/// bytes).
+const UNVERSIONED: u8 = 0;
+
+/// Metadata kept alongside a key's value.
+///
+/// Every field is optional, and an absent field is simply not stored.
+/// Timestamps are in seconds since the unix epoch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyMetadata {
+    pub created_at: Option<i64>,
+    /// When the key expires, as written by `set_with_expiry` on backends
+    /// without native expiry.
+    pub expires_at: Option<i64>,
+    pub content_type: Option<String>,
+    /// The encoding the value was stored in (e.g., `gzip`), as given by the
+    /// guest, which is never applied nor removed by slight.
+    pub content_encoding: Option<String>,
+    /// When the key was soft-deleted (i.e., it holds a tombstone).
+    pub deleted_at: Option<i64>,
+    /// The revision of the key's value, as written by `set_if_version`.
+    pub revision: Option<u64>,
+    /// The checksum of the key's value, as written by
+    /// `integrity::IntegrityImplementor`.
+    pub checksum: Option<String>,
+}
+
+impl KeyMetadata {
+    /// Whether no field is set.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Returns the set fields as name/value pairs.
+    ///
+    /// Names only contain lowercase letters and underscores, so they are valid
+    /// in every backend's native metadata slot (e.g., azblob requires metadata
+    /// names to be C# identifiers).
+    pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![];
+        if let Some(created_at) = self.created_at {
+            pairs.push((CREATED_AT, created_at.to_string()));
+        }
+        if let Some(expires_at) = self.expires_at {
+            pairs.push((EXPIRES_AT, expires_at.to_string()));
+        }
+        if let Some(content_type) = &self.content_type {
+            pairs.push((CONTENT_TYPE, content_type.clone()));
+        }
+        if let Some(content_encoding) = &self.content_encoding {
+            pairs.push((CONTENT_ENCODING, content_encoding.clone()));
+        }
+        if let Some(deleted_at) = self.deleted_at {
+            pairs.push((DELETED_AT, deleted_at.to_string()));
+        }
+        if let Some(revision) = self.revision {
+            pairs.push((REVISION, revision.to_string()));
+        }
+        if let Some(checksum) = &self.checksum {
+            pairs.push((CHECKSUM, checksum.clone()));
+        }
+        pairs
+    }
+
+    /// Builds a `KeyMetadata` from name/value pairs, as returned by `to_pairs`.
+    ///
+    /// Unknown names are ignored, so metadata written by a newer version (or
+    /// by another tool sharing the backend) can still be read.
+    pub fn from_pairs<K, V>(pairs: impl IntoIterator<Item = (K, V)>) -> Result<Self>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut metadata = Self::default();
+        for (name, value) in pairs {
+            let value = value.as_ref();
+            match name.as_ref() {
+                CREATED_AT => {
+                    metadata.created_at = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("invalid '{CREATED_AT}' metadata"))?,
+                    )
+                }
+                EXPIRES_AT => {
+                    metadata.expires_at = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("invalid '{EXPIRES_AT}' metadata"))?,
+                    )
+                }
+                CONTENT_TYPE => metadata.content_type = Some(value.to_owned()),
+                CONTENT_ENCODING => metadata.content_encoding = Some(value.to_owned()),
+                DELETED_AT => {
+                    metadata.deleted_at = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("invalid '{DELETED_AT}' metadata"))?,
+                    )
+                }
+                REVISION => {
+                    metadata.revision = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("invalid '{REVISION}' metadata"))?,
+                    )
+                }
+                CHECKSUM => metadata.checksum = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Encodes the metadata for backends without a native metadata slot.
+    ///
+    /// The layout is the format version (i.e., `FORMAT_VERSION`), followed by
+    /// a sequence of length-prefixed pairs:
+    /// ```text
+    /// [version: u8][name length: u16 BE][name][value length: u32 BE][value] ...
+    /// ```
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![FORMAT_VERSION];
+        for (name, value) in self.to_pairs() {
+            buf.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            buf.extend_from_slice(name.as_bytes());
+            buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            buf.extend_from_slice(value.as_bytes());
+        }
+        buf
+    }
+
+    /// Decodes metadata produced by `encode`, by this or any earlier version.
+    ///
+    /// Answers with `KeyvalueError::UnsupportedFormat` for a format version it
+    /// doesn't know (e.g., written by a newer version of slight), rather than
+    /// misreading it.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        match buf.first() {
+            // an empty sidecar holds no pairs, whatever its version
+            None => Ok(Self::default()),
+            // the layout of version 1 is that of unversioned sidecars, after
+            // the version byte
+            Some(&UNVERSIONED) => Self::decode_pairs(buf),
+            Some(&FORMAT_VERSION) => Self::decode_pairs(&buf[1..]),
+            Some(version) => Err(KeyvalueError::UnsupportedFormat(format!(
+                "unknown key metadata format version {version}"
+            ))
+            .into()),
+        }
+    }
+
+    fn decode_pairs(mut buf: &[u8]) -> Result<Self> {
+        let mut pairs = vec![];
+        while !buf.is_empty() {
+            let name = take_prefixed(&mut buf, 2)?;
+            let value = take_prefixed(&mut buf, 4)?;
+            pairs.push((name, value));
+        }
+        Self::from_pairs(pairs)
+    }
+}
+
+/// Splits a UTF-8 string prefixed by its big-endian length (of `width` bytes)
+/// off the front of `buf`.
+fn take_prefixed(buf: &mut &[u8], width: usize) -> Result<String> {
+    if buf.len() < width {
+        bail!("truncated key metadata");
+    }
+    let (len, rest) = buf.split_at(width);
+    let len = len.iter().fold(0usize, |len, b| len << 8 | *b as usize);
+    if rest.len() < len {
+        bail!("truncated key metadata");
+    }
+    let (s, rest) = rest.split_at(len);
+    *buf = rest;
+    String::from_utf8(s.to_vec()).with_context(|| "key metadata is not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> KeyMetadata {
+        KeyMetadata {
+            created_at: Some(1_700_000_000),
+            expires_at: Some(1_700_000_600),
+            content_type: Some("application/json".into()),
+            content_encoding: Some("gzip".into()),
+            deleted_at: None,
+            revision: Some(7),
+            checksum: Some("crc32:cbf43926".into()),
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() -> Result<()> {
+        let buf = metadata().encode();
+        assert_eq!(buf[0], FORMAT_VERSION);
+        assert_eq!(KeyMetadata::decode(&buf)?, metadata());
+        assert_eq!(
+            KeyMetadata::decode(&KeyMetadata::default().encode())?,
+            KeyMetadata::default()
+        );
+        assert_eq!(KeyMetadata::decode(&[])?, KeyMetadata::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_unversioned() -> Result<()> {
+        // sidecars written before versioning are the pairs alone
+        let buf = metadata().encode();
+        assert_eq!(buf[1], UNVERSIONED);
+        assert_eq!(KeyMetadata::decode(&buf[1..])?, metadata());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_unknown_version() {
+        let mut buf = metadata().encode();
+        buf[0] = FORMAT_VERSION + 1;
+        let e = KeyMetadata::decode(&buf).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<KeyvalueError>(),
+            Some(KeyvalueError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let buf = metadata().encode();
+        assert!(KeyMetadata::decode(&buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_from_pairs_ignores_unknown_names() -> Result<()> {
+        let metadata =
+            KeyMetadata::from_pairs([("revision", "3"), ("written_by", "another-tool")])?;
+        assert_eq!(metadata.revision, Some(3));
+        assert!(KeyMetadata::from_pairs([("revision", "not a number")]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Hooks for embedders to follow the lifecycle of keyvalue stores.
+//!
+//! An observer registered with `Keyvalue::with_observer` is told whenever a guest
+//! opens a store and whenever that store is torn down (i.e., once the guest's
+//! `keyvalue` resource and every clone of it are dropped). This lets embedders
+//! pre-warm caches, log lifecycle events, or cap the number of stores open at
+//! once. Without an observer, opening and closing stores does nothing extra.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+/// Receives keyvalue store lifecycle events.
+///
+/// `name` is the store name the guest opened, and `implementor` the resource
+/// backing it (e.g., `keyvalue.redis`).
+pub trait KeyvalueObserver {
+    /// Called before a store is handed to the guest. Returning an error fails
+    /// the guest's `open` with it (e.g., to enforce a quota).
+    fn on_open(&self, _name: &str, _implementor: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once a store opened with a successful `on_open` is torn down,
+    /// including when opening it fails after `on_open`.
+    fn on_close(&self, _name: &str, _implementor: &str) {}
+}
+
+/// Notifies the observer that a store was closed when dropped.
+pub(crate) struct OpenStore {
+    observer: Arc<dyn KeyvalueObserver + Send + Sync>,
+    name: String,
+    implementor: String,
+}
+
+impl OpenStore {
+    pub(crate) fn new(
+        observer: Arc<dyn KeyvalueObserver + Send + Sync>,
+        name: &str,
+        implementor: &str,
+    ) -> Self {
+        Self {
+            observer,
+            name: name.to_string(),
+            implementor: implementor.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Debug for OpenStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenStore")
+            .field("name", &self.name)
+            .field("implementor", &self.implementor)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for OpenStore {
+    fn drop(&mut self) {
+        self.observer.on_close(&self.name, &self.implementor);
+    }
+}
@@ -1,12 +1,17 @@
 use anyhow::Result;
+use azure_core::{
+    error::ErrorKind,
+    request_options::{Metadata, Range},
+    StatusCode,
+};
 use azure_storage_blobs::{
-    container::operations::BlobItem,
-    prelude::{BlobClient, ContainerClient, DeleteSnapshotsMethod},
+    container::operations::{BlobItem, ListBlobsBuilder},
+    prelude::{BlobClient, BlockId, BlockList, ContainerClient, DeleteSnapshotsMethod},
 };
 use futures::stream::StreamExt;
 
 /// Get the value given a `blob_client`
-pub async fn get(blob_client: BlobClient) -> Result<Vec<u8>> {
+pub async fn get(blob_client: BlobClient) -> azure_core::Result<Vec<u8>> {
     let mut stream = blob_client.get().chunk_size(128u64).into_stream();
     let mut result = vec![];
     // The stream is composed of individual calls to the get blob endpoint
@@ -21,8 +26,21 @@ pub async fn get(blob_client: BlobClient) -> Result<Vec<u8>> {
     Ok(result)
 }
 
+/// Get the bytes in `range` of the value given a `blob_client`
+pub async fn get_range(blob_client: BlobClient, range: Range) -> azure_core::Result<Vec<u8>> {
+    let mut stream = blob_client.get().range(range).into_stream();
+    let mut result = vec![];
+    while let Some(value) = stream.next().await {
+        let mut body = value?.data;
+        while let Some(value) = body.next().await {
+            result.extend(&value?);
+        }
+    }
+    Ok(result)
+}
+
 /// Set the value given a `blob_client` and `value`
-pub async fn set(blob_client: BlobClient, value: Vec<u8>) -> Result<()> {
+pub async fn set(blob_client: BlobClient, value: Vec<u8>) -> azure_core::Result<()> {
     blob_client
         .put_block_blob(value)
         .content_type("text/plain")
@@ -31,6 +49,50 @@ pub async fn set(blob_client: BlobClient, value: Vec<u8>) -> Result<()> {
     Ok(())
 }
 
+/// Stage `data` as the block `block_id` of the blob given a `blob_client`, which
+/// is only part of the value once committed with `put_block_list`
+pub async fn put_block(
+    blob_client: BlobClient,
+    block_id: BlockId,
+    data: Vec<u8>,
+) -> azure_core::Result<()> {
+    blob_client.put_block(block_id, data).into_future().await?;
+    Ok(())
+}
+
+/// Set the value given a `blob_client` to the staged blocks of `block_list`, in order
+pub async fn put_block_list(
+    blob_client: BlobClient,
+    block_list: BlockList,
+) -> azure_core::Result<()> {
+    blob_client
+        .put_block_list(block_list)
+        .content_type("text/plain")
+        .into_future()
+        .await?;
+    Ok(())
+}
+
+/// Set the value given a `blob_client` and `value`, along with the content
+/// headers the blob is served with and its user-defined `metadata`
+pub async fn set_with_content_type(
+    blob_client: BlobClient,
+    value: Vec<u8>,
+    content_type: String,
+    content_encoding: Option<String>,
+    metadata: Metadata,
+) -> azure_core::Result<()> {
+    let mut put = blob_client
+        .put_block_blob(value)
+        .content_type(content_type)
+        .metadata(metadata);
+    if let Some(content_encoding) = content_encoding {
+        put = put.content_encoding(content_encoding);
+    }
+    put.into_future().await?;
+    Ok(())
+}
+
 /// Delete the `value` given a `blob_client`
 pub async fn delete(blob_client: BlobClient) -> Result<()> {
     blob_client
@@ -41,8 +103,103 @@ pub async fn delete(blob_client: BlobClient) -> Result<()> {
     Ok(())
 }
 
+/// Get the user-defined metadata of the blob given a `blob_client`
+pub async fn get_metadata(blob_client: BlobClient) -> azure_core::Result<Metadata> {
+    Ok(blob_client.get_metadata().into_future().await?.metadata)
+}
+
+/// Get the size of the value given a `blob_client`, from its properties (i.e., without reading it)
+pub async fn get_size(blob_client: BlobClient) -> azure_core::Result<u64> {
+    Ok(blob_client
+        .get_properties()
+        .into_future()
+        .await?
+        .blob
+        .properties
+        .content_length)
+}
+
+/// Whether the blob exists given a `blob_client`, from its properties (i.e., without reading it)
+pub async fn exists(blob_client: BlobClient) -> azure_core::Result<bool> {
+    blob_client.exists().await
+}
+
+/// Replace the user-defined metadata of the blob given a `blob_client` and `metadata`
+pub async fn set_metadata(blob_client: BlobClient, metadata: Metadata) -> azure_core::Result<()> {
+    blob_client
+        .set_metadata()
+        .metadata(metadata)
+        .into_future()
+        .await?;
+    Ok(())
+}
+
+/// Whether `e` is the service reporting that the blob doesn't exist
+pub fn is_not_found(e: &azure_core::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::HttpResponse {
+            status: StatusCode::NotFound,
+            ..
+        }
+    )
+}
+
+/// Whether `e` is the service reporting that a requested range starts past
+/// the end of the blob
+pub fn is_range_not_satisfiable(e: &azure_core::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::HttpResponse {
+            status: StatusCode::RequestedRangeNotSatisfiable,
+            ..
+        }
+    )
+}
+
+/// Whether `e` is the service throttling requests (i.e., the account is over
+/// its scalability targets)
+pub fn is_throttled(e: &azure_core::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::HttpResponse {
+            status: StatusCode::ServiceUnavailable | StatusCode::TooManyRequests,
+            ..
+        }
+    )
+}
+
+/// Whether `e` is the service rejecting the account's credentials, or the
+/// credentials failing to be obtained
+pub fn is_unauthorized(e: &azure_core::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::HttpResponse {
+            status: StatusCode::Unauthorized | StatusCode::Forbidden,
+            ..
+        } | ErrorKind::Credential
+    )
+}
+
+/// Whether `e` is a failure to reach the service
+pub fn is_io_error(e: &azure_core::Error) -> bool {
+    matches!(e.kind(), ErrorKind::Io)
+}
+
 pub async fn list_blobs(container_client: ContainerClient) -> Result<Vec<BlobItem>> {
-    let mut stream = container_client.list_blobs().into_stream();
+    collect_blobs(container_client.list_blobs()).await
+}
+
+/// List only the blobs whose name starts with `prefix`, filtering on the service side
+pub async fn list_blobs_with_prefix(
+    container_client: ContainerClient,
+    prefix: &str,
+) -> Result<Vec<BlobItem>> {
+    collect_blobs(container_client.list_blobs().prefix(prefix.to_owned())).await
+}
+
+async fn collect_blobs(builder: ListBlobsBuilder) -> Result<Vec<BlobItem>> {
+    let mut stream = builder.into_stream();
     let mut results = vec![];
     while let Some(value) = stream.next().await {
         let value = value?;
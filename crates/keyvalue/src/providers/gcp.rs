@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use gcp_auth::{AuthenticationManager, CustomServiceAccount};
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use serde::Deserialize;
+use tracing::log;
+
+use crate::keyvalue::KeyvalueError;
+
+/// The configs of a GCP-backed capability.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct GcpConfig {
+    pub google_application_credentials: Option<String>,
+    pub gcp_project_id: Option<String>,
+}
+
+/// Creates an `AuthenticationManager` for a GCP-backed capability.
+///
+/// If the capability sets `GOOGLE_APPLICATION_CREDENTIALS`, it is treated as the
+/// path to a service account JSON key file. Otherwise, the standard application
+/// default credentials lookup is used (i.e., the `GOOGLE_APPLICATION_CREDENTIALS`
+/// environment variable, the gcloud config directory, and the metadata server).
+pub async fn authentication_manager(config: &GcpConfig) -> Result<AuthenticationManager> {
+    match &config.google_application_credentials {
+        Some(path) => {
+            log::info!("Authenticating to GCP with service account at '{}'", path);
+            let service_account = CustomServiceAccount::from_file(path)
+                .with_context(|| format!("failed to load GCP service account from '{path}'"))?;
+            Ok(service_account.into())
+        }
+        None => {
+            log::info!("Authenticating to GCP with application default credentials");
+            AuthenticationManager::new()
+                .await
+                .with_context(|| "failed to find GCP application default credentials")
+        }
+    }
+}
+
+/// Resolves the GCP project to use, preferring the capability's `GCP_PROJECT_ID`
+/// config and falling back to the project of the authenticated account.
+pub async fn project_id(
+    config: &GcpConfig,
+    authentication_manager: &AuthenticationManager,
+) -> Result<String> {
+    match &config.gcp_project_id {
+        Some(project_id) => Ok(project_id.clone()),
+        None => authentication_manager.project_id().await.with_context(|| {
+            "GCP_PROJECT_ID must be set when it can't be inferred from the credentials"
+        }),
+    }
+}
+
+/// Reads the delay a throttled response suggests, from its `Retry-After`
+/// header (in seconds).
+pub fn retry_after_ms(res: &Response) -> Option<u64> {
+    res.headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .map(|seconds| seconds * 1000)
+}
+
+/// Classifies the GCP errors returned as is (see
+/// `implementors::classify_error`): failing to get an access token, the service
+/// rejecting it or throttling requests, and failing to reach the service, or to
+/// hear back in time.
+pub fn classify_error(e: &anyhow::Error) -> Option<KeyvalueError> {
+    if e.downcast_ref::<gcp_auth::Error>().is_some() {
+        return Some(KeyvalueError::AuthenticationError(e.to_string()));
+    }
+    let reqwest_error = e.downcast_ref::<reqwest::Error>()?;
+    match reqwest_error.status() {
+        Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+            Some(KeyvalueError::AuthenticationError(e.to_string()))
+        }
+        Some(StatusCode::TOO_MANY_REQUESTS) => Some(KeyvalueError::Throttled(None)),
+        _ if reqwest_error.is_timeout() => Some(KeyvalueError::TimeoutError(e.to_string())),
+        _ if reqwest_error.is_connect() => Some(KeyvalueError::ConnectionError(e.to_string())),
+        _ => None,
+    }
+}
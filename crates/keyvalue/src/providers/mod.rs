@@ -1,2 +1,4 @@
 #[cfg(feature = "azblob")]
 pub mod azure;
+#[cfg(any(feature = "firestore", feature = "gcpstorage"))]
+pub mod gcp;
@@ -0,0 +1,90 @@
+use std::fmt::Debug;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+/// A key along with its value, as streamed by a `KeyvalueScanImplementor`.
+pub type Pair = (String, Vec<u8>);
+
+/// A stream of the key/value pairs of a store, as returned by
+/// `KeyvalueImplementor::scan`.
+#[async_trait]
+pub trait KeyvalueScanImplementor {
+    /// Reads the next batch of pairs, of whatever size suits the backend (e.g.,
+    /// a page of results), which may be empty.
+    ///
+    /// Returns `None` once every pair was read.
+    async fn next_batch(&mut self) -> Result<Option<Vec<Pair>>>;
+}
+
+impl Debug for dyn KeyvalueScanImplementor + Send + Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyvalueScanImplementor")
+            .finish_non_exhaustive()
+    }
+}
+
+/// This is a `KeyvalueScanImplementor` that maps the pairs of another one,
+/// dropping the pairs `map` returns `None` for (e.g., the keys a wrapper
+/// hides from guests).
+pub struct MappedScan<F> {
+    inner: Box<dyn KeyvalueScanImplementor + Send + Sync>,
+    map: F,
+}
+
+impl<F> MappedScan<F>
+where
+    F: Fn(Pair) -> Option<Pair> + Send + Sync,
+{
+    pub fn new(inner: Box<dyn KeyvalueScanImplementor + Send + Sync>, map: F) -> Self {
+        Self { inner, map }
+    }
+}
+
+#[async_trait]
+impl<F> KeyvalueScanImplementor for MappedScan<F>
+where
+    F: Fn(Pair) -> Option<Pair> + Send + Sync,
+{
+    async fn next_batch(&mut self) -> Result<Option<Vec<Pair>>> {
+        Ok(self
+            .inner
+            .next_batch()
+            .await?
+            .map(|batch| batch.into_iter().filter_map(&self.map).collect()))
+    }
+}
+
+/// This is the underlying struct behind the `keyvalue-scan` resource.
+///
+/// It buffers the batches of its implementor, so that guests read as many
+/// pairs at a time as they ask for.
+#[derive(Debug)]
+pub struct KeyvalueScanInner {
+    implementor: Mutex<Box<dyn KeyvalueScanImplementor + Send + Sync>>,
+    buffered: Mutex<Vec<Pair>>,
+}
+
+impl KeyvalueScanInner {
+    pub fn new(implementor: Box<dyn KeyvalueScanImplementor + Send + Sync>) -> Self {
+        Self {
+            implementor: Mutex::new(implementor),
+            buffered: Mutex::new(vec![]),
+        }
+    }
+
+    /// Reads up to `max` of the next pairs, or `None` once every pair was read.
+    pub async fn next(&self, max: usize) -> Result<Option<Vec<Pair>>> {
+        let mut implementor = self.implementor.lock().await;
+        let mut buffered = self.buffered.lock().await;
+        while buffered.is_empty() {
+            match implementor.next_batch().await? {
+                Some(batch) => buffered.extend(batch),
+                None => return Ok(None),
+            }
+        }
+        let len = max.min(buffered.len());
+        Ok(Some(buffered.drain(..len).collect()))
+    }
+}
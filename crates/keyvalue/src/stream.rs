@@ -0,0 +1,179 @@
+use std::{fmt::Debug, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::{implementors::KeyvalueImplementor, keyvalue::KeyvalueError};
+
+/// A stream of the bytes of a value, as returned by
+/// `KeyvalueImplementor::get_stream`.
+#[async_trait]
+pub trait KeyvalueReadStreamImplementor {
+    /// Reads up to `size` of the next bytes, which may be fewer (but not none)
+    /// even before the end of the value.
+    ///
+    /// Returns `None` once the whole value was read.
+    async fn read(&mut self, size: u64) -> Result<Option<Vec<u8>>>;
+
+    /// How many bytes are left to read.
+    fn available(&self) -> u64;
+}
+
+impl Debug for dyn KeyvalueReadStreamImplementor + Send + Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyvalueReadStreamImplementor")
+            .finish_non_exhaustive()
+    }
+}
+
+/// A stream writing the value of a key, as returned by
+/// `KeyvalueImplementor::set_stream`.
+///
+/// Nothing is written to the key until `close`, and a stream dropped before
+/// then writes nothing.
+#[async_trait]
+pub trait KeyvalueWriteStreamImplementor {
+    /// Appends `data` to the value.
+    async fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Replaces the value of the key with the bytes written so far.
+    async fn close(&mut self) -> Result<()>;
+}
+
+impl Debug for dyn KeyvalueWriteStreamImplementor + Send + Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyvalueWriteStreamImplementor")
+            .finish_non_exhaustive()
+    }
+}
+
+/// This is a `KeyvalueReadStreamImplementor` over a value read whole, for
+/// implementors whose backend can't stream reads.
+pub struct BufferedReadStream {
+    value: Vec<u8>,
+    offset: usize,
+}
+
+impl BufferedReadStream {
+    pub fn new(value: Vec<u8>) -> Self {
+        Self { value, offset: 0 }
+    }
+}
+
+#[async_trait]
+impl KeyvalueReadStreamImplementor for BufferedReadStream {
+    async fn read(&mut self, size: u64) -> Result<Option<Vec<u8>>> {
+        if self.offset == self.value.len() {
+            return Ok(None);
+        }
+        let len = usize::try_from(size)
+            .unwrap_or(usize::MAX)
+            .min(self.value.len() - self.offset);
+        let chunk = self.value[self.offset..self.offset + len].to_vec();
+        self.offset += len;
+        Ok(Some(chunk))
+    }
+
+    fn available(&self) -> u64 {
+        (self.value.len() - self.offset) as u64
+    }
+}
+
+/// This is a `KeyvalueWriteStreamImplementor` that buffers the value, and
+/// `set`s it on `implementor` on close, for implementors whose backend can't
+/// stream writes.
+pub struct BufferedWriteStream {
+    implementor: Arc<dyn KeyvalueImplementor + Send + Sync>,
+    key: String,
+    buffer: Vec<u8>,
+}
+
+impl BufferedWriteStream {
+    pub fn new(implementor: Arc<dyn KeyvalueImplementor + Send + Sync>, key: &str) -> Self {
+        Self {
+            implementor,
+            key: key.to_string(),
+            buffer: vec![],
+        }
+    }
+}
+
+#[async_trait]
+impl KeyvalueWriteStreamImplementor for BufferedWriteStream {
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(data);
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.implementor.set(&self.key, &self.buffer).await
+    }
+}
+
+/// This is the underlying struct behind the `keyvalue-read-stream` resource.
+#[derive(Debug)]
+pub struct KeyvalueReadStreamInner {
+    implementor: Mutex<Box<dyn KeyvalueReadStreamImplementor + Send + Sync>>,
+}
+
+impl KeyvalueReadStreamInner {
+    pub fn new(implementor: Box<dyn KeyvalueReadStreamImplementor + Send + Sync>) -> Self {
+        Self {
+            implementor: Mutex::new(implementor),
+        }
+    }
+
+    /// Reads up to `size` of the next bytes, or `None` once the whole value was
+    /// read.
+    pub async fn read(&self, size: u64) -> Result<Option<Vec<u8>>> {
+        self.implementor.lock().await.read(size).await
+    }
+
+    /// How many bytes are left to read.
+    pub async fn available(&self) -> u64 {
+        self.implementor.lock().await.available()
+    }
+}
+
+/// This is the underlying struct behind the `keyvalue-write-stream` resource.
+///
+/// Once closed (whether or not that succeeded), it can't be written to (or
+/// closed) again.
+#[derive(Debug)]
+pub struct KeyvalueWriteStreamInner {
+    key: String,
+    implementor: Mutex<Option<Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>>>,
+}
+
+impl KeyvalueWriteStreamInner {
+    pub fn new(
+        key: &str,
+        implementor: Box<dyn KeyvalueWriteStreamImplementor + Send + Sync>,
+    ) -> Self {
+        Self {
+            key: key.to_string(),
+            implementor: Mutex::new(Some(implementor)),
+        }
+    }
+
+    fn closed(&self) -> anyhow::Error {
+        KeyvalueError::InvalidValue(format!("the stream writing '{}' is closed", self.key)).into()
+    }
+
+    /// Appends `data` to the value.
+    pub async fn write(&self, data: &[u8]) -> Result<()> {
+        match self.implementor.lock().await.as_mut() {
+            Some(implementor) => implementor.write(data).await,
+            None => Err(self.closed()),
+        }
+    }
+
+    /// Replaces the value of the key with the bytes written so far.
+    pub async fn close(&self) -> Result<()> {
+        match self.implementor.lock().await.take() {
+            Some(mut implementor) => implementor.close().await,
+            None => Err(self.closed()),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    fmt::Debug,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use tokio::{
+    sync::{mpsc, Mutex},
+    time::MissedTickBehavior,
+};
+
+use crate::keyvalue::{ChangeKind, KeyChange};
+
+/// A stream of the changes to a key, as returned by `KeyvalueImplementor::watch`.
+#[async_trait]
+pub trait KeyvalueWatchImplementor {
+    /// Waits for the next change to the key.
+    ///
+    /// Guests wait for changes with a timeout, so no change may be lost when
+    /// the returned future is dropped before it completes.
+    async fn next_change(&mut self) -> Result<ChangeKind>;
+}
+
+impl Debug for dyn KeyvalueWatchImplementor + Send + Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyvalueWatchImplementor")
+            .finish_non_exhaustive()
+    }
+}
+
+/// This is a `KeyvalueWatchImplementor` that reads the changes sent by a
+/// background task or thread (e.g., one listening to the backend's
+/// notifications), which should stop once the watch is dropped (i.e., once its
+/// sender is closed).
+pub struct ChannelWatch {
+    changes: mpsc::UnboundedReceiver<Result<ChangeKind>>,
+}
+
+impl ChannelWatch {
+    pub fn new() -> (mpsc::UnboundedSender<Result<ChangeKind>>, Self) {
+        let (sender, changes) = mpsc::unbounded_channel();
+        (sender, Self { changes })
+    }
+}
+
+#[async_trait]
+impl KeyvalueWatchImplementor for ChannelWatch {
+    async fn next_change(&mut self) -> Result<ChangeKind> {
+        match self.changes.recv().await {
+            Some(change) => change,
+            None => bail!("keyvalue watch stopped"),
+        }
+    }
+}
+
+/// The state of a key, as polled by `poll`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyState {
+    Missing,
+    /// The key was set with an expiry that has passed, but its value is still
+    /// around.
+    Expired,
+    /// The key holds a value, last written at the given time with the given
+    /// length.
+    Present(SystemTime, u64),
+}
+
+impl KeyState {
+    /// The change from `self` to `next`, if it is one guests see. A key that
+    /// expired and is then removed was already gone, so that isn't a change.
+    fn change_to(&self, next: &KeyState) -> Option<ChangeKind> {
+        match (self, next) {
+            (from, to) if from == to => None,
+            (_, KeyState::Present(..)) => Some(ChangeKind::Set),
+            (KeyState::Present(..), KeyState::Expired) => Some(ChangeKind::Expire),
+            (KeyState::Present(..), KeyState::Missing) => Some(ChangeKind::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Watches a key by calling `state` every `interval` on a background task,
+/// for backends that can't notify their changes. The task stops when the
+/// watch is dropped, or after `state` fails.
+///
+/// Changes undone between two polls (e.g., a `set` shortly followed by a
+/// `delete`) go unnoticed, and so do writes that neither change the length of
+/// the value nor the time it was written at (as far as `state` can tell it).
+pub fn poll(
+    interval: Duration,
+    mut state: impl FnMut() -> Result<KeyState> + Send + 'static,
+) -> Result<Box<dyn KeyvalueWatchImplementor + Send + Sync>> {
+    // the first state is read right away, so that changes made after `watch`
+    // returned are seen
+    let mut last = state()?;
+    let (sender, watch) = ChannelWatch::new();
+    tokio::spawn(async move {
+        // `interval` panics on a zero period
+        let mut ticks = tokio::time::interval(interval.max(Duration::from_millis(1)));
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // the first tick completes right away
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            if sender.is_closed() {
+                return;
+            }
+            match state() {
+                Ok(current) => {
+                    if let Some(kind) = last.change_to(&current) {
+                        let _ = sender.send(Ok(kind));
+                    }
+                    last = current;
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            }
+        }
+    });
+    Ok(Box::new(watch))
+}
+
+/// This is the underlying struct behind the `keyvalue-watch` resource.
+#[derive(Debug)]
+pub struct KeyvalueWatchInner {
+    key: String,
+    implementor: Mutex<Box<dyn KeyvalueWatchImplementor + Send + Sync>>,
+}
+
+impl KeyvalueWatchInner {
+    pub fn new(key: &str, implementor: Box<dyn KeyvalueWatchImplementor + Send + Sync>) -> Self {
+        Self {
+            key: key.to_owned(),
+            implementor: Mutex::new(implementor),
+        }
+    }
+
+    /// Waits up to `timeout` for the next change to the key, or returns `None`
+    /// if there was none by then.
+    pub async fn next(&self, timeout: Duration) -> Result<Option<KeyChange>> {
+        let mut implementor = self.implementor.lock().await;
+        match tokio::time::timeout(timeout, implementor.next_change()).await {
+            Ok(kind) => Ok(Some(KeyChange {
+                key: self.key.clone(),
+                kind: kind?,
+            })),
+            Err(_) => Ok(None),
+        }
+    }
+}
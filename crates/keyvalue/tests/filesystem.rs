@@ -0,0 +1,75 @@
+//! Checks that the filesystem implementor's writes are atomic, so readers
+//! never see a partial value.
+//!
+//! Run with `cargo test -p slight-keyvalue --test filesystem`.
+use std::{collections::HashMap, sync::Arc};
+
+use slight_common::BasicState;
+use slight_file::{resource::KeyvalueResource, Resource};
+use slight_keyvalue::implementors::{filesystem::FilesystemImplementor, KeyvalueImplementor};
+
+const WRITERS: u8 = 4;
+const WRITES: usize = 200;
+const VALUE_LEN: usize = 64 * 1024;
+
+fn filesystem_state(name: &str) -> BasicState {
+    BasicState::new(
+        None,
+        Resource::Keyvalue(KeyvalueResource::Filesystem),
+        name.to_string(),
+        Some(HashMap::new()),
+        "./slightfile.toml",
+    )
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writes_are_never_seen_partially() {
+    let name = "slight-keyvalue-test-atomic-writes";
+    let implementor = Arc::new(
+        FilesystemImplementor::new(&filesystem_state(name), name)
+            .await
+            .unwrap(),
+    );
+    implementor.set("key", &[0; VALUE_LEN]).await.unwrap();
+
+    let writers: Vec<_> = (1..=WRITERS)
+        .map(|writer| {
+            let implementor = implementor.clone();
+            tokio::spawn(async move {
+                // each writer fills the whole value with its own byte
+                let value = vec![writer; VALUE_LEN];
+                for _ in 0..WRITES {
+                    implementor.set("key", &value).await.unwrap();
+                }
+            })
+        })
+        .collect();
+
+    let reader = {
+        let implementor = implementor.clone();
+        tokio::spawn(async move {
+            let mut reads = 0;
+            while reads < WRITES {
+                let value = implementor.get("key").await.unwrap();
+                assert_eq!(value.len(), VALUE_LEN, "read a truncated value");
+                assert!(
+                    value.iter().all(|b| *b == value[0]),
+                    "read a value mixing two writes"
+                );
+                reads += 1;
+            }
+        })
+    };
+
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    reader.await.unwrap();
+
+    // no temporary file is left behind to show up as a key
+    assert_eq!(implementor.keys().await.unwrap(), vec!["key".to_string()]);
+    implementor.delete("key").await.unwrap();
+    // deleting a missing key succeeds
+    implementor.delete("key").await.unwrap();
+    std::fs::remove_dir_all(&implementor.base).ok();
+}
@@ -0,0 +1,61 @@
+//! Checks that `Keyvalue::validate_all` reports every keyvalue capability of a
+//! slightfile, passing or failing.
+//!
+//! Run with `cargo test -p slight-keyvalue --test validate`.
+use std::collections::HashMap;
+
+use slight_common::BasicState;
+use slight_file::{
+    capability_store::CapabilityStore, resource::KeyvalueResource, Resource, ResourceName,
+};
+use slight_keyvalue::Keyvalue;
+
+fn filesystem_state(name: &str, configs: &[(&str, &str)]) -> BasicState {
+    BasicState::new(
+        None,
+        Resource::Keyvalue(KeyvalueResource::Filesystem),
+        name.to_string(),
+        Some(
+            configs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+        ),
+        "./slightfile.toml",
+    )
+}
+
+#[tokio::test]
+async fn validate_all_reports_each_capability() {
+    let mut capability_store = CapabilityStore::new();
+    for (name, configs) in [
+        ("slight-keyvalue-test-validate-pass", vec![]),
+        (
+            "slight-keyvalue-test-validate-fail",
+            vec![("SOFT_DELETE", "maybe")],
+        ),
+    ] {
+        capability_store.insert(
+            ResourceName::Specific(name.to_string()),
+            "keyvalue",
+            filesystem_state(name, &configs),
+        );
+    }
+    let keyvalue = Keyvalue::new(
+        Resource::Keyvalue(KeyvalueResource::Filesystem),
+        capability_store,
+    );
+
+    let outcomes = keyvalue.validate_all().await;
+    let outcomes: Vec<(&str, bool)> = outcomes
+        .iter()
+        .map(|(name, outcome)| (name.as_str(), outcome.is_ok()))
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            ("slight-keyvalue-test-validate-fail", false),
+            ("slight-keyvalue-test-validate-pass", true),
+        ]
+    );
+}
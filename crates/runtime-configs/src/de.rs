@@ -0,0 +1,194 @@
+//! Deserialization of a capability's configs into a struct.
+//!
+//! Configs are strings in the slightfile, so `ConfigsDeserializer` parses each
+//! value into the type of its field (e.g., booleans, numbers, or unit enum
+//! variants named by the value), and names the offending config when it can't.
+
+use std::fmt::{self, Display};
+
+use serde::de::{
+    self, value::StrDeserializer, DeserializeOwned, DeserializeSeed, Deserializer,
+    IntoDeserializer, MapAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Lists the fields of the struct `T` (i.e., the names of the configs it reads,
+/// as renamed by serde).
+pub fn struct_fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    let mut fields = None;
+    let _ = T::deserialize(FieldsDeserializer(&mut fields));
+    fields.unwrap_or_default()
+}
+
+/// Records the fields serde asks for, and fails without deserializing anything.
+struct FieldsDeserializer<'a>(&'a mut Option<&'static [&'static str]>);
+
+impl<'de, 'a> Deserializer<'de> for FieldsDeserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom(
+            "configs must be deserialized into a struct",
+        ))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        *self.0 = Some(fields);
+        self.deserialize_any(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Deserializes the `(name, value)` pairs of a capability's configs as a map.
+pub struct ConfigsDeserializer {
+    configs: std::vec::IntoIter<(&'static str, String)>,
+    value: Option<(&'static str, String)>,
+}
+
+impl ConfigsDeserializer {
+    pub fn new(configs: Vec<(&'static str, String)>) -> Self {
+        Self {
+            configs: configs.into_iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> Deserializer<'de> for ConfigsDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for ConfigsDeserializer {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.configs.next() {
+            Some((name, value)) => {
+                self.value = Some((name, value));
+                let name: StrDeserializer<Error> = name.into_deserializer();
+                seed.deserialize(name).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (name, value) = self
+            .value
+            .take()
+            .expect("next_value_seed called before next_key_seed");
+        seed.deserialize(ValueDeserializer { name, value })
+    }
+}
+
+/// Deserializes a single config value, parsing it as needed.
+struct ValueDeserializer {
+    name: &'static str,
+    value: String,
+}
+
+impl ValueDeserializer {
+    fn parse<T: std::str::FromStr>(&self, expected: &str) -> Result<T, Error> {
+        self.value.trim().parse().map_err(|_| {
+            de::Error::custom(format!(
+                "invalid value for config '{}': expected {expected}, got '{}'",
+                self.name, self.value
+            ))
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident, $expected:literal;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse($expected)?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.value)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool, "'true' or 'false'";
+        deserialize_i8 => visit_i8, "an integer";
+        deserialize_i16 => visit_i16, "an integer";
+        deserialize_i32 => visit_i32, "an integer";
+        deserialize_i64 => visit_i64, "an integer";
+        deserialize_u8 => visit_u8, "a non-negative integer";
+        deserialize_u16 => visit_u16, "a non-negative integer";
+        deserialize_u32 => visit_u32, "a non-negative integer";
+        deserialize_u64 => visit_u64, "a non-negative integer";
+        deserialize_f32 => visit_f32, "a number";
+        deserialize_f64 => visit_f64, "a number";
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let name = self.name;
+        visitor
+            .visit_enum(self.value.into_deserializer())
+            .map_err(|e: Error| {
+                de::Error::custom(format!("invalid value for config '{name}': {e}"))
+            })
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct newtype_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
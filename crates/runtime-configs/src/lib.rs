@@ -1,3 +1,4 @@
+mod de;
 pub mod implementors;
 
 use std::path::Path;
@@ -5,6 +6,7 @@ use std::path::Path;
 use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
 use regex::Regex;
+use serde::de::DeserializeOwned;
 
 use implementors::{azapp::AzApp, envvars::EnvVars, usersecrets::UserSecrets};
 use slight_common::{impl_resource, BasicState};
@@ -264,6 +266,42 @@ pub async fn get_from_state(config_name: &str, state: &BasicState) -> Result<Str
     }
 }
 
+/// Like `get_from_state`, but for configs a capability is allowed to omit.
+///
+/// Returns `Ok(None)` when the capability's `[capability.configs]` section doesn't
+/// declare `config_name` (or, for secret stores, when the secret can't be found)
+/// rather than failing.
+pub async fn maybe_get_from_state(config_name: &str, state: &BasicState) -> Result<Option<String>> {
+    if state.secret_store.is_some() {
+        return Ok(get_from_state(config_name, state).await.ok());
+    }
+
+    match &state.configs_map {
+        Some(configs) if configs.contains_key(config_name) => {
+            get_from_state(config_name, state).await.map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Deserializes a capability's configs into `T`, a struct with a field per
+/// config (e.g., with `#[serde(rename_all = "SCREAMING_SNAKE_CASE")]`).
+///
+/// Each field is resolved like `maybe_get_from_state`, so configs absent from the
+/// slightfile leave `Option` (or `#[serde(default)]`) fields unset, and required
+/// ones fail with the name of the missing config. Values are parsed into the
+/// type of their field (e.g., `bool`, integers, or unit enum variants).
+pub async fn configs_from_state<T: DeserializeOwned>(state: &BasicState) -> Result<T> {
+    let mut configs = vec![];
+    for name in de::struct_fields::<T>() {
+        if let Some(value) = maybe_get_from_state(name, state).await? {
+            configs.push((*name, value));
+        }
+    }
+    T::deserialize(de::ConfigsDeserializer::new(configs))
+        .with_context(|| format!("invalid configs for capability '{}'", state.name))
+}
+
 fn maybe_get_config_store_and_value(c: &str) -> Result<(String, String)> {
     let mut regex_match = Regex::new(r"^\$\{(.+)\}$")?;
     if let Some(prelim_cap) = regex_match.captures(c) {
@@ -283,7 +321,9 @@ mod unittests {
     use anyhow::Result;
     use slight_file::SlightFile;
 
-    use crate::maybe_get_config_store_and_value;
+    use serde::Deserialize;
+
+    use crate::{de, maybe_get_config_store_and_value};
 
     #[test]
     fn parse_this_dot_that() -> Result<()> {
@@ -359,4 +399,45 @@ mod unittests {
 
         Ok(())
     }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    struct TestConfig {
+        address: String,
+        db: Option<u32>,
+        #[serde(default)]
+        wal: bool,
+    }
+
+    #[test]
+    fn deserialize_configs() -> Result<()> {
+        assert_eq!(de::struct_fields::<TestConfig>(), ["ADDRESS", "DB", "WAL"]);
+        let configs = vec![
+            ("ADDRESS", "localhost".to_string()),
+            ("WAL", "true".to_string()),
+        ];
+        assert_eq!(
+            TestConfig::deserialize(de::ConfigsDeserializer::new(configs))?,
+            TestConfig {
+                address: "localhost".to_string(),
+                db: None,
+                wal: true,
+            }
+        );
+
+        let missing = TestConfig::deserialize(de::ConfigsDeserializer::new(vec![]));
+        assert_eq!(missing.unwrap_err().to_string(), "missing field `ADDRESS`");
+
+        let configs = vec![
+            ("ADDRESS", "localhost".to_string()),
+            ("DB", "-1".to_string()),
+        ];
+        let invalid = TestConfig::deserialize(de::ConfigsDeserializer::new(configs));
+        assert_eq!(
+            invalid.unwrap_err().to_string(),
+            "invalid value for config 'DB': expected a non-negative integer, got '-1'"
+        );
+
+        Ok(())
+    }
 }
@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
 use crate::ResourceName;
 
@@ -131,6 +134,73 @@ impl<T> std::iter::IntoIterator for CapabilityStore<T> {
     }
 }
 
+/// A `CapabilityStore` that can be replaced while it's in use (e.g., when slight
+/// reloads the slightfile), shared by every clone.
+///
+/// `load` returns a snapshot of the store, which a `reload` doesn't change: the
+/// swap is atomic, so readers see either the old store or the new one in full,
+/// never a mix of both. Whatever was resolved from an older snapshot keeps
+/// working, and only later `load`s see the new store.
+///
+/// Usage:
+///
+/// ```rust
+/// use slight_file::capability_store::{CapabilityStore, ReloadableCapabilityStore};
+/// use slight_file::ResourceName;
+///
+/// let store = ReloadableCapabilityStore::new(CapabilityStore::new());
+/// let before = store.load();
+///
+/// let mut reloaded = CapabilityStore::new();
+/// reloaded.insert(ResourceName::Specific("my-container".to_owned()), "keyvalue", 1);
+/// store.reload(reloaded);
+///
+/// assert_eq!(before.get("my-container", "keyvalue"), None);
+/// assert_eq!(store.load().get("my-container", "keyvalue"), Some(&1));
+/// ```
+#[derive(Debug)]
+pub struct ReloadableCapabilityStore<T> {
+    inner: Arc<RwLock<Arc<CapabilityStore<T>>>>,
+}
+
+impl<T> Clone for ReloadableCapabilityStore<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Default for ReloadableCapabilityStore<T> {
+    fn default() -> Self {
+        Self::new(CapabilityStore::new())
+    }
+}
+
+impl<T> ReloadableCapabilityStore<T> {
+    pub fn new(store: CapabilityStore<T>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Arc::new(store))),
+        }
+    }
+
+    /// Returns a snapshot of the current store.
+    pub fn load(&self) -> Arc<CapabilityStore<T>> {
+        self.inner.read().unwrap().clone()
+    }
+
+    /// Replaces the store for every clone, returning the previous one.
+    pub fn reload(&self, store: CapabilityStore<T>) -> Arc<CapabilityStore<T>> {
+        std::mem::replace(&mut *self.inner.write().unwrap(), Arc::new(store))
+    }
+}
+
+impl<T> From<CapabilityStore<T>> for ReloadableCapabilityStore<T> {
+    fn from(store: CapabilityStore<T>) -> Self {
+        Self::new(store)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::Resource;
@@ -180,4 +250,34 @@ mod tests {
         assert_eq!(store.get("my-other-container", "keyvalue"), Some(&state_3));
         Ok(())
     }
+
+    #[test]
+    fn test_reloadable_capability_store() -> Result<()> {
+        let mut store = CapabilityStore::new();
+        store.insert(
+            ResourceName::Specific("my-container".to_owned()),
+            "keyvalue",
+            "redis",
+        );
+        let reloadable = ReloadableCapabilityStore::new(store);
+        let shared = reloadable.clone();
+        let before = reloadable.load();
+
+        let mut store = CapabilityStore::new();
+        store.insert(
+            ResourceName::Specific("my-container".to_owned()),
+            "keyvalue",
+            "filesystem",
+        );
+        store.insert(ResourceName::Any, "messaging", "mosquitto");
+        let previous = reloadable.reload(store);
+
+        assert_eq!(previous.get("my-container", "keyvalue"), Some(&"redis"));
+        assert_eq!(before.get("my-container", "keyvalue"), Some(&"redis"));
+        assert_eq!(before.get("my-pubsub", "messaging"), None);
+        let after = shared.load();
+        assert_eq!(after.get("my-container", "keyvalue"), Some(&"filesystem"));
+        assert_eq!(after.get("my-pubsub", "messaging"), Some(&"mosquitto"));
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::{collections::HashMap, fmt::Display, path::Path};
 
 use serde::{Deserialize, Deserializer, Serialize};
@@ -30,6 +30,45 @@ pub struct SlightFile {
     pub capability: Option<Vec<Capability>>,
 }
 
+impl SlightFile {
+    /// Merges the capabilities of `other` into this slightfile, as when layering
+    /// an environment-specific slightfile on top of a base one.
+    ///
+    /// Capabilities are matched by capability type (e.g., `keyvalue`) and name:
+    /// a capability of `other` replaces the matching one of this slightfile (last
+    /// wins), and is added otherwise. The secret store and secret settings of this
+    /// slightfile are kept. Both slightfiles must have the same `specversion`.
+    pub fn merge(&mut self, other: SlightFile) -> Result<()> {
+        if self.specversion != other.specversion {
+            bail!(
+                "Error: cannot merge a {:?} slightfile into a {:?} slightfile",
+                other.specversion,
+                self.specversion
+            );
+        }
+        let capabilities = self.capability.get_or_insert_with(Vec::new);
+        for cap in other.capability.unwrap_or_default() {
+            let existing = capabilities.iter_mut().find(|c| {
+                c.resource().to_cap_name() == cap.resource().to_cap_name() && c.name() == cap.name()
+            });
+            match existing {
+                Some(existing) => {
+                    tracing::info!(
+                        "overriding {} capability '{}' ({} -> {})",
+                        cap.resource().to_cap_name(),
+                        cap.name(),
+                        existing.resource(),
+                        cap.resource()
+                    );
+                    *existing = cap;
+                }
+                None => capabilities.push(cap),
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Capability {
@@ -135,12 +174,14 @@ impl Config {
 #[derive(Debug, Clone, Default)]
 pub struct SlightFileBuilder {
     file_content: String,
+    override_contents: Vec<String>,
 }
 
 impl SlightFileBuilder {
     pub fn new() -> Self {
         Self {
             file_content: String::new(),
+            override_contents: Vec::new(),
         }
     }
     pub fn path(mut self, path: impl AsRef<Path>) -> Result<Self> {
@@ -148,8 +189,20 @@ impl SlightFileBuilder {
         self.file_content = toml_file_contents;
         Ok(self)
     }
+    /// Adds a slightfile to merge on top of the one at `path` (see `SlightFile::merge`).
+    ///
+    /// Overrides are merged in the order they are added, so later ones win.
+    pub fn override_path(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let toml_file_contents = std::fs::read_to_string(path.as_ref())?;
+        self.override_contents.push(toml_file_contents);
+        Ok(self)
+    }
     pub fn build(self) -> Result<SlightFileInner> {
         let mut slight_file = SlightFileInner::from_toml_string(&self.file_content)?;
+        for override_content in &self.override_contents {
+            let override_file = SlightFileInner::from_toml_string(override_content)?;
+            slight_file.as_mut().merge(override_file.as_ref().clone())?;
+        }
         slight_file.check_version()?;
         slight_file.validate_namespace()?;
         Ok(slight_file)
@@ -226,6 +279,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn merge_overrides() -> Result<()> {
+        let path = format!("{}/tests/merge", env!("CARGO_MANIFEST_DIR"));
+        let toml_file = SlightFileBuilder::new()
+            .path(format!("{path}/base.toml"))?
+            .override_path(format!("{path}/override.toml"))?
+            .build()?;
+
+        let capability = toml_file.as_ref().capability.as_ref().unwrap();
+        assert_eq!(capability.len(), 3);
+        let resources = capability
+            .iter()
+            .map(|c| (c.name().to_string(), c.resource().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            resources,
+            vec![
+                (
+                    "my-container".to_string(),
+                    "keyvalue.filesystem".to_string()
+                ),
+                ("my-container2".to_string(), "keyvalue.redis".to_string()),
+                ("my-topic".to_string(), "messaging.mosquitto".to_string()),
+            ]
+        );
+        assert!(capability[1]
+            .configs()
+            .unwrap()
+            .contains_key("REDIS_ADDRESS"));
+
+        Ok(())
+    }
+
     #[test]
     fn resource_to_str() {
         let azblob = Resource::Blob(resource::BlobResource::Azblob);
@@ -27,6 +27,20 @@ pub enum KeyvalueResource {
     Azblob,
     #[serde(rename = "keyvalue.filesystem")]
     Filesystem,
+    #[serde(rename = "keyvalue.firestore")]
+    Firestore,
+    #[serde(rename = "keyvalue.gcpstorage")]
+    GcpStorage,
+    #[serde(rename = "keyvalue.inmemory")]
+    InMemory,
+    #[serde(rename = "keyvalue.mongodb")]
+    MongoDb,
+    #[serde(rename = "keyvalue.null")]
+    Null,
+    #[serde(rename = "keyvalue.postgres")]
+    Postgres,
+    #[serde(rename = "keyvalue.proxy")]
+    Proxy,
     #[serde(rename = "keyvalue.redis")]
     Redis,
     #[serde(rename = "kv.awsdynamodb")]
@@ -47,6 +61,13 @@ impl Display for KeyvalueResource {
             KeyvalueResource::AwsDynamoDb => write!(f, "keyvalue.awsdynamodb"),
             KeyvalueResource::Azblob => write!(f, "keyvalue.azblob"),
             KeyvalueResource::Filesystem => write!(f, "keyvalue.filesystem"),
+            KeyvalueResource::Firestore => write!(f, "keyvalue.firestore"),
+            KeyvalueResource::GcpStorage => write!(f, "keyvalue.gcpstorage"),
+            KeyvalueResource::InMemory => write!(f, "keyvalue.inmemory"),
+            KeyvalueResource::MongoDb => write!(f, "keyvalue.mongodb"),
+            KeyvalueResource::Null => write!(f, "keyvalue.null"),
+            KeyvalueResource::Postgres => write!(f, "keyvalue.postgres"),
+            KeyvalueResource::Proxy => write!(f, "keyvalue.proxy"),
             KeyvalueResource::Redis => write!(f, "keyvalue.redis"),
             KeyvalueResource::V1AwsDynamoDb => write!(f, "kv.awsdynamodb"),
             KeyvalueResource::V1Azblob => write!(f, "kv.azblob"),
@@ -13,8 +13,10 @@ fn version() -> &'static str {
 pub struct Args {
     #[clap(subcommand)]
     pub command: Commands,
+    /// The slightfile to use. When given more than once, later slightfiles are
+    /// merged on top of the first one, overriding its capabilities by name
     #[clap(short, long, value_parser)]
-    pub config: Option<String>,
+    pub config: Vec<String>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -25,6 +27,17 @@ pub enum Commands {
         module: WasmModule,
         #[clap(short, long)]
         link_all_capabilities: bool,
+        /// Prefix every keyvalue key with this, e.g. to share a backend between deployments
+        #[clap(long, value_parser)]
+        keyvalue_prefix: Option<String>,
+    },
+    /// Open every keyvalue store of the slightfile and check its health, without
+    /// running a module. Exits with an error if any store fails
+    #[cfg(feature = "keyvalue")]
+    Validate {
+        /// Prefix every keyvalue key with this, as with `run`
+        #[clap(long, value_parser)]
+        keyvalue_prefix: Option<String>,
     },
     /// Add a secret to the application
     Secret {
@@ -3,3 +3,5 @@ pub mod buildjs;
 pub mod new;
 pub mod run;
 pub mod secret;
+#[cfg(feature = "keyvalue")]
+pub mod validate;
@@ -11,8 +11,9 @@ use slight_common::{BasicState, Capability, Ctx as _, WasmtimeBuildable};
 #[cfg(feature = "distributed-locking")]
 use slight_distributed_locking::DistributedLocking;
 use slight_file::{
-    capability_store::CapabilityStore, Capability as TomlCapability, Resource, SecretStoreResource,
-    SlightFile, SlightFileBuilder, SpecVersion,
+    capability_store::{CapabilityStore, ReloadableCapabilityStore},
+    Capability as TomlCapability, Resource, SecretStoreResource, SlightFile, SlightFileBuilder,
+    SlightFileInner, SpecVersion,
 };
 #[cfg(feature = "http-client")]
 use slight_http_client::HttpClient;
@@ -37,14 +38,21 @@ pub type IORedirects = slight_runtime::IORedirects;
 pub struct RunArgs {
     pub module: PathBuf,
     pub slightfile: PathBuf,
+    /// Slightfiles merged on top of `slightfile`, in order (see `SlightFile::merge`).
+    pub slightfile_overrides: Vec<PathBuf>,
     pub io_redirects: Option<IORedirects>,
     pub link_all_capabilities: bool,
+    /// Prepended to every key of every keyvalue store (see
+    /// `slight_keyvalue::Keyvalue::with_global_prefix`).
+    pub keyvalue_prefix: Option<String>,
 }
 
 pub async fn handle_run(args: RunArgs) -> Result<()> {
-    let toml = SlightFileBuilder::new()
-        .path(args.slightfile.clone())?
-        .build()?;
+    let toml = load_slightfile(&args.slightfile, &args.slightfile_overrides)?;
+    // shared by every keyvalue resource, and swapped on reloads
+    let keyvalue_store =
+        ReloadableCapabilityStore::new(capability_store_from(toml.as_ref(), &args.slightfile)?);
+    tokio::spawn(reload_on_hangup(args.clone(), keyvalue_store.clone()));
     let http_enabled = toml.has_http_cap();
     tracing::info!("Starting slight");
     let mut host_builder = Builder::from_module(&args.module)?;
@@ -60,6 +68,8 @@ pub async fn handle_run(args: RunArgs) -> Result<()> {
         &args.slightfile,
         &mut host_builder,
         &mut linked_capabilities,
+        args.keyvalue_prefix.as_deref(),
+        &keyvalue_store,
     )
     .await?;
 
@@ -79,6 +89,8 @@ pub async fn handle_run(args: RunArgs) -> Result<()> {
             &mut store,
             args.io_redirects,
             args.link_all_capabilities,
+            args.keyvalue_prefix.as_deref(),
+            &keyvalue_store,
         )
         .await?;
 
@@ -103,12 +115,65 @@ pub async fn handle_run(args: RunArgs) -> Result<()> {
     Ok(())
 }
 
+/// Reads `slightfile`, merging its overrides on top of it.
+pub(crate) fn load_slightfile(
+    slightfile: &Path,
+    slightfile_overrides: &[PathBuf],
+) -> Result<SlightFileInner> {
+    let mut toml_builder = SlightFileBuilder::new().path(slightfile)?;
+    for slightfile_override in slightfile_overrides {
+        toml_builder = toml_builder.override_path(slightfile_override)?;
+    }
+    toml_builder.build()
+}
+
+/// Reloads the keyvalue capabilities from the slightfile whenever slight gets
+/// a SIGHUP (e.g., `kill -HUP <pid>` after changing a connection string or an
+/// implementor), without restarting.
+///
+/// The stores already open keep their implementor, and the next
+/// `keyvalue_open` of a store picks up the new config. A slightfile that fails
+/// to load is logged, and the current config kept.
+#[cfg(unix)]
+async fn reload_on_hangup(args: RunArgs, keyvalue_store: ReloadableCapabilityStore<BasicState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!("failed to install SIGHUP handler, reloads are disabled: {e}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match load_slightfile(&args.slightfile, &args.slightfile_overrides)
+            .and_then(|toml| capability_store_from(toml.as_ref(), &args.slightfile))
+        {
+            Ok(capability_store) => {
+                keyvalue_store.reload(capability_store);
+                tracing::info!("Reloaded {}", args.slightfile.display());
+            }
+            Err(e) => tracing::error!(
+                "failed to reload {}, keeping the current config: {e}",
+                args.slightfile.display()
+            ),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_args: RunArgs, _keyvalue_store: ReloadableCapabilityStore<BasicState>) {
+    log::debug!("reloading the slightfile is only supported on unix");
+}
+
 #[cfg(not(feature = "http-server"))]
 async fn update_http_states(
     _toml: &SlightFile,
     _toml_file_path: impl AsRef<Path>,
     _module: impl AsRef<Path>,
     _store: &mut Store<slight_runtime::RuntimeContext>,
+    _keyvalue_prefix: Option<&str>,
+    _keyvalue_store: &ReloadableCapabilityStore<BasicState>,
 ) -> Result<(), anyhow::Error> {
     log::debug!("http-server feature is not enabled");
     Ok(())
@@ -122,6 +187,8 @@ async fn update_http_states(
     store: &mut Store<slight_runtime::RuntimeContext>,
     maybe_stdio: Option<IORedirects>,
     link_all: bool,
+    keyvalue_prefix: Option<&str>,
+    keyvalue_store: &ReloadableCapabilityStore<BasicState>,
 ) -> Result<(), anyhow::Error> {
     let mut guest_builder = Builder::from_module(module)?;
     let mut linked_capabilities = HashSet::new();
@@ -134,6 +201,8 @@ async fn update_http_states(
         &toml_file_path,
         &mut guest_builder,
         &mut linked_capabilities,
+        keyvalue_prefix,
+        keyvalue_store,
     )
     .await?;
     if let Some(ioredirects) = maybe_stdio {
@@ -221,6 +290,8 @@ async fn build_store_instance(
     toml_file_path: impl AsRef<Path>,
     builder: &mut Builder,
     linked_capabilities: &mut HashSet<String>,
+    keyvalue_prefix: Option<&str>,
+    keyvalue_store: &ReloadableCapabilityStore<BasicState>,
 ) -> Result<()> {
     let mut capability_store = CapabilityStore::<BasicState>::new();
 
@@ -258,8 +329,11 @@ async fn build_store_instance(
                     linked_capabilities.insert("keyvalue".to_string());
                 }
 
-                let resource =
-                    slight_keyvalue::Keyvalue::new(resource_type, capability_store.clone());
+                let mut resource =
+                    slight_keyvalue::Keyvalue::new(resource_type, keyvalue_store.clone());
+                if let Some(prefix) = keyvalue_prefix {
+                    resource = resource.with_global_prefix(prefix.to_string());
+                }
                 builder.add_to_builder("keyvalue".to_string(), resource);
             }
             #[cfg(feature = "distributed-locking")]
@@ -336,6 +410,29 @@ async fn build_store_instance(
     Ok(())
 }
 
+/// The states of every capability of the slightfile (but the http server,
+/// which has none).
+pub(crate) fn capability_store_from(
+    toml: &SlightFile,
+    toml_file_path: impl AsRef<Path>,
+) -> Result<CapabilityStore<BasicState>> {
+    let mut capability_store = CapabilityStore::new();
+    for c in toml.capability.as_ref().unwrap() {
+        let resource_type = c.resource();
+        if !matches!(resource_type, Resource::HttpServer(_)) {
+            maybe_add_named_capability_to_store(
+                toml.specversion,
+                toml.secret_store.clone(),
+                &mut capability_store,
+                c.clone(),
+                &toml_file_path,
+                &resource_type,
+            )?;
+        }
+    }
+    Ok(capability_store)
+}
+
 fn maybe_add_named_capability_to_store(
     specversion: SpecVersion,
     secret_store: Option<SecretStoreResource>,
@@ -399,12 +496,14 @@ mod unittest {
         let args = RunArgs {
             module: PathBuf::from(module),
             slightfile: PathBuf::from(slightfile),
+            slightfile_overrides: vec![],
             io_redirects: Some(IORedirects {
                 stdin_path: Some(PathBuf::from(&stdin_path)),
                 stdout_path: Some(PathBuf::from(&stdout_path)),
                 stderr_path: Some(PathBuf::from(&stderr_path)),
             }),
             link_all_capabilities: false,
+            keyvalue_prefix: None,
         };
 
         handle_run(args).await?;
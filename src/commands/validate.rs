@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use slight_file::{resource::KeyvalueResource, Resource};
+use slight_keyvalue::Keyvalue;
+
+use crate::commands::run::{capability_store_from, load_slightfile};
+
+#[derive(Clone, Default)]
+pub struct ValidateArgs {
+    pub slightfile: PathBuf,
+    /// Slightfiles merged on top of `slightfile`, in order (see `SlightFile::merge`).
+    pub slightfile_overrides: Vec<PathBuf>,
+    /// Prepended to every key of every keyvalue store, as when running.
+    pub keyvalue_prefix: Option<String>,
+}
+
+/// Opens every keyvalue capability of the slightfile and checks its health
+/// (see `Keyvalue::validate_all`), without running a module, printing whether
+/// each one passed. Fails if any of them did not, e.g., to stop a deploy.
+pub async fn handle_validate(args: ValidateArgs) -> Result<()> {
+    let toml = load_slightfile(&args.slightfile, &args.slightfile_overrides)?;
+    let capability_store = capability_store_from(toml.as_ref(), &args.slightfile)?;
+    // the implementor is only used to open stores requested by name, which
+    // validating doesn't do
+    let mut keyvalue = Keyvalue::new(
+        Resource::Keyvalue(KeyvalueResource::Filesystem),
+        capability_store,
+    );
+    if let Some(prefix) = args.keyvalue_prefix {
+        keyvalue = keyvalue.with_global_prefix(prefix);
+    }
+
+    let outcomes = keyvalue.validate_all().await;
+    let mut failed = vec![];
+    for (name, outcome) in &outcomes {
+        match outcome {
+            Ok(()) => println!("{name}: pass"),
+            Err(e) => {
+                println!("{name}: fail ({e})");
+                failed.push(name.as_str());
+            }
+        }
+    }
+    if !failed.is_empty() {
+        bail!(
+            "{} of {} keyvalue capabilities failed validation: {}",
+            failed.len(),
+            outcomes.len(),
+            failed.join(", ")
+        );
+    }
+    println!("{} keyvalue capabilities passed", outcomes.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod unittest {
+    use std::path::PathBuf;
+
+    use tempfile::tempdir;
+    use tokio::fs;
+
+    use crate::commands::validate::{handle_validate, ValidateArgs};
+
+    const PASSING: &str = r#"
+[[capability]]
+resource = "keyvalue.filesystem"
+name = "slight-validate-test-pass"
+"#;
+
+    const FAILING: &str = r#"
+[[capability]]
+resource = "keyvalue.filesystem"
+name = "slight-validate-test-fail"
+    [capability.configs]
+    SOFT_DELETE = "maybe"
+"#;
+
+    async fn validate(capabilities: &[&str]) -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let slightfile = tmp_dir.path().join("slightfile.toml");
+        fs::write(
+            &slightfile,
+            format!("specversion = \"0.2\"\n{}", capabilities.concat()),
+        )
+        .await?;
+        handle_validate(ValidateArgs {
+            slightfile: PathBuf::from(&slightfile),
+            ..Default::default()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_handle_validate_passes() -> anyhow::Result<()> {
+        validate(&[PASSING]).await
+    }
+
+    #[tokio::test]
+    async fn test_handle_validate_reports_failures() -> anyhow::Result<()> {
+        let e = validate(&[PASSING, FAILING])
+            .await
+            .expect_err("a failing capability passed validation");
+        assert_eq!(
+            e.to_string(),
+            "1 of 2 keyvalue capabilities failed validation: slight-validate-test-fail"
+        );
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
 
 use anyhow::Result;
 use clap::Parser;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_subscriber::{filter::LevelFilter, prelude::*, EnvFilter, Layer};
 
+#[cfg(feature = "keyvalue")]
+use slight_lib::commands::validate::{handle_validate, ValidateArgs};
 use slight_lib::{
     cli::{Args, Commands},
     commands::{
@@ -14,29 +19,101 @@ use slight_lib::{
     },
 };
 
+/// The buckets of the duration histograms served at `SLIGHT_METRICS_ADDRESS`,
+/// in seconds.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
 /// The entry point for slight CLI
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+    let otlp = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => Some(otlp_layer(endpoint)?),
+        Err(_) => None,
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(EnvFilter::from_default_env()),
+        )
+        .with(otlp)
         .init();
+    if let Ok(address) = std::env::var("SLIGHT_METRICS_ADDRESS") {
+        install_metrics_exporter(&address)?;
+    }
     let args = Args::parse();
 
+    let res = run(&args).await;
+    // flushes the spans not exported yet
+    opentelemetry::global::shutdown_tracer_provider();
+    res
+}
+
+/// Exports the spans of slight (e.g., those of each keyvalue operation) at info
+/// level and above over OTLP/gRPC to `endpoint` (e.g., `http://localhost:4317`),
+/// as set by `OTEL_EXPORTER_OTLP_ENDPOINT`. Spans are exported in batches, from
+/// a service named as per `OTEL_SERVICE_NAME`.
+fn otlp_layer<S>(endpoint: String) -> Result<impl Layer<S>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    Ok(tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(LevelFilter::INFO))
+}
+
+/// Serves the metrics of slight (e.g., `keyvalue_operation_duration_seconds`)
+/// in the Prometheus text format over HTTP on `address` (e.g., `0.0.0.0:9184`,
+/// scraped at `/metrics`), as set by `SLIGHT_METRICS_ADDRESS`. Durations are
+/// served as histograms with the `DURATION_BUCKETS` buckets.
+fn install_metrics_exporter(address: &str) -> Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(address.parse::<SocketAddr>()?)
+        .set_buckets_for_metric(
+            Matcher::Suffix("_duration_seconds".to_string()),
+            &DURATION_BUCKETS,
+        )?
+        .install()?;
+    Ok(())
+}
+
+async fn run(args: &Args) -> Result<()> {
     match &args.command {
         Commands::Run {
             module,
             link_all_capabilities,
+            keyvalue_prefix,
         } => {
             let run_args = RunArgs {
                 module: PathBuf::from(&module.path),
-                slightfile: PathBuf::from(args.config.unwrap()),
+                slightfile: PathBuf::from(base_config(args)),
+                slightfile_overrides: args.config.iter().skip(1).map(PathBuf::from).collect(),
                 link_all_capabilities: *link_all_capabilities,
+                keyvalue_prefix: keyvalue_prefix.clone(),
                 ..Default::default()
             };
             handle_run(run_args).await
         }
-        Commands::Secret { key, value } => handle_secret(key, value, args.config.unwrap()),
+        #[cfg(feature = "keyvalue")]
+        Commands::Validate { keyvalue_prefix } => {
+            handle_validate(ValidateArgs {
+                slightfile: PathBuf::from(base_config(args)),
+                slightfile_overrides: args.config.iter().skip(1).map(PathBuf::from).collect(),
+                keyvalue_prefix: keyvalue_prefix.clone(),
+            })
+            .await
+        }
+        Commands::Secret { key, value } => handle_secret(key, value, base_config(args)),
         Commands::Add {
             interface_at_release,
         } => handle_add(interface_at_release.to_owned(), None).await,
@@ -51,3 +128,10 @@ async fn main() -> Result<()> {
         } => handle_buildjs(&engine.path, src, &output.path),
     }
 }
+
+/// The slightfile given first, which later ones are merged on top of.
+fn base_config(args: &Args) -> &str {
+    args.config
+        .first()
+        .expect("a slightfile must be given with --config")
+}
@@ -14,12 +14,25 @@ fn main() -> Result<()> {
         "Hello, world! the value is: {}",
         std::str::from_utf8(&keyvalue.get("key")?)?
     );
+    assert!(keyvalue.get_consistent("key", true)? == value);
+    assert!(keyvalue.get_consistent("key", false)? == value);
     keyvalue.delete("key")?;
-    let value = keyvalue.get("key");
-    assert!(value.is_err());
+    assert!(matches!(
+        keyvalue.get("key"),
+        Err(KeyvalueError::KeyNotFound(_))
+    ));
+
+    // test exists
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set("present", "value".as_bytes())?;
+    assert!(keyvalue.exists("present")?);
+    assert!(!keyvalue.exists("absent")?);
+    keyvalue.delete("present")?;
+    assert!(!keyvalue.exists("present")?);
 
     // test keys
     let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    assert!(keyvalue.supports(Operation::Keys));
     let value = "spiderlightning".as_bytes();
     keyvalue.set("key", value)?;
     keyvalue.set("key2", value)?;
@@ -29,6 +42,33 @@ fn main() -> Result<()> {
     keyvalue.delete("key")?;
     keyvalue.delete("key2")?;
 
+    // test listing keys by page
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    for key in ["page:1", "page:2", "page:3", "other"] {
+        keyvalue.set(key, "value".as_bytes())?;
+    }
+    let mut listed = vec![];
+    let mut cursor = None;
+    loop {
+        // pages may be short (even empty) while more keys remain
+        let (keys, next) = keyvalue.list_keys(Some("page:"), cursor.as_deref(), 2)?;
+        listed.extend(keys);
+        cursor = match next {
+            Some(next) => Some(next),
+            None => break,
+        };
+    }
+    listed.sort();
+    listed.dedup();
+    assert_eq!(listed, vec!["page:1", "page:2", "page:3"]);
+    assert!(matches!(
+        keyvalue.list_keys(None, None, 0),
+        Err(KeyvalueError::InvalidValue(_))
+    ));
+    for key in ["page:1", "page:2", "page:3", "other"] {
+        keyvalue.delete(key)?;
+    }
+
     let keyvalue1 = Keyvalue::open("slight-keyvalue-test-1")?;
     let keyvalue2 = Keyvalue::open("slight-keyvalue-test-2")?;
     keyvalue1.set("key1", "value1".as_bytes())?;
@@ -46,11 +86,518 @@ fn main() -> Result<()> {
     keyvalue1.delete("key1")?;
     keyvalue2.delete("key2")?;
 
+    // test delete prefix
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set("session:1", "value1".as_bytes())?;
+    keyvalue.set("session:2", "value2".as_bytes())?;
+    keyvalue.set("other", "value3".as_bytes())?;
+    assert_eq!(keyvalue.delete_prefix("session:")?, 2);
+    assert!(keyvalue.get("session:1").is_err());
+    assert!(keyvalue.get("other")? == "value3".as_bytes());
+    keyvalue.delete("other")?;
+
+    // test bulk reads and writes
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set_bulk(&[
+        ("bulk1", "value1".as_bytes()),
+        ("bulk2", "value2".as_bytes()),
+    ])?;
+    assert_eq!(
+        keyvalue.get_bulk(&["bulk1", "missing", "bulk2"])?,
+        vec![
+            ("bulk1".to_string(), Some("value1".as_bytes().to_vec())),
+            ("missing".to_string(), None),
+            ("bulk2".to_string(), Some("value2".as_bytes().to_vec())),
+        ]
+    );
+    keyvalue.delete_bulk(&["bulk1", "missing", "bulk2"])?;
+    assert_eq!(
+        keyvalue.get_bulk(&["bulk1", "bulk2"])?,
+        vec![("bulk1".to_string(), None), ("bulk2".to_string(), None)]
+    );
+
+    // test replacing every key
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set("config:old", "old".as_bytes())?;
+    keyvalue.set("config:kept", "old".as_bytes())?;
+    keyvalue.replace_all(&[
+        ("config:kept", "new".as_bytes()),
+        ("config:new", "new".as_bytes()),
+    ])?;
+    let mut keys = keyvalue.keys()?;
+    keys.sort();
+    assert_eq!(keys, vec!["config:kept", "config:new"]);
+    assert!(keyvalue.get("config:kept")? == "new".as_bytes());
+    keyvalue.replace_all(&[])?;
+    assert!(keyvalue.keys()?.is_empty());
+
+    // test delete prefix in the background
+    for i in 0..3 {
+        keyvalue.set(&format!("job:{i}"), "value".as_bytes())?;
+    }
+    keyvalue.set("other", "value".as_bytes())?;
+    let job = keyvalue.delete_prefix_in_background("job:")?;
+    let status = loop {
+        let status = keyvalue.delete_status(job)?;
+        if status.done {
+            break status;
+        }
+    };
+    assert_eq!(status.deleted, 3);
+    assert_eq!(status.error, None);
+    assert!(keyvalue.get("job:0").is_err());
+    assert!(keyvalue.get("other")? == "value".as_bytes());
+    assert!(matches!(
+        keyvalue.delete_status(job),
+        Err(KeyvalueError::InvalidValue(_))
+    ));
+    keyvalue.delete("other")?;
+
+    // test binary values
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    let value = (0..=255).collect::<Vec<u8>>();
+    keyvalue.set("binary", &value)?;
+    assert_eq!(keyvalue.get("binary")?, value);
+    keyvalue.delete("binary")?;
+
+    // test range reads
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set("ranged", "spiderlightning".as_bytes())?;
+    assert!(keyvalue.get_range("ranged", 0, 6)? == "spider".as_bytes());
+    assert!(keyvalue.get_range("ranged", 6, 100)? == "lightning".as_bytes());
+    assert!(keyvalue.get_range("ranged", 100, 6)?.is_empty());
+    assert!(keyvalue.get_range("ranged", 0, 0)?.is_empty());
+    assert!(keyvalue.get_range("missing", 0, 6).is_err());
+    keyvalue.delete("ranged")?;
+
+    // test streams
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    let writer = keyvalue.set_stream("streamed")?;
+    writer.write("spider".as_bytes())?;
+    writer.write("lightning".as_bytes())?;
+    writer.close()?;
+    assert!(writer.write("more".as_bytes()).is_err());
+    assert!(keyvalue.get("streamed")? == "spiderlightning".as_bytes());
+    let reader = keyvalue.get_stream("streamed")?;
+    assert_eq!(reader.available()?, 15);
+    let mut streamed = vec![];
+    while let Some(chunk) = reader.read(4)? {
+        assert!(chunk.len() <= 4);
+        streamed.extend(chunk);
+    }
+    assert!(streamed == "spiderlightning".as_bytes());
+    assert_eq!(reader.available()?, 0);
+    assert!(keyvalue.get_stream("missing").is_err());
+    keyvalue.delete("streamed")?;
+
+    // test set reporting
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    let outcome = keyvalue.set_reporting("reported", "value".as_bytes())?;
+    assert!(!outcome.existed_before);
+    assert_eq!(outcome.bytes_written, 5);
+    let outcome = keyvalue.set_reporting("reported", "value2".as_bytes())?;
+    assert!(outcome.existed_before);
+    assert_eq!(outcome.bytes_written, 6);
+    keyvalue.delete("reported")?;
+
+    // test expiry
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    if keyvalue.supports(Operation::SetWithExpiry) {
+        keyvalue.set_with_expiry("expiring", "value".as_bytes(), 1)?;
+        keyvalue.set_with_expiry("renewed", "value".as_bytes(), 1)?;
+        keyvalue.set("renewed", "value".as_bytes())?;
+        assert_eq!(keyvalue.get("expiring")?, "value".as_bytes());
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        assert!(keyvalue.get("expiring").is_err());
+        assert!(!keyvalue.keys()?.contains(&"expiring".to_string()));
+        assert_eq!(keyvalue.get("renewed")?, "value".as_bytes());
+        keyvalue.delete("renewed")?;
+    } else {
+        assert!(matches!(
+            keyvalue.set_with_expiry("expiring", "value".as_bytes(), 1),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+    assert!(matches!(
+        keyvalue.set_with_expiry("expiring", "value".as_bytes(), 0),
+        Err(KeyvalueError::InvalidValue(_) | KeyvalueError::OperationNotSupported(_))
+    ));
+
+    // test revisions
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    assert_eq!(
+        keyvalue.set_if_version("versioned", "value1".as_bytes(), 0)?,
+        1
+    );
+    assert!(matches!(
+        keyvalue.set_if_version("versioned", "value2".as_bytes(), 0),
+        Err(KeyvalueError::VersionConflict(1))
+    ));
+    assert_eq!(
+        keyvalue.set_if_version("versioned", "value2".as_bytes(), 1)?,
+        2
+    );
+    let versioned = keyvalue.get_versioned("versioned")?;
+    assert!(versioned.value == "value2".as_bytes());
+    assert_eq!(versioned.revision, 2);
+    keyvalue.delete("versioned")?;
+
+    // test compare-and-swap
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    if keyvalue.supports(Operation::CompareAndSwap) {
+        assert!(keyvalue.cas("swapped", None, "value1".as_bytes())?);
+        assert!(!keyvalue.cas("swapped", None, "value2".as_bytes())?);
+        assert!(keyvalue.cas("swapped", Some("value1".as_bytes()), "value2".as_bytes())?);
+        assert!(!keyvalue.cas("swapped", Some("value1".as_bytes()), "value3".as_bytes())?);
+        assert!(keyvalue.get("swapped")? == "value2".as_bytes());
+        keyvalue.delete("swapped")?;
+    } else {
+        assert!(matches!(
+            keyvalue.cas("swapped", None, "value1".as_bytes()),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+
+    // test increment
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    if keyvalue.supports(Operation::Increment) {
+        assert_eq!(keyvalue.increment("counter", 5)?, 5);
+        assert_eq!(keyvalue.increment("counter", -2)?, 3);
+        assert!(keyvalue.get("counter")? == "3".as_bytes());
+        keyvalue.set("counter", "value".as_bytes())?;
+        assert!(matches!(
+            keyvalue.increment("counter", 1),
+            Err(KeyvalueError::InvalidValue(_))
+        ));
+        keyvalue.delete("counter")?;
+    } else {
+        assert!(matches!(
+            keyvalue.increment("counter", 1),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+
+    // test idempotency keys
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set_idempotent("idempotent", "value1".as_bytes(), "token-1")?;
+    keyvalue.set("idempotent", "value2".as_bytes())?;
+    // a retry of the first set doesn't overwrite the second one
+    keyvalue.set_idempotent("idempotent", "value1".as_bytes(), "token-1")?;
+    assert!(keyvalue.get("idempotent")? == "value2".as_bytes());
+    assert!(matches!(
+        keyvalue.set_idempotent("other", "value".as_bytes(), "token-1"),
+        Err(KeyvalueError::InvalidValue(_))
+    ));
+    keyvalue.delete("idempotent")?;
+    assert_eq!(
+        keyvalue.set_if_version_idempotent("idempotent", "value1".as_bytes(), 0, "token-2")?,
+        1
+    );
+    assert_eq!(
+        keyvalue.set_if_version_idempotent("idempotent", "value1".as_bytes(), 0, "token-2")?,
+        1
+    );
+    keyvalue.delete_idempotent("idempotent", "token-3")?;
+    keyvalue.delete_idempotent("idempotent", "token-3")?;
+    assert!(keyvalue.get("idempotent").is_err());
+
+    // test content types
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    // the gzip encoding of "value"
+    let gzipped = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x2b, 0x4b, 0xcc, 0x29, 0x4d,
+        0x05, 0x00, 0x34, 0x58, 0x77, 0x1d, 0x05, 0x00, 0x00, 0x00,
+    ];
+    keyvalue.set_with_content_type("page", &gzipped, "text/html", Some("gzip"))?;
+    assert_eq!(keyvalue.get("page")?, gzipped);
+    let stat = keyvalue.stat("page")?;
+    assert_eq!(stat.content_type.as_deref(), Some("text/html"));
+    assert_eq!(stat.content_encoding.as_deref(), Some("gzip"));
+    keyvalue.set_with_content_type("page", "value".as_bytes(), "text/plain", None)?;
+    assert_eq!(keyvalue.stat("page")?.content_encoding, None);
+    keyvalue.delete("page")?;
+
+    // test binary keys
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    let key = [0u8, 1, b'/', 0, 0xfe, 0xff];
+    keyvalue.set_raw(&key, "value".as_bytes())?;
+    assert!(keyvalue.get_raw(&key)? == "value".as_bytes());
+    assert!(keyvalue.get_raw(&[0u8, 1, b'/', 0, 0xfe]).is_err());
+    assert!(keyvalue.get("AAEvAP7_")? == "value".as_bytes());
+    keyvalue.delete_raw(&key)?;
+    assert!(keyvalue.get_raw(&key).is_err());
+
+    // test namespaces
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set_in("users", "alice", "value1".as_bytes())?;
+    keyvalue.set_in("orders", "alice", "value2".as_bytes())?;
+    assert!(keyvalue.get_in("users", "alice")? == "value1".as_bytes());
+    assert!(keyvalue.get("users:alice")? == "value1".as_bytes());
+    assert_eq!(keyvalue.keys_in("users")?, vec!["alice".to_string()]);
+    assert!(keyvalue.get_in("bad:ns", "alice").is_err());
+    keyvalue.delete_in("users", "alice")?;
+    keyvalue.delete_in("orders", "alice")?;
+    assert!(keyvalue.keys_in("users")?.is_empty());
+
+    // test capabilities, printing the store's row of the compatibility table
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    let capabilities = keyvalue.capabilities();
+    for (op, flag) in [
+        (Operation::Get, KeyvalueCapabilities::GET),
+        (Operation::Set, KeyvalueCapabilities::SET),
+        (Operation::Keys, KeyvalueCapabilities::KEYS),
+        (Operation::Delete, KeyvalueCapabilities::DELETE),
+        (Operation::DeletePrefix, KeyvalueCapabilities::DELETE_PREFIX),
+        (
+            Operation::KeysOlderThan,
+            KeyvalueCapabilities::KEYS_OLDER_THAN,
+        ),
+        (Operation::Undelete, KeyvalueCapabilities::UNDELETE),
+        (Operation::Lock, KeyvalueCapabilities::LOCK),
+        (Operation::KeysByIndex, KeyvalueCapabilities::KEYS_BY_INDEX),
+        (Operation::Scan, KeyvalueCapabilities::SCAN),
+        (
+            Operation::SetWithExpiry,
+            KeyvalueCapabilities::SET_WITH_EXPIRY,
+        ),
+        (
+            Operation::CompareAndSwap,
+            KeyvalueCapabilities::COMPARE_AND_SWAP,
+        ),
+        (Operation::Watch, KeyvalueCapabilities::WATCH),
+        (Operation::Increment, KeyvalueCapabilities::INCREMENT),
+    ] {
+        let supported = capabilities.contains(flag);
+        assert_eq!(keyvalue.supports(op), supported);
+        println!("| {op:?} | {} |", if supported { "yes" } else { "no" });
+    }
+
+    // test keys older than
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set("fresh", "value".as_bytes())?;
+    if keyvalue.supports(Operation::KeysOlderThan) {
+        assert!(!keyvalue.keys_older_than(60)?.contains(&"fresh".to_string()));
+    } else {
+        assert!(matches!(
+            keyvalue.keys_older_than(60),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+    keyvalue.delete("fresh")?;
+
+    // test keys by index
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    if keyvalue.supports(Operation::KeysByIndex) {
+        // items only carry the index attribute when written by other clients
+        keyvalue.keys_by_index("slight-keyvalue-test")?;
+    } else {
+        assert!(matches!(
+            keyvalue.keys_by_index("slight-keyvalue-test"),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+
+    // test scans
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set("scanned1", "value1".as_bytes())?;
+    keyvalue.set("scanned2", "value2".as_bytes())?;
+    if keyvalue.supports(Operation::Scan) {
+        let scan = keyvalue.scan()?;
+        let mut pairs = vec![];
+        while let Some(batch) = scan.next(1)? {
+            assert!(batch.len() <= 1);
+            pairs.extend(batch);
+        }
+        assert!(pairs
+            .iter()
+            .any(|pair| pair.key == "scanned1" && pair.value == "value1".as_bytes()));
+        assert!(pairs
+            .iter()
+            .any(|pair| pair.key == "scanned2" && pair.value == "value2".as_bytes()));
+        assert!(keyvalue.scan()?.next(0).is_err());
+    } else {
+        assert!(matches!(
+            keyvalue.scan(),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+    keyvalue.delete("scanned1")?;
+    keyvalue.delete("scanned2")?;
+
+    // test watch
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    if keyvalue.supports(Operation::Watch) {
+        let watch = keyvalue.watch("watched")?;
+        assert!(watch.next(0)?.is_none());
+        // redis only reports changes with keyspace notifications enabled on
+        // the server, so changes may not show up
+        keyvalue.set("watched", "value".as_bytes())?;
+        if let Some(change) = watch.next(5000)? {
+            assert_eq!(change.key, "watched");
+            assert!(matches!(change.kind, ChangeKind::Set));
+            keyvalue.delete("watched")?;
+            assert!(matches!(
+                watch.next(5000)?.map(|change| change.kind),
+                Some(ChangeKind::Delete)
+            ));
+        } else {
+            keyvalue.delete("watched")?;
+        }
+    } else {
+        assert!(matches!(
+            keyvalue.watch("watched"),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+
+    // test undelete
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-2")?;
+    keyvalue.set("undeleted", "value".as_bytes())?;
+    keyvalue.delete("undeleted")?;
+    assert!(keyvalue.get("undeleted").is_err());
+    if keyvalue.supports(Operation::Undelete) {
+        keyvalue.undelete("undeleted")?;
+        assert!(keyvalue.get("undeleted")? == "value".as_bytes());
+        keyvalue.delete("undeleted")?;
+    } else {
+        assert!(matches!(
+            keyvalue.undelete("undeleted"),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+
+    // test locks
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    if keyvalue.supports(Operation::Lock) {
+        let guard = keyvalue.lock("locked", 60_000)?;
+        assert!(matches!(
+            keyvalue.lock("locked", 60_000),
+            Err(KeyvalueError::LockHeld(_))
+        ));
+        keyvalue.unlock(&guard.key, &guard.token)?;
+        let guard = keyvalue.lock("locked", 60_000)?;
+        keyvalue.unlock(&guard.key, &guard.token)?;
+    } else {
+        assert!(matches!(
+            keyvalue.lock("locked", 60_000),
+            Err(KeyvalueError::OperationNotSupported(_))
+        ));
+    }
+
+    // test connection status and reconnects
+    let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+    keyvalue.set("connected", "value".as_bytes())?;
+    assert_eq!(keyvalue.connection_status(), ConnectionStatus::Connected);
+    keyvalue.reconnect()?;
+    assert!(keyvalue.get("connected")? == "value".as_bytes());
+    assert_eq!(keyvalue.connection_status(), ConnectionStatus::Connected);
+    keyvalue.delete("connected")?;
+
+    // test trace context propagation
+    keyvalue.set_trace_context(
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        Some("vendor=value"),
+    )?;
+    keyvalue.set("traced", "value".as_bytes())?;
+    assert!(keyvalue.get("traced")? == "value".as_bytes());
+    keyvalue.delete("traced")?;
+    assert!(matches!(
+        keyvalue.set_trace_context("not-a-traceparent", None),
+        Err(KeyvalueError::InvalidValue(_))
+    ));
+
+    // test opening and dropping many stores
+    for i in 0..64 {
+        let keyvalue = Keyvalue::open("slight-keyvalue-test-4")?;
+        keyvalue.set(&format!("short-lived-{i}"), "value".as_bytes())?;
+        keyvalue.delete(&format!("short-lived-{i}"))?;
+    }
+
     // test get empty key
     let keyvalue3 = Keyvalue::open("slight-keyvalue-test-3")?;
     let value = keyvalue3.get("");
     assert!(value.is_err());
 
+    // test clearing a store
+    keyvalue3.set("cleared1", "value".as_bytes())?;
+    keyvalue3.set("cleared2", "value".as_bytes())?;
+    keyvalue3.clear()?;
+    assert!(keyvalue3.keys()?.is_empty());
+
+    // test soft-delete
+    if let Some(keyvalue) = open_configured("slight-keyvalue-test-soft-delete") {
+        assert!(keyvalue.supports(Operation::Undelete));
+        keyvalue.set("soft-deleted", "value".as_bytes())?;
+        keyvalue.delete("soft-deleted")?;
+        assert!(matches!(
+            keyvalue.get("soft-deleted"),
+            Err(KeyvalueError::KeyNotFound(_))
+        ));
+        assert!(keyvalue.keys()?.is_empty());
+        assert!(keyvalue.stat("soft-deleted")?.deleted_at.is_some());
+        keyvalue.undelete("soft-deleted")?;
+        assert!(keyvalue.get("soft-deleted")? == "value".as_bytes());
+        assert_eq!(keyvalue.stat("soft-deleted")?.deleted_at, None);
+        keyvalue.delete("soft-deleted")?;
+        keyvalue.delete("never-set")?;
+    }
+
+    // test the write-ahead log
+    if let Some(keyvalue) = open_configured("slight-keyvalue-test-wal") {
+        keyvalue.set("journaled", "value".as_bytes())?;
+        keyvalue.set_bulk(&[
+            ("journaled-bulk1", "value1".as_bytes()),
+            ("journaled-bulk2", "value2".as_bytes()),
+        ])?;
+        assert!(keyvalue.get("journaled")? == "value".as_bytes());
+        assert!(keyvalue.get("journaled-bulk2")? == "value2".as_bytes());
+        if keyvalue.supports(Operation::Increment) {
+            assert_eq!(keyvalue.increment("journaled-counter", 2)?, 2);
+            assert_eq!(keyvalue.increment("journaled-counter", 3)?, 5);
+            keyvalue.delete("journaled-counter")?;
+        }
+        keyvalue.delete_bulk(&["journaled", "journaled-bulk1", "journaled-bulk2"])?;
+        assert!(keyvalue.keys()?.is_empty());
+    }
+
+    // test integrity verification
+    if let Some(keyvalue) = open_configured("slight-keyvalue-test-integrity") {
+        keyvalue.set("checksummed", "value".as_bytes())?;
+        assert!(keyvalue.get("checksummed")? == "value".as_bytes());
+        assert!(keyvalue.get_range("checksummed", 1, 3)? == "alu".as_bytes());
+        keyvalue.set("checksummed", "new value".as_bytes())?;
+        assert!(keyvalue.get("checksummed")? == "new value".as_bytes());
+        assert!(!keyvalue.supports(Operation::Increment));
+        keyvalue.delete("checksummed")?;
+    }
+
+    // test retries (the filesystem doesn't fail transiently, so operations
+    // succeed on their first attempt)
+    if let Some(keyvalue) = open_configured("slight-keyvalue-test-retry") {
+        keyvalue.set("retried", "value".as_bytes())?;
+        assert!(keyvalue.get("retried")? == "value".as_bytes());
+        assert!(matches!(
+            keyvalue.get("missing"),
+            Err(KeyvalueError::KeyNotFound(_))
+        ));
+        keyvalue.delete("retried")?;
+    }
+
+    // test key prefixes, which guests don't see
+    if let Some(keyvalue) = open_configured("slight-keyvalue-test-key-prefix") {
+        keyvalue.set("prefixed", "value".as_bytes())?;
+        assert!(keyvalue.get("prefixed")? == "value".as_bytes());
+        assert_eq!(keyvalue.keys()?, vec!["prefixed".to_string()]);
+        keyvalue.delete("prefixed")?;
+        assert!(keyvalue.keys()?.is_empty());
+    }
+
     println!("finished running keyvalue-test");
     Ok(())
 }
+
+/// Opens a store testing an opt-in config (e.g., `SOFT_DELETE`), which only
+/// some slightfiles (e.g., the filesystem one) configure.
+fn open_configured(name: &str) -> Option<Keyvalue> {
+    Keyvalue::open(name).ok()
+}
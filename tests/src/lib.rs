@@ -147,6 +147,22 @@ mod integration_tests {
             Ok(())
         }
 
+        #[test]
+        fn inmemory_test() -> Result<()> {
+            let out_dir = PathBuf::from(format!("{}/target/wasms", env!("CARGO_MANIFEST_DIR")));
+            let out_dir = out_dir.join("wasm32-wasi/debug/keyvalue-test.wasm");
+            let file_config = &format!(
+                "{}/keyvalue-test/keyvalue_inmemory_slightfile.toml",
+                env!("CARGO_MANIFEST_DIR")
+            );
+            run(
+                &slight_path(),
+                vec!["-c", file_config, "run", out_dir.to_str().unwrap()],
+                None,
+            );
+            Ok(())
+        }
+
         #[test]
         fn azblob_test() -> Result<()> {
             let out_dir = PathBuf::from(format!("{}/target/wasms", env!("CARGO_MANIFEST_DIR")));
@@ -176,6 +192,40 @@ mod integration_tests {
             Ok(())
         }
 
+        #[test]
+        fn mongodb_test() -> Result<()> {
+            // expects a mongodb server at MONGODB_CONNECTION_URI
+            let out_dir = PathBuf::from(format!("{}/target/wasms", env!("CARGO_MANIFEST_DIR")));
+            let out_dir = out_dir.join("wasm32-wasi/debug/keyvalue-test.wasm");
+            let file_config = &format!(
+                "{}/keyvalue-test/keyvalue_mongodb_slightfile.toml",
+                env!("CARGO_MANIFEST_DIR")
+            );
+            run(
+                &slight_path(),
+                vec!["-c", file_config, "run", out_dir.to_str().unwrap()],
+                None,
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn postgres_test() -> Result<()> {
+            // expects a postgres server at POSTGRES_CONNECTION_URL
+            let out_dir = PathBuf::from(format!("{}/target/wasms", env!("CARGO_MANIFEST_DIR")));
+            let out_dir = out_dir.join("wasm32-wasi/debug/keyvalue-test.wasm");
+            let file_config = &format!(
+                "{}/keyvalue-test/keyvalue_postgres_slightfile.toml",
+                env!("CARGO_MANIFEST_DIR")
+            );
+            run(
+                &slight_path(),
+                vec!["-c", file_config, "run", out_dir.to_str().unwrap()],
+                None,
+            );
+            Ok(())
+        }
+
         #[test]
         #[cfg(unix)] // TODO: Add Windows support
         fn redis_test() -> Result<()> {
@@ -200,8 +250,10 @@ mod integration_tests {
                 }
             }
 
+            // cap the number of clients so that leaked connections (e.g., from stores
+            // the guest has already dropped) make the test fail
             let mut cmd = Command::new(binary_path)
-                .args(["--port", port.to_string().as_str()])
+                .args(["--port", port.to_string().as_str(), "--maxclients", "16"])
                 .spawn()?;
 
             // sleep 5 seconds waiting for redis server to start